@@ -0,0 +1,252 @@
+//! Conformal Anomaly Detection.
+//!
+//! A Conformal Anomaly Detector (CAD) computes, for each test object,
+//! a p-value measuring how well it conforms to the previously observed
+//! (unlabeled) objects, and flags it as anomalous if its p-value is at
+//! most the significance level `epsilon`.
+//! If the objects are i.i.d., the rate of false alarms is at most
+//! `epsilon`.
+//!
+//! # Examples
+//!
+//! ```
+//! #[macro_use(array)]
+//! extern crate ndarray;
+//! extern crate random_world;
+//!
+//! # fn main() {
+//! use random_world::anomaly::*;
+//! use random_world::ncm::*;
+//!
+//! let mut cad = CAD::new(KNN::new(1), Some(0.3));
+//! let train_inputs = array![[0., 0.],
+//!                           [1., 0.],
+//!                           [0., 1.],
+//!                           [1., 1.]];
+//! let test_inputs = array![[0.5, 0.5],
+//!                          [5., 5.]];
+//!
+//! cad.train(&train_inputs.view())
+//!    .expect("Failed to train");
+//! let anomalies = cad.predict(&test_inputs.view())
+//!                    .expect("Failed to predict");
+//! assert!(anomalies == array![false, true]);
+//! # }
+//! ```
+use ndarray::prelude::*;
+
+use cp::{ConfidencePredictor, CP};
+use error::Result;
+use ncm::NonconformityScorer;
+
+
+/// Returns the (only) label of `n` objects.
+fn no_targets(n: usize) -> Array1<usize> {
+    Array1::zeros(n)
+}
+
+/// A Conformal Anomaly Detector, for some nonconformity scorer N
+/// and matrix element type T.
+///
+/// CAD can either be transductive, where the p-value of a test object
+/// is computed with respect to all the training objects, or inductive,
+/// where the nonconformity scorer is trained on a proper training set
+/// and p-values are computed with respect to a calibration set.
+/// On-line anomaly detection is obtained by adding each object, once
+/// tested, to the training set (`update()`) or, for an inductive CAD,
+/// to the calibration set (`update_calibration()`).
+///
+/// The nonconformity scorer is used as for a Conformal Predictor
+/// with a single label.
+pub struct CAD<T: Sync, N: NonconformityScorer<T>> {
+    cp: CP<T, N>,
+}
+
+impl<T, N> CAD<T, N>
+        where T: Clone + Sync + Copy, N: NonconformityScorer<T> + Sync {
+    /// Constructs a new (transductive) Conformal Anomaly Detector
+    /// `CAD<T,N>` from a nonconformity scorer.
+    ///
+    /// # Arguments
+    ///
+    /// * `ncm` - An object implementing NonconformityScorer.
+    /// * `epsilon` - Either Some() significance level in [0,1] or None.
+    pub fn new(ncm: N, epsilon: Option<f64>) -> CAD<T, N> {
+        CAD {
+            cp: CP::new(ncm, 1, epsilon),
+        }
+    }
+
+    /// Constructs a new Inductive Conformal Anomaly Detector
+    /// `CAD<T,N>` from a nonconformity scorer.
+    ///
+    /// # Arguments
+    ///
+    /// * `ncm` - An object implementing NonconformityScorer.
+    /// * `epsilon` - Either Some() significance level in [0,1] or None.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// #[macro_use(array)]
+    /// extern crate ndarray;
+    /// extern crate random_world;
+    ///
+    /// # fn main() {
+    /// use random_world::anomaly::*;
+    /// use random_world::ncm::*;
+    ///
+    /// let mut cad = CAD::new_inductive(KNN::new(1), None);
+    /// let train_inputs = array![[0.], [1.], [2.]];
+    /// let calibration_inputs = array![[0.5], [1.5], [1.8]];
+    ///
+    /// cad.train(&train_inputs.view())
+    ///    .expect("Failed to train");
+    /// cad.calibrate(&calibration_inputs.view())
+    ///    .expect("Failed to calibrate");
+    /// let pvalues = cad.predict_confidence(&array![[1.1], [10.]].view())
+    ///                  .expect("Failed to predict");
+    /// assert!(pvalues == array![1., 0.25]);
+    ///
+    /// // On-line mode: add a tested object to the calibration set.
+    /// cad.update_calibration(&array![[1.1]].view())
+    ///    .expect("Failed to update calibration");
+    /// # }
+    /// ```
+    pub fn new_inductive(ncm: N, epsilon: Option<f64>) -> CAD<T, N> {
+        CAD {
+            cp: CP::new_inductive(ncm, 1, epsilon),
+        }
+    }
+
+    /// Sets the significance level.
+    ///
+    /// # Arguments
+    ///
+    /// * `epsilon` - Significance level in [0,1].
+    pub fn set_epsilon(&mut self, epsilon: f64) {
+        self.cp.set_epsilon(epsilon);
+    }
+
+    /// Sets the number of threads used for prediction.
+    ///
+    /// # Arguments
+    ///
+    /// * `n_threads` - Number of threads. If 1, prediction is serial;
+    ///                 if 0, the number of threads is the number of CPUs.
+    ///
+    /// # Errors
+    ///
+    /// - if the thread pool cannot be created.
+    pub fn set_threads(&mut self, n_threads: usize) -> Result<()> {
+        self.cp.set_threads(n_threads)
+    }
+
+    /// Trains a Conformal Anomaly Detector on a set of objects
+    /// (the proper training set, for an inductive CAD).
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<T>) with values of type T of training
+    ///              vectors.
+    pub fn train(&mut self, inputs: &ArrayView2<T>) -> Result<()> {
+        self.cp.train(inputs, &no_targets(inputs.rows()).view())
+    }
+
+    /// Updates a Conformal Anomaly Detector with more training objects.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<T>) with values of type T of training
+    ///              vectors.
+    pub fn update(&mut self, inputs: &ArrayView2<T>) -> Result<()> {
+        self.cp.update(inputs, &no_targets(inputs.rows()).view())
+    }
+
+    /// Calibrates an Inductive Conformal Anomaly Detector.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<T>) with values of type T of calibration
+    ///              vectors.
+    pub fn calibrate(&mut self, inputs: &ArrayView2<T>) -> Result<()> {
+        self.cp.calibrate(inputs, &no_targets(inputs.rows()).view())
+    }
+
+    /// Updates a calibrated Inductive Conformal Anomaly Detector with
+    /// more calibration objects.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<T>) with values of type T of calibration
+    ///              vectors.
+    pub fn update_calibration(&mut self, inputs: &ArrayView2<T>) -> Result<()> {
+        self.cp.update_calibration(inputs, &no_targets(inputs.rows()).view())
+    }
+
+    /// Returns anomaly flags for test vectors.
+    ///
+    /// The return value is a vector of `bool` (`Array1<bool>`), whose
+    /// `i`-th element is true if the `i`-th input object is anomalous,
+    /// i.e., if its p-value is at most `epsilon`.
+    ///
+    /// # Errors
+    ///
+    /// - if the significance level was not set.
+    pub fn predict(&mut self, inputs: &ArrayView2<T>) -> Result<Array1<bool>> {
+        // Anomalies are the objects excluded from the prediction set.
+        let preds = self.cp.predict(inputs)?;
+
+        Ok(preds.column(0).mapv(|normal| !normal))
+    }
+
+    /// Returns the p-values of test vectors.
+    ///
+    /// The return value is a vector of `f64` (`Array1<f64>`), whose
+    /// `i`-th element is the p-value of the `i`-th input object.
+    pub fn predict_confidence(&mut self, inputs: &ArrayView2<T>) -> Result<Array1<f64>> {
+        let pvalues = self.cp.predict_confidence(inputs)?;
+
+        Ok(pvalues.column(0).to_owned())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ncm::KNN;
+
+    /// Verify that CAD computes the same p-values as a CP with a
+    /// single label, both in transductive and inductive mode.
+    #[test]
+    fn single_label_cp() {
+        let train_inputs = array![[0., 0.],
+                                  [1., 0.],
+                                  [0., 1.],
+                                  [1., 1.],
+                                  [2., 2.],
+                                  [1., 2.]];
+        let test_inputs = array![[2., 1.],
+                                 [5., 5.]];
+        let targets = Array1::zeros(6);
+
+        let mut cad = CAD::new(KNN::new(2), None);
+        let mut cp = CP::new(KNN::new(2), 1, None);
+        cad.train(&train_inputs.view()).unwrap();
+        cp.train(&train_inputs.view(), &targets.view()).unwrap();
+        let pvalues = cp.predict_confidence(&test_inputs.view()).unwrap();
+        assert!(cad.predict_confidence(&test_inputs.view()).unwrap() == pvalues.column(0));
+
+        let mut cad = CAD::new_inductive(KNN::new(2), Some(0.3));
+        let mut cp = CP::new_inductive(KNN::new(2), 1, Some(0.3));
+        cad.train(&train_inputs.slice(s![..3, ..])).unwrap();
+        cad.calibrate(&train_inputs.slice(s![3.., ..])).unwrap();
+        cp.train(&train_inputs.slice(s![..3, ..]), &targets.slice(s![..3])).unwrap();
+        cp.calibrate(&train_inputs.slice(s![3.., ..]), &targets.slice(s![3..])).unwrap();
+        let pvalues = cp.predict_confidence(&test_inputs.view()).unwrap();
+        assert!(cad.predict_confidence(&test_inputs.view()).unwrap() == pvalues.column(0));
+        assert!(cad.predict(&test_inputs.view()).unwrap() ==
+                pvalues.column(0).mapv(|p| p <= 0.3));
+    }
+}
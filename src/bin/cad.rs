@@ -0,0 +1,154 @@
+extern crate ndarray;
+#[macro_use]
+extern crate serde_derive;
+extern crate docopt;
+extern crate random_world;
+
+use random_world::anomaly::*;
+use random_world::ncm::*;
+use random_world::utils::{load_inputs, store_predictions};
+use docopt::Docopt;
+use ndarray::*;
+use std::cmp::min;
+
+const USAGE: &str = "
+Detect anomalies using Conformal Anomaly Detection with k-NN
+nonconformity measure.
+
+Each row of the CSV files is an (unlabeled) object.
+If no <testing-file> is specified, on-line mode is assumed: each object
+of <training-file> is tested, and then added to the training set (or,
+with --inductive, to the calibration set).
+The output is, for each tested object, its p-value or, if --epsilon is
+specified, whether it is anomalous.
+
+Usage: cad [options] [--] <output-file> <training-file> [<testing-file>]
+       cad (--help | --version)
+
+Options:
+    -e, --epsilon=<epsilon>     Significance level. If specified, the output are
+                                anomaly flags rather than p-values.
+    -k, --knn=<kn>              Number of neighbors for k-NN [default: 5].
+    --inductive                 Inductive CAD. In batch mode, the first half
+                                of <training-file> is the proper training set,
+                                the second half the calibration set.
+    --n-train=<n>               Number of initial objects forming the proper
+                                training set in on-line mode, with --inductive
+                                [default: 100].
+    --threads=<t>               Number of threads used for prediction. If 0,
+                                the number of CPUs [default: 1].
+    -h, --help                  Show help.
+    --version                   Show the version.
+";
+
+#[derive(Deserialize)]
+struct Args {
+    flag_epsilon: Option<f64>,
+    flag_knn: usize,
+    flag_inductive: bool,
+    flag_n_train: usize,
+    flag_threads: usize,
+    arg_training_file: String,
+    arg_testing_file: Option<String>,
+    arg_output_file: String,
+}
+
+
+fn main() {
+    // Parse args from command line.
+    let args: Args = Docopt::new(USAGE)
+                            .and_then(|d| d.deserialize())
+                            .unwrap_or_else(|e| e.exit());
+
+    let ncm = KNN::new(args.flag_knn);
+    let mut cad = if args.flag_inductive {
+        CAD::new_inductive(ncm, args.flag_epsilon)
+    } else {
+        CAD::new(ncm, args.flag_epsilon)
+    };
+    cad.set_threads(args.flag_threads)
+       .expect("Failed to create a thread pool");
+
+    let train_inputs = load_inputs(&args.arg_training_file)
+                        .expect("Failed to load data");
+
+    // If testing file is specified, test its objects.
+    // Otherwise, use CAD in on-line mode.
+    if let Some(testing_file) = args.arg_testing_file {
+        println!("Predicting {}", testing_file);
+        let test_inputs = load_inputs(&testing_file)
+                            .expect("Failed to load data");
+
+        if args.flag_inductive {
+            // Train on half, calibrate on the other half.
+            let n_train = (train_inputs.rows() / 2) as isize;
+            cad.train(&train_inputs.slice(s![..n_train, ..]))
+               .expect("Failed to train the model");
+            cad.calibrate(&train_inputs.slice(s![n_train.., ..]))
+               .expect("Failed to calibrate the model");
+        } else {
+            cad.train(&train_inputs.view())
+               .expect("Failed to train the model");
+        }
+
+        store_output(&mut cad, &test_inputs.view(), args.flag_epsilon.is_some(),
+                     &args.arg_output_file, false);
+    } else {
+        println!("Using CAD in on-line mode on training data");
+
+        // Train on the first object or, for an inductive CAD, on the
+        // first n_train objects. The calibration set is initially empty.
+        let n_train = if args.flag_inductive {
+            min(args.flag_n_train, train_inputs.rows())
+        } else {
+            1
+        };
+        let n_train_ = n_train as isize;
+        cad.train(&train_inputs.slice(s![..n_train_, ..]))
+           .expect("Failed to train the model");
+        if args.flag_inductive {
+            cad.calibrate(&train_inputs.slice(s![n_train_..n_train_, ..]))
+               .expect("Failed to calibrate the model");
+        }
+
+        // Reset output file.
+        store_predictions(Array2::<f64>::zeros((0,0)).view(),
+                          &args.arg_output_file, false).expect("Failed to initialize file");
+
+        // Test the remaining objects in on-line mode, and add them
+        // to the training (or calibration) set.
+        for x in train_inputs.outer_iter().skip(n_train) {
+            let x_ = x.into_shape((1, x.len())).unwrap();
+
+            store_output(&mut cad, &x_, args.flag_epsilon.is_some(),
+                         &args.arg_output_file, true);
+
+            if args.flag_inductive {
+                cad.update_calibration(&x_)
+                   .expect("Failed to update the calibration set");
+            } else {
+                cad.update(&x_)
+                   .expect("Failed to update the model");
+            }
+        }
+    }
+}
+
+/// Tests objects, and stores their p-values or, if `flags` is true,
+/// their anomaly flags into `output_file`.
+fn store_output(cad: &mut CAD<f64, KNN<f64>>, inputs: &ArrayView2<f64>, flags: bool,
+                output_file: &str, append: bool) {
+    if flags {
+        let anomalies = cad.predict(inputs)
+                           .expect("Failed to predict");
+        let anomalies = anomalies.into_shape((inputs.rows(), 1)).unwrap();
+        store_predictions(anomalies.view(), output_file, append)
+            .expect("Failed to store the output");
+    } else {
+        let pvalues = cad.predict_confidence(inputs)
+                         .expect("Failed to predict");
+        let pvalues = pvalues.into_shape((inputs.rows(), 1)).unwrap();
+        store_predictions(pvalues.view(), output_file, append)
+            .expect("Failed to store the output");
+    }
+}
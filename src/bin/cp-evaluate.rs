@@ -0,0 +1,99 @@
+#[macro_use]
+extern crate serde_derive;
+extern crate docopt;
+extern crate ndarray;
+extern crate random_world;
+
+use random_world::evaluation::*;
+use random_world::utils::{load_data, load_pvalues, store_predictions};
+use docopt::Docopt;
+use ndarray::*;
+
+const USAGE: &str = "
+Evaluate the p-values output by cp-predict or icp-predict.
+
+The true labels are read from the first column of <testing-file>.
+When cp-predict is used in on-line mode, the first example has no
+prediction: use --skip=1 to discard its label.
+
+If --curve is specified, the criteria are computed for epsilon in
+0, 1/n, ..., 1, with n = --n-epsilons, and stored into <curve-file>
+one row per epsilon, with columns:
+    epsilon, error rate, N, OneC, empty, M, E, OE.
+
+Usage: cp-evaluate [options] [--] <pvalues-file> <testing-file>
+       cp-evaluate (--help | --version)
+
+Options:
+    -e, --epsilon=<epsilon>     Significance level [default: 0.1].
+    --skip=<s>                  Number of labels of <testing-file> to skip
+                                [default: 0].
+    --curve=<curve-file>        Store the calibration curve into <curve-file>.
+    --n-epsilons=<n>            Number of steps of the calibration curve
+                                [default: 100].
+    -h, --help                  Show help.
+    --version                   Show the version.
+";
+
+#[derive(Deserialize)]
+struct Args {
+    flag_epsilon: f64,
+    flag_skip: usize,
+    flag_curve: Option<String>,
+    flag_n_epsilons: usize,
+    arg_pvalues_file: String,
+    arg_testing_file: String,
+}
+
+
+fn main() {
+    // Parse args from command line.
+    let args: Args = Docopt::new(USAGE)
+                            .and_then(|d| d.deserialize())
+                            .unwrap_or_else(|e| e.exit());
+
+    // Load p-values and true labels.
+    let pvalues = load_pvalues(&args.arg_pvalues_file)
+                    .expect("Failed to load p-values");
+    let (_, targets) = load_data(&args.arg_testing_file)
+                            .expect("Failed to load data");
+    if args.flag_skip > targets.len() {
+        panic!("Cannot skip more labels than available");
+    }
+    let targets = targets.slice(s![args.flag_skip as isize..]);
+
+    // Criteria at significance level epsilon.
+    let sets = evaluate_sets(&pvalues.view(), &targets, args.flag_epsilon)
+                    .expect("Failed to evaluate prediction sets");
+    let criteria = evaluate_pvalues(&pvalues.view(), &targets)
+                    .expect("Failed to evaluate p-values");
+
+    println!("Examples: {}", targets.len());
+    println!("Epsilon: {}", sets.epsilon);
+    println!("Error rate: {}", sets.error_rate);
+    println!("N: {}", sets.n);
+    println!("OneC: {}", sets.one_c);
+    println!("Empty: {}", sets.empty);
+    println!("M: {}", sets.m);
+    println!("E: {}", sets.e);
+    println!("OE: {}", sets.observed_excess);
+    println!("S: {}", criteria.s);
+    println!("U: {}", criteria.u);
+    println!("F: {}", criteria.f);
+    println!("OF: {}", criteria.observed_fuzziness);
+
+    // Calibration curve.
+    if let Some(curve_file) = args.flag_curve {
+        let curve = calibration_curve(&pvalues.view(), &targets,
+                                      &epsilon_grid(args.flag_n_epsilons))
+                        .expect("Failed to compute the calibration curve");
+        let rows = curve.iter()
+                        .flat_map(|c| vec![c.epsilon, c.error_rate, c.n, c.one_c,
+                                           c.empty, c.m, c.e, c.observed_excess])
+                        .collect::<Vec<_>>();
+        let rows = Array::from_shape_vec((curve.len(), 8), rows)
+                         .expect("Unexpected error in building the curve");
+        store_predictions(rows.view(), &curve_file, false)
+            .expect("Failed to store the calibration curve");
+    }
+}
@@ -0,0 +1,159 @@
+#[macro_use]
+extern crate serde_derive;
+extern crate docopt;
+extern crate ndarray;
+extern crate random_world;
+
+use random_world::cp::*;
+use random_world::ncm::*;
+use random_world::utils::{load_data, load_inputs, load_npy, load_npy_data,
+                          store_predictions, store_forced_predictions};
+use docopt::Docopt;
+use ndarray::*;
+
+const USAGE: &str = "
+Predict using Inductive Conformal Prediction from nonconformity scores
+computed externally (e.g., by a model trained with another library).
+
+Each row of <calibration-file> has format:
+    label, score
+where score is the nonconformity score of a calibration example w.r.t.
+its label (the row may instead contain one score per label).
+Each row of <scores-file> contains the nonconformity scores of a test
+example w.r.t. each label.
+With --aps or --raps, rows contain label probabilities (e.g., softmax
+outputs of a classifier) rather than scores, that is:
+    label, p1, p2, ...
+for <calibration-file>, and p1, p2, ... for <scores-file>; nonconformity
+scores are computed by Adaptive Prediction Sets (APS) or its
+regularized version (RAPS).
+Files are CSV files or, if their name ends with .npy, NumPy arrays.
+
+Usage: cp-from-scores [options] [--] <output-file> <calibration-file> <scores-file>
+       cp-from-scores (--help | --version)
+
+Options:
+    -e, --epsilon=<epsilon>     Significance level. If specified, the output are
+                                label predictions rather than p-values.
+    --forced                    Output forced predictions, one row per test
+                                example with columns label, confidence and
+                                credibility, rather than p-values or label
+                                predictions.
+    -s, --smooth                Smooth CP.
+    --seed=<s>                  PRNG seed. Only used if either --smooth or
+                                randomized scores are set.
+    --aps                       Compute APS scores from label probabilities.
+    --raps                      Compute RAPS scores from label probabilities.
+    --k-reg=<k>                 Number of top-ranked labels not penalized by
+                                RAPS [default: 1].
+    --lambda=<l>                Penalty of RAPS for each further label
+                                [default: 0.01].
+    --randomized                Randomized APS or RAPS scores, with PRNG seed
+                                given by --seed (default: 0).
+    --threads=<t>               Number of threads used for prediction. If 0,
+                                the number of CPUs [default: 1].
+    --n-labels=<n>              Number of labels. By default, the number of
+                                columns of <scores-file>.
+    -h, --help                  Show help.
+    --version                   Show the version.
+";
+
+#[derive(Deserialize)]
+struct Args {
+    flag_epsilon: Option<f64>,
+    flag_forced: bool,
+    flag_smooth: bool,
+    flag_seed: Option<u64>,
+    flag_aps: bool,
+    flag_raps: bool,
+    flag_k_reg: usize,
+    flag_lambda: f64,
+    flag_randomized: bool,
+    flag_threads: usize,
+    flag_n_labels: Option<usize>,
+    arg_output_file: String,
+    arg_calibration_file: String,
+    arg_scores_file: String,
+}
+
+
+fn main() {
+    // Parse args from command line.
+    let args: Args = Docopt::new(USAGE)
+                            .and_then(|d| d.deserialize())
+                            .unwrap_or_else(|e| e.exit());
+
+    // Nonconformity measure.
+    let seed = if args.flag_randomized {
+        Some(args.flag_seed.unwrap_or(0))
+    } else {
+        None
+    };
+    if args.flag_aps {
+        run(args, RAPS::new_aps(seed));
+    } else if args.flag_raps {
+        let ncm = RAPS::new(args.flag_k_reg, args.flag_lambda, seed)
+                       .expect("Invalid RAPS parameters");
+        run(args, ncm);
+    } else {
+        run(args, PrecomputedScores::new());
+    }
+}
+
+/// Runs ICP with nonconformity measure `ncm` on the calibration and
+/// test files.
+fn run<N>(args: Args, ncm: N) where N: NonconformityScorer<f64> + Sync {
+    let (calibration_scores, calibration_targets) = if is_npy(&args.arg_calibration_file) {
+        load_npy_data(&args.arg_calibration_file)
+    } else {
+        load_data(&args.arg_calibration_file)
+    }.expect("Failed to load calibration scores");
+    let test_scores = if is_npy(&args.arg_scores_file) {
+        load_npy(&args.arg_scores_file)
+    } else {
+        load_inputs(&args.arg_scores_file)
+    }.expect("Failed to load test scores");
+
+    let n_labels = args.flag_n_labels.unwrap_or_else(|| test_scores.cols());
+
+    // Initialize ICP.
+    let mut cp = if args.flag_smooth {
+        let seed = args.flag_seed.map(|s| [0, s]);
+        CP::new_smooth_inductive(ncm, n_labels, args.flag_epsilon, seed)
+    } else {
+        CP::new_inductive(ncm, n_labels, args.flag_epsilon)
+    };
+    cp.set_threads(args.flag_threads)
+      .expect("Failed to create a thread pool");
+
+    // There is no proper training set.
+    cp.train(&Array2::zeros((0, test_scores.cols())).view(),
+             &Array1::zeros(0).view())
+      .expect("Failed to train the model");
+    cp.calibrate(&calibration_scores.view(), &calibration_targets.view())
+      .expect("Failed to calibrate the model");
+
+    println!("Predicting {}", args.arg_scores_file);
+    let inputs = test_scores.view();
+    if args.flag_forced {
+        let preds = cp.predict_forced(&inputs)
+                      .expect("Failed to predict");
+        store_forced_predictions(&preds, &args.arg_output_file, false)
+            .expect("Failed to store the output");
+    } else if args.flag_epsilon.is_some() {
+        let preds = cp.predict(&inputs)
+                      .expect("Failed to predict");
+        store_predictions(preds.view(), &args.arg_output_file, false)
+            .expect("Failed to store the output");
+    } else {
+        let preds = cp.predict_confidence(&inputs)
+                      .expect("Failed to predict");
+        store_predictions(preds.view(), &args.arg_output_file, false)
+            .expect("Failed to store the output");
+    }
+}
+
+/// Returns true if `fname` is a NumPy `.npy` file.
+fn is_npy(fname: &str) -> bool {
+    fname.ends_with(".npy")
+}
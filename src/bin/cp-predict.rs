@@ -6,31 +6,69 @@ extern crate serde_derive;
 extern crate docopt;
 extern crate random_world;
 extern crate itertools;
+extern crate serde;
 
 use random_world::cp::*;
 use random_world::ncm::*;
-use random_world::utils::{load_data, store_predictions};
+use random_world::utils::{load_data, store_predictions, store_forced_predictions,
+                          load_model, save_model};
 use itertools::Itertools;
 use docopt::Docopt;
 use ndarray::*;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
 
 const USAGE: &'static str = "
 Predict data using Conformal Prediction.
 
 If no <testing-file> is specified, on-line mode is assumed.
+With --load-model, a previously trained model is used to predict
+<testing-file>; specify kde if it uses a KDE nonconformity measure.
 
-Usage: cp-predict knn [--knn=<k>] [options] [--] <output-file> <training-file> [<testing-file>]
-       cp-predict kde [--kernel<kernel>] [--bandwidth=<bw>] [options] [--] <output-file> <training-file> [<testing-file>]
+Usage: cp-predict knn [--knn=<k>] [--knn-mode=<mode>] [options] [--] <output-file> <training-file> [<testing-file>]
+       cp-predict kde [--kernel=<kernel>] [--bandwidth=<bw>] [options] [--] <output-file> <training-file> [<testing-file>]
+       cp-predict [knn | kde] --load-model=<model> [options] [--] <output-file> <testing-file>
        cp-predict (--help | --version)
 
 Options:
     -e, --epsilon=<epsilon>     Significance level. If specified, the output are
                                 label predictions rather than p-values.
+    --forced                    Output forced predictions, one row per test
+                                object with columns label, confidence and
+                                credibility, rather than p-values or label
+                                predictions. Cannot be used with --aci.
     -s, --smooth                Smooth CP.
-    --seed                      PRNG seed. Only used if --smooth set.
+    --seed=<s>                  PRNG seed. Only used if --smooth set.
     -k, --knn=<kn>              Number of neighbors for k-NN [default: 5].
+    --knn-mode=<mode>           Score of k-NN: sum of the distances from the
+                                same-label neighbors (sum), its ratio to the
+                                sum of the distances from the other-label
+                                neighbors (ratio), or the ratio of sums
+                                weighted by neighbor rank (weighted-ratio)
+                                [default: sum].
+    --kernel=<kernel>           Kernel for KDE: gaussian, epanechnikov, tophat,
+                                exponential, linear or cosine
+                                [default: gaussian].
+    --bandwidth=<bw>            Bandwidth for KDE: either a positive number, or
+                                silverman or scott to estimate it from the
+                                training data [default: silverman].
+    --threads=<t>               Number of threads used for prediction. If 0,
+                                the number of CPUs [default: 1].
+    --aci=<gammas>              In on-line mode, adapt the significance level
+                                so that the error rate tracks --epsilon, with
+                                Adaptive Conformal Inference and step size
+                                <gammas>. If <gammas> is a comma separated
+                                list of step sizes, DtACI is used. The output
+                                are label predictions at the adapted
+                                significance level.
+    --aci-interval=<i>          Length of the interval over which DtACI
+                                controls the error rate [default: 100].
+    --aci-trajectory=<file>     Store the adapted significance levels into
+                                <file>.
     --n-labels=<n>              Number of labels. If specified in advance it
                                 slightly improves performances.
+    --load-model=<model>        Load a trained model from file <model>.
+    --save-model=<model>        Store the model into file <model> once done.
     -h, --help                  Show help.
     --version                   Show the version.
 ";
@@ -38,58 +76,106 @@ Options:
 #[derive(Deserialize)]
 struct Args {
     flag_epsilon: Option<f64>,
+    flag_forced: bool,
     flag_smooth: bool,
     flag_seed: Option<u64>,
     flag_knn: usize,
-    flag_kernel: Option<String>,
-    flag_bandwidth: Option<f64>,
+    flag_knn_mode: String,
+    flag_threads: usize,
+    flag_kernel: String,
+    flag_bandwidth: String,
+    flag_aci: Option<String>,
+    flag_aci_interval: usize,
+    flag_aci_trajectory: Option<String>,
     flag_n_labels: Option<usize>,
-    arg_training_file: String,
+    flag_load_model: Option<String>,
+    flag_save_model: Option<String>,
+    arg_training_file: Option<String>,
     arg_testing_file: Option<String>,
     arg_output_file: String,
-    cmd_knn: bool,
     cmd_kde: bool,
 }
 
 
 fn main() {
     // Parse args from command line.
-    let args: Args = Docopt::new(USAGE)
-                            .and_then(|d| d.deserialize())
-                            .unwrap_or_else(|e| e.exit());
+    let mut args: Args = Docopt::new(USAGE)
+                                .and_then(|d| d.deserialize())
+                                .unwrap_or_else(|e| e.exit());
+
+    // With --load-model, the only input file is the testing file, which
+    // docopt may parse as <training-file> if knn or kde is specified.
+    if args.flag_load_model.is_some() && args.arg_testing_file.is_none() {
+        args.arg_testing_file = args.arg_training_file.take();
+    }
+    // ACI outputs label predictions at the adapted significance level.
+    if args.flag_forced && args.flag_aci.is_some() {
+        panic!("--forced cannot be used with --aci");
+    }
 
     // Nonconformity measure.
-    let ncm = if args.cmd_knn {
-        KNN::new(args.flag_knn)
-    } else if args.cmd_kde {
-        unimplemented!();
+    if args.cmd_kde {
+        let kernel = args.flag_kernel.parse::<Kernel>()
+                                     .expect("Invalid kernel");
+        let bandwidth = args.flag_bandwidth.parse::<Bandwidth>()
+                                           .expect("Invalid bandwidth");
+        // The bandwidth cannot be estimated from the first example.
+        if args.arg_testing_file.is_none() {
+            if let Bandwidth::Fixed(_) = bandwidth {} else {
+                panic!("On-line mode with kde requires a numeric --bandwidth");
+            }
+        }
+        run(args, KDE::new(kernel, bandwidth));
     } else {
-        // Docopt shouldn't let this happen.
-        panic!("This shouldn't happen");
-    };
+        let k = args.flag_knn;
+        let mode = args.flag_knn_mode.parse::<KNNMode>()
+                                     .expect("Invalid k-NN mode");
+        let mut knn = KNN::new(k);
+        knn.set_mode(mode)
+           .expect("Failed to set k-NN mode");
+        run(args, knn);
+    }
+}
 
-    // Load training and test data.
-    let (train_inputs, train_targets) = load_data(&args.arg_training_file)
-                                        .expect("Failed to load data");
+/// Runs CP with nonconformity measure `ncm`, unless a model is loaded.
+fn run<N>(args: Args, ncm: N)
+        where N: NonconformityScorer<f64> + Sync + Serialize + DeserializeOwned {
+    // Initialize CP, either from a stored model or from scratch.
+    let (mut cp, training_data) = if let Some(ref model_file) = args.flag_load_model {
+        let mut cp: CP<f64, N> = load_model(model_file)
+                                 .expect("Failed to load the model");
+        if let Some(epsilon) = args.flag_epsilon {
+            cp.set_epsilon(epsilon);
+        }
+        (cp, None)
+    } else {
+        // Load training data.
+        let training_file = args.arg_training_file.as_ref()
+                                .expect("No training file specified");
+        let (train_inputs, train_targets) = load_data(training_file)
+                                            .expect("Failed to load data");
 
-    // Number of labels.
-    let n_labels = match args.flag_n_labels {
-        Some(n_labels) => n_labels,
-        None => train_targets.into_iter()
-                             .unique()
-                             .count()
-    };
+        // Number of labels.
+        let n_labels = match args.flag_n_labels {
+            Some(n_labels) => n_labels,
+            None => train_targets.into_iter()
+                                 .unique()
+                                 .count()
+        };
 
-    // Initialize CP.
-    let mut cp = if args.flag_smooth {
-        let seed = match args.flag_seed {
-            Some(s) => Some([0, s]),
-            None => None,
+        let cp = if args.flag_smooth {
+            let seed = match args.flag_seed {
+                Some(s) => Some([0, s]),
+                None => None,
+            };
+            CP::new_smooth(ncm, n_labels, args.flag_epsilon, seed)
+        } else {
+            CP::new(ncm, n_labels, args.flag_epsilon)
         };
-        CP::new_smooth(ncm, n_labels, args.flag_epsilon, seed)
-    } else {
-        CP::new(ncm, n_labels, args.flag_epsilon)
+        (cp, Some((train_inputs, train_targets)))
     };
+    cp.set_threads(args.flag_threads)
+      .expect("Failed to create a thread pool");
 
     // If testing file is specified, predict test data.
     // Otherwise, use CP in on-line mode.
@@ -97,12 +183,19 @@ fn main() {
         println!("Predicting {}", testing_file);
         let (test_inputs, _) = load_data(&testing_file)
                                     .expect("Failed to load data");
-        // Train.
-        cp.train(&train_inputs.view(), &train_targets.view())
-          .expect("Failed to train the model");
+        // Train, unless the model was loaded.
+        if let Some((train_inputs, train_targets)) = training_data {
+            cp.train(&train_inputs.view(), &train_targets.view())
+              .expect("Failed to train the model");
+        }
 
         // Predict and store results.
-        if let Some(_) = args.flag_epsilon {
+        if args.flag_forced {
+            let preds = cp.predict_forced(&test_inputs.view())
+                          .expect("Failed to predict");
+            store_forced_predictions(&preds, &args.arg_output_file, false)
+                .expect("Failed to store the output");
+        } else if let Some(_) = args.flag_epsilon {
             let preds = cp.predict(&test_inputs.view())
                           .expect("Failed to predict");
             store_predictions(preds.view(), &args.arg_output_file, false)
@@ -115,6 +208,7 @@ fn main() {
         }
     } else {
         println!("Using CP in on-line mode on training data");
+        let (train_inputs, train_targets) = training_data.expect("No training data");
 
         // Train on first data point.
         let x = train_inputs.slice(s![0..1, ..]);
@@ -126,19 +220,71 @@ fn main() {
         store_predictions(Array2::<f64>::zeros((0,0)).view(),
                           &args.arg_output_file, false).expect("Failed to initialize file");
 
-        // Update and predict the remaining points in on-line mode.
-        for (x, y) in train_inputs.outer_iter().zip(train_targets.view()).skip(1) {
-            let x_ = x.into_shape((1, x.len())).unwrap();
-            let y_ = array![*y];
-            let preds = cp.predict_confidence(&x_)
-                          .expect("Failed to predict");
+        // Update and predict the remaining points in on-line mode,
+        // adapting the significance level if requested.
+        if let Some(ref gammas) = args.flag_aci {
+            let gammas = gammas.split(',')
+                               .map(|g| g.trim()
+                                         .parse::<f64>()
+                                         .expect("Invalid step size"))
+                               .collect::<Vec<_>>();
+            let epsilon = args.flag_epsilon.expect("--aci requires --epsilon");
+            let mut aci = if gammas.len() == 1 {
+                ACI::new(cp, epsilon, gammas[0])
+            } else {
+                ACI::new_dtaci(cp, epsilon, gammas, args.flag_aci_interval)
+            };
 
-            cp.update(&x_, &y_.view())
-              .expect("Failed to update CP");
+            for (x, y) in train_inputs.outer_iter().zip(train_targets.view()).skip(1) {
+                let x_ = x.into_shape((1, x.len())).unwrap();
+                let y_ = array![*y];
+                let preds = aci.predict(&x_)
+                               .expect("Failed to predict");
 
-            // Write to file.
-            store_predictions(preds.view(), &args.arg_output_file, true)
-                .expect("Failed to store the output");
+                aci.update(&x_, &y_.view())
+                   .expect("Failed to update CP");
+
+                // Write to file.
+                store_predictions(preds.view(), &args.arg_output_file, true)
+                    .expect("Failed to store the output");
+            }
+            println!("Error rate: {}", aci.error_rate());
+
+            if let Some(ref trajectory_file) = args.flag_aci_trajectory {
+                let trajectory = aci.epsilon_trajectory().to_vec();
+                let trajectory = Array::from_shape_vec((trajectory.len(), 1), trajectory)
+                                       .expect("Unexpected error in storing the trajectory");
+                store_predictions(trajectory.view(), trajectory_file, false)
+                    .expect("Failed to store the trajectory");
+            }
+            cp = aci.into_predictor();
+        } else {
+            for (x, y) in train_inputs.outer_iter().zip(train_targets.view()).skip(1) {
+                let x_ = x.into_shape((1, x.len())).unwrap();
+                let y_ = array![*y];
+                let preds = cp.predict_confidence(&x_)
+                              .expect("Failed to predict");
+
+                cp.update(&x_, &y_.view())
+                  .expect("Failed to update CP");
+
+                // Write to file.
+                if args.flag_forced {
+                    let forced = preds.outer_iter()
+                                      .map(|p| ForcedPrediction::from_pvalues(&p))
+                                      .collect::<Vec<_>>();
+                    store_forced_predictions(&forced, &args.arg_output_file, true)
+                        .expect("Failed to store the output");
+                } else {
+                    store_predictions(preds.view(), &args.arg_output_file, true)
+                        .expect("Failed to store the output");
+                }
+            }
         }
     }
-}
\ No newline at end of file
+
+    // Store the model.
+    if let Some(model_file) = args.flag_save_model {
+        save_model(&cp, &model_file).expect("Failed to store the model");
+    }
+}
@@ -6,31 +6,70 @@ extern crate serde_derive;
 extern crate docopt;
 extern crate random_world;
 extern crate itertools;
+extern crate serde;
 
 use random_world::cp::*;
 use random_world::ncm::*;
-use random_world::utils::{load_data, store_predictions};
+use random_world::utils::{load_data, store_predictions, store_forced_predictions,
+                          load_model, save_model};
 use itertools::Itertools;
 use docopt::Docopt;
 use ndarray::*;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::cmp::min;
 
 const USAGE: &'static str = "
 Predict data using Conformal Prediction.
 
 If no <testing-file> is specified, on-line mode is assumed.
+With --load-model, a previously trained and calibrated ICP is used to
+predict <testing-file>; specify kde if it uses a KDE nonconformity measure.
 
-Usage: icp-predict knn [--knn=<k>] [options] [--] <output-file> <training-file> [<testing-file>]
-       icp-predict kde [--kernel<kernel>] [--bandwidth=<bw>] [options] [--] <output-file> <training-file> [<testing-file>]
+Usage: icp-predict knn [--knn=<k>] [--knn-mode=<mode>] [options] [--] <output-file> <training-file> [<testing-file>]
+       icp-predict kde [--kernel=<kernel>] [--bandwidth=<bw>] [options] [--] <output-file> <training-file> [<testing-file>]
+       icp-predict [knn | kde] --load-model=<model> [options] [--] <output-file> <testing-file>
        icp-predict (--help | --version)
 
 Options:
     -e, --epsilon=<epsilon>     Significance level. If specified, the output are
                                 label predictions rather than p-values.
+    --forced                    Output forced predictions, one row per test
+                                object with columns label, confidence and
+                                credibility, rather than p-values or label
+                                predictions.
     -s, --smooth                Smooth CP.
-    --seed                      PRNG seed. Only used if --smooth set.
+    --seed=<s>                  PRNG seed. Only used if --smooth set.
     -k, --knn=<kn>              Number of neighbors for k-NN [default: 5].
+    --knn-mode=<mode>           Score of k-NN: sum of the distances from the
+                                same-label neighbors (sum), its ratio to the
+                                sum of the distances from the other-label
+                                neighbors (ratio), or the ratio of sums
+                                weighted by neighbor rank (weighted-ratio)
+                                [default: sum].
+    --kernel=<kernel>           Kernel for KDE: gaussian, epanechnikov, tophat,
+                                exponential, linear or cosine
+                                [default: gaussian].
+    --bandwidth=<bw>            Bandwidth for KDE: either a positive number, or
+                                silverman or scott to estimate it from the
+                                proper training data [default: silverman].
+    --threads=<t>               Number of threads used for prediction. If 0,
+                                the number of CPUs [default: 1].
+    --folds=<f>                 Use a Cross-Conformal Predictor with <f> folds,
+                                rather than splitting the training data into
+                                proper training and calibration sets.
+                                Not supported with --smooth and --threads.
+    --n-train=<n>               Number of initial examples forming the proper
+                                training set in on-line mode [default: 100].
+    --retrain-every=<r>         In on-line mode, every <r> examples move the
+                                calibration examples into the proper training
+                                set. If not specified, the proper training set
+                                is fixed.
     --n-labels=<n>              Number of labels. If specified in advance it
                                 slightly improves performances.
+    --load-model=<model>        Load a trained ICP from file <model>.
+    --save-model=<model>        Store the ICP into file <model> once done.
+                                Not supported with --folds.
     -h, --help                  Show help.
     --version                   Show the version.
 ";
@@ -38,93 +77,250 @@ Options:
 #[derive(Deserialize)]
 struct Args {
     flag_epsilon: Option<f64>,
+    flag_forced: bool,
     flag_smooth: bool,
     flag_seed: Option<u64>,
     flag_knn: usize,
-    flag_kernel: Option<String>,
-    flag_bandwidth: Option<f64>,
+    flag_knn_mode: String,
+    flag_threads: usize,
+    flag_folds: Option<usize>,
+    flag_n_train: usize,
+    flag_retrain_every: Option<usize>,
+    flag_kernel: String,
+    flag_bandwidth: String,
     flag_n_labels: Option<usize>,
-    arg_training_file: String,
+    flag_load_model: Option<String>,
+    flag_save_model: Option<String>,
+    arg_training_file: Option<String>,
     arg_testing_file: Option<String>,
     arg_output_file: String,
-    cmd_knn: bool,
     cmd_kde: bool,
 }
 
 
 fn main() {
     // Parse args from command line.
-    let args: Args = Docopt::new(USAGE)
-                            .and_then(|d| d.deserialize())
-                            .unwrap_or_else(|e| e.exit());
+    let mut args: Args = Docopt::new(USAGE)
+                                .and_then(|d| d.deserialize())
+                                .unwrap_or_else(|e| e.exit());
+
+    // With --load-model, the only input file is the testing file, which
+    // docopt may parse as <training-file> if knn or kde is specified.
+    if args.flag_load_model.is_some() && args.arg_testing_file.is_none() {
+        args.arg_testing_file = args.arg_training_file.take();
+    }
 
     // Nonconformity measure.
-    let ncm = if args.cmd_knn {
-        KNN::new(args.flag_knn)
-    } else if args.cmd_kde {
-        unimplemented!();
+    if args.cmd_kde {
+        let kernel = args.flag_kernel.parse::<Kernel>()
+                                     .expect("Invalid kernel");
+        let bandwidth = args.flag_bandwidth.parse::<Bandwidth>()
+                                           .expect("Invalid bandwidth");
+        run(args, move || KDE::new(kernel, bandwidth));
     } else {
-        // Docopt shouldn't let this happen.
-        panic!("This shouldn't happen");
-    };
+        let k = args.flag_knn;
+        let mode = args.flag_knn_mode.parse::<KNNMode>()
+                                     .expect("Invalid k-NN mode");
+        run(args, move || {
+            let mut knn = KNN::new(k);
+            knn.set_mode(mode)
+               .expect("Failed to set k-NN mode");
+            knn
+        });
+    }
+}
 
-    // Load training and test data.
-    let (train_inputs, train_targets) = load_data(&args.arg_training_file)
-                                        .expect("Failed to load data");
+/// Runs ICP (or CCP) with nonconformity measures constructed by
+/// `new_ncm`, unless a model is loaded.
+fn run<N, F>(args: Args, new_ncm: F)
+        where N: NonconformityScorer<f64> + Sync + Serialize + DeserializeOwned,
+              F: Fn() -> N + Copy {
 
-    // Number of labels.
+    // Load training data, unless a trained model is loaded.
+    let training_data = args.arg_training_file.as_ref()
+                            .map(|f| load_data(f).expect("Failed to load data"));
+
+    // Number of labels. Not needed if the model is loaded.
     let n_labels = match args.flag_n_labels {
         Some(n_labels) => n_labels,
-        None => train_targets.into_iter()
-                             .unique()
-                             .count()
+        None => training_data.as_ref()
+                             .map_or(0, |(_, train_targets)| {
+                                 train_targets.into_iter()
+                                              .unique()
+                                              .count()
+                             })
     };
 
-    // Initialize CP.
-    let mut cp = if args.flag_smooth {
-        let seed = match args.flag_seed {
-            Some(s) => Some([0, s]),
-            None => None,
+    // Initialize ICP.
+    let (smooth, epsilon, n_threads) = (args.flag_smooth, args.flag_epsilon,
+                                        args.flag_threads);
+    let seed = match args.flag_seed {
+        Some(s) => Some([0, s]),
+        None => None,
+    };
+    let new_icp = move || {
+        let mut cp = if smooth {
+            CP::new_smooth_inductive(new_ncm(), n_labels, epsilon, seed)
+        } else {
+            CP::new_inductive(new_ncm(), n_labels, epsilon)
         };
-        // Not sure why we'd need smooth ICP, as it's mostly of theoretical
-        // interest (and implemented for transductive CP in cp-predict).
-        unimplemented!();
-    } else {
-        CP::new_inductive(ncm, n_labels, args.flag_epsilon)
+        cp.set_threads(n_threads)
+          .expect("Failed to create a thread pool");
+        cp
     };
 
+    if args.flag_folds.is_some() &&
+            (args.flag_load_model.is_some() || args.flag_save_model.is_some()) {
+        panic!("Cross-Conformal Predictors cannot be loaded or stored");
+    }
+    if args.flag_folds.is_some() && (args.flag_smooth || args.flag_threads != 1) {
+        panic!("Cross-Conformal Predictors do not support --smooth or --threads");
+    }
+
     // If testing file is specified, predict test data.
     // Otherwise, use CP in on-line mode.
     if let Some(testing_file) = args.arg_testing_file {
         println!("Predicting {}", testing_file);
         let (test_inputs, _) = load_data(&testing_file)
                                     .expect("Failed to load data");
-        // Train on half, calibrate on the other half.
-        let n_train = (train_inputs.rows() / 2) as isize;
 
-        cp.train(&train_inputs.slice(s![..n_train, ..]),
-                 &train_targets.slice(s![..n_train]))
-          .expect("Failed to train the model");
+        if let Some(n_folds) = args.flag_folds {
+            // CCP trains and calibrates on all the data.
+            let (train_inputs, train_targets) = training_data.expect("No training data");
+            let mut cp = CCP::new(new_ncm, n_folds, n_labels, args.flag_epsilon);
+            cp.train(&train_inputs.view(), &train_targets.view())
+              .expect("Failed to train the model");
+
+            predict_and_store(&mut cp, &test_inputs.view(), args.flag_epsilon,
+                              args.flag_forced, &args.arg_output_file);
+        } else {
+            let mut cp = if let Some(ref model_file) = args.flag_load_model {
+                let mut cp: CP<f64, N> = load_model(model_file)
+                                         .expect("Failed to load the model");
+                if let Some(epsilon) = args.flag_epsilon {
+                    cp.set_epsilon(epsilon);
+                }
+                cp.set_threads(args.flag_threads)
+                  .expect("Failed to create a thread pool");
+                cp
+            } else {
+                // Train on half, calibrate on the other half.
+                let (train_inputs, train_targets) = training_data.expect("No training data");
+                let n_train = (train_inputs.rows() / 2) as isize;
+                let mut cp = new_icp();
+
+                cp.train(&train_inputs.slice(s![..n_train, ..]),
+                         &train_targets.slice(s![..n_train]))
+                  .expect("Failed to train the model");
+
+                cp.calibrate(&train_inputs.slice(s![n_train.., ..]),
+                             &train_targets.slice(s![n_train..]))
+                  .expect("Failed to train the model");
+                cp
+            };
 
-        cp.calibrate(&train_inputs.slice(s![n_train.., ..]),
-                     &train_targets.slice(s![n_train..]))
+            predict_and_store(&mut cp, &test_inputs.view(), args.flag_epsilon,
+                              args.flag_forced, &args.arg_output_file);
+
+            // Store the model.
+            if let Some(model_file) = args.flag_save_model {
+                save_model(&cp, &model_file).expect("Failed to store the model");
+            }
+        }
+    } else {
+        if args.flag_folds.is_some() {
+            panic!("On-line mode is not supported for Cross-Conformal Prediction");
+        }
+        println!("Using ICP in on-line mode on training data");
+
+        let (train_inputs, train_targets) = training_data.expect("No training data");
+        let mut cp = new_icp();
+
+        // Train on the first n_train points. The calibration set is
+        // initially empty.
+        let n_train = min(args.flag_n_train, train_inputs.rows());
+        let n_train_ = n_train as isize;
+        cp.train(&train_inputs.slice(s![..n_train_, ..]),
+                 &train_targets.slice(s![..n_train_]))
           .expect("Failed to train the model");
+        cp.calibrate(&train_inputs.slice(s![n_train_..n_train_, ..]),
+                     &train_targets.slice(s![n_train_..n_train_]))
+          .expect("Failed to calibrate the model");
 
-        // Predict and store results.
-        if let Some(_) = args.flag_epsilon {
-            let preds = cp.predict(&test_inputs.view())
-                          .expect("Failed to predict");
-            store_predictions(preds.view(), &args.arg_output_file, false)
-                .expect("Failed to store the output");
-        } else {
-            let preds = cp.predict_confidence(&test_inputs.view())
+        // Reset output file.
+        store_predictions(Array2::<f64>::zeros((0,0)).view(),
+                          &args.arg_output_file, false).expect("Failed to initialize file");
+
+        // Predict the remaining points in on-line mode, and add them
+        // to the calibration set once their label is revealed.
+        let mut n_calibration = 0;
+        for (i, (x, y)) in train_inputs.outer_iter()
+                                       .zip(train_targets.view())
+                                       .enumerate()
+                                       .skip(n_train) {
+            let x_ = x.into_shape((1, x.len())).unwrap();
+            let y_ = array![*y];
+            let preds = cp.predict_confidence(&x_)
                           .expect("Failed to predict");
-            store_predictions(preds.view(), &args.arg_output_file, false)
-                .expect("Failed to store the output");
+
+            n_calibration += 1;
+            if args.flag_retrain_every == Some(n_calibration) {
+                // Move the calibration examples (including the current
+                // one) into the proper training set, and start again
+                // with an empty calibration set.
+                let start = (i + 1 - n_calibration) as isize;
+                let end = (i + 1) as isize;
+                cp.update(&train_inputs.slice(s![start..end, ..]),
+                          &train_targets.slice(s![start..end]))
+                  .expect("Failed to update the model");
+                cp.calibrate(&train_inputs.slice(s![end..end, ..]),
+                             &train_targets.slice(s![end..end]))
+                  .expect("Failed to calibrate the model");
+                n_calibration = 0;
+            } else {
+                cp.update_calibration(&x_, &y_.view())
+                  .expect("Failed to update the calibration set");
+            }
+
+            // Write to file.
+            if args.flag_forced {
+                let forced = preds.outer_iter()
+                                  .map(|p| ForcedPrediction::from_pvalues(&p))
+                                  .collect::<Vec<_>>();
+                store_forced_predictions(&forced, &args.arg_output_file, true)
+                    .expect("Failed to store the output");
+            } else {
+                store_predictions(preds.view(), &args.arg_output_file, true)
+                    .expect("Failed to store the output");
+            }
+        }
+
+        // Store the model.
+        if let Some(model_file) = args.flag_save_model {
+            save_model(&cp, &model_file).expect("Failed to store the model");
         }
+    }
+}
+
+/// Predicts test vectors, and stores forced predictions (if `forced`),
+/// label predictions (if `epsilon` is specified) or p-values into
+/// `output_file`.
+fn predict_and_store(cp: &mut dyn ConfidencePredictor<f64>, inputs: &ArrayView2<f64>,
+                     epsilon: Option<f64>, forced: bool, output_file: &str) {
+    if forced {
+        let preds = cp.predict_forced(inputs)
+                      .expect("Failed to predict");
+        store_forced_predictions(&preds, output_file, false)
+            .expect("Failed to store the output");
+    } else if let Some(_) = epsilon {
+        let preds = cp.predict(inputs)
+                      .expect("Failed to predict");
+        store_predictions(preds.view(), output_file, false)
+            .expect("Failed to store the output");
     } else {
-        // On-line version of ICP is not implemented. It's not clear to
-        // me whether it'd be useful at all.
-        unimplemented!();
+        let preds = cp.predict_confidence(inputs)
+                      .expect("Failed to predict");
+        store_predictions(preds.view(), output_file, false)
+            .expect("Failed to store the output");
     }
-}
\ No newline at end of file
+}
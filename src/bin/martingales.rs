@@ -8,18 +8,23 @@ extern crate random_world;
 
 use ndarray::prelude::*;
 use random_world::exchangeability::*;
-use random_world::utils::{load_pvalues, store_predictions};
+use random_world::utils::{load_pvalues, store_predictions, load_model, save_model};
 use docopt::Docopt;
 
 const USAGE: &'static str = "
 Test exchangeability using martingales.
 
+With --load-model, a stored martingale is updated from its last state.
+
 Usage: martingales plugin [--bandwidth=<bw>] [options] <output-file> <pvalues-file>
        martingales power [--epsilon=<e>] [options] <output-file> <pvalues-file>
+       martingales --load-model=<model> [options] <output-file> <pvalues-file>
        martingales (--help | --version)
 
 Options:
     --seed                      PRNG seed.
+    --load-model=<model>        Load a martingale from file <model>.
+    --save-model=<model>        Store the martingale into file <model> once done.
     -h, --help                  Show help.
     --version                   Show the version.
 ";
@@ -31,6 +36,8 @@ struct Args {
     flag_epsilon: Option<f64>,
     flag_seed: Option<u64>,
     flag_bandwidth: Option<f64>,
+    flag_load_model: Option<String>,
+    flag_save_model: Option<String>,
     arg_pvalues_file: String,
     arg_output_file: String,
 }
@@ -50,7 +57,9 @@ fn main() {
                 predictions (i.e., one p-value per example).");
     }
         
-    let mut martingale = if args.cmd_plugin {
+    let mut martingale = if let Some(ref model_file) = args.flag_load_model {
+        load_model(model_file).expect("Failed to load the martingale")
+    } else if args.cmd_plugin {
         Martingale::new_plugin(args.flag_bandwidth)
     } else if args.cmd_power {
         Martingale::new_power(args.flag_epsilon.unwrap())
@@ -68,4 +77,9 @@ fn main() {
         store_predictions(arr2(&[[m]]).view(), &args.arg_output_file, true)
             .expect("Failed to store results");
     }
-}
\ No newline at end of file
+
+    // Store the martingale.
+    if let Some(model_file) = args.flag_save_model {
+        save_model(&martingale, &model_file).expect("Failed to store the martingale");
+    }
+}
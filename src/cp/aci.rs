@@ -0,0 +1,473 @@
+//! Adaptive Conformal Inference.
+use ndarray::prelude::*;
+use std::marker::PhantomData;
+
+use cp::ConfidencePredictor;
+use error::{Result, check_lengths, check_labels};
+
+
+/// Pinball loss of an expert with significance level `theta`, when
+/// the true label has p-value `beta`, for target significance level
+/// `epsilon`.
+fn pinball_loss(beta: f64, theta: f64, epsilon: f64) -> f64 {
+    epsilon * (beta - theta) - (beta - theta).min(0.)
+}
+
+/// Adaptive Conformal Inference (ACI), for some confidence predictor P
+/// and matrix element type T.
+///
+/// ACI (Gibbs and Candès, 2021) wraps a confidence predictor used in
+/// on-line mode, and adapts the significance level so that the long-run
+/// error rate tracks the target `epsilon` even under distribution shift.
+/// After each revealed label, the effective significance level is
+/// updated as
+///
+/// $$\varepsilon_{t+1} = \varepsilon_t + \gamma (\varepsilon - err_t)$$
+///
+/// where $err_t$ is 1 if the prediction set at level $\varepsilon_t$
+/// did not contain the true label, and 0 otherwise.
+/// The prediction set at level $\varepsilon_t$ contains the labels whose
+/// p-value is larger than $\varepsilon_t$; note that $\varepsilon_t$ may
+/// fall outside [0,1] (giving, respectively, all the labels or no label).
+///
+/// With `new_dtaci()`, several experts with different step sizes are
+/// run in parallel, and the effective significance level is their
+/// average weighted by the exponential reweighting scheme of Dynamically
+/// tuned ACI (DtACI; Gibbs and Candès, 2022).
+///
+/// ACI implements `ConfidencePredictor`: `update()` reveals the labels
+/// of the inputs of the last call to `predict()` or `predict_confidence()`,
+/// and updates both the effective significance level and the underlying
+/// predictor.
+pub struct ACI<T, P: ConfidencePredictor<T>> {
+    predictor: P,
+    epsilon: f64,
+    // Step sizes and significance levels of the experts.
+    gammas: Vec<f64>,
+    expert_epsilons: Vec<f64>,
+    weights: Vec<f64>,
+    // Learning and mixing rates of DtACI.
+    eta: f64,
+    sigma: f64,
+    trajectory: Vec<f64>,
+    n_errors: usize,
+    // Inputs and p-values of the last prediction, whose labels are
+    // revealed by update().
+    last_prediction: Option<(Array2<T>, Array2<f64>)>,
+    marker: PhantomData<T>,
+}
+
+impl<T, P: ConfidencePredictor<T>> ACI<T, P> {
+    /// Constructs Adaptive Conformal Inference on top of a confidence
+    /// predictor.
+    ///
+    /// # Arguments
+    ///
+    /// * `predictor` - An object implementing ConfidencePredictor.
+    /// * `epsilon` - Target significance level in [0,1].
+    /// * `gamma` - Step size (positive).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// #[macro_use(array)]
+    /// extern crate ndarray;
+    /// extern crate random_world;
+    ///
+    /// # fn main() {
+    /// use random_world::cp::*;
+    /// use random_world::ncm::*;
+    ///
+    /// let cp = CP::new(KNN::new(1), 2, None);
+    /// let mut aci = ACI::new(cp, 0.1, 0.05);
+    ///
+    /// aci.train(&array![[0.], [1.]].view(), &array![0, 1].view())
+    ///    .expect("Failed to train");
+    /// let preds = aci.predict(&array![[0.1]].view())
+    ///                .expect("Failed to predict");
+    /// assert!(preds[[0, 0]]);
+    /// // Reveal the label, which was in the prediction set.
+    /// aci.update(&array![[0.1]].view(), &array![0].view())
+    ///    .expect("Failed to update");
+    /// assert!(aci.epsilon_trajectory() == &[0.1]);
+    /// assert!(aci.effective_epsilon() == 0.1 + 0.05 * 0.1);
+    /// # }
+    /// ```
+    pub fn new(predictor: P, epsilon: f64, gamma: f64) -> ACI<T, P> {
+        assert!(epsilon >= 0. && epsilon <= 1.);
+        assert!(gamma > 0.);
+
+        ACI {
+            predictor: predictor,
+            epsilon: epsilon,
+            gammas: vec![gamma],
+            expert_epsilons: vec![epsilon],
+            weights: vec![1.],
+            eta: 0.,
+            sigma: 0.,
+            trajectory: vec![],
+            n_errors: 0,
+            last_prediction: None,
+            marker: PhantomData,
+        }
+    }
+
+    /// Constructs Dynamically tuned Adaptive Conformal Inference
+    /// (DtACI) on top of a confidence predictor.
+    ///
+    /// One ACI expert is run for each step size in `gammas`; experts
+    /// are reweighted according to their pinball loss, with learning
+    /// rate $\eta$ and mixing rate $\sigma$ chosen as suggested by
+    /// Gibbs and Candès (2022) for a local interval of length $I$:
+    ///
+    /// $$\sigma = \frac{1}{2I}, \quad
+    ///   \eta = \sqrt{\frac{3}{I}} \sqrt{\frac{\log(K I) + 2}
+    ///          {(1-\varepsilon)^2 \varepsilon^3 + \varepsilon^2 (1-\varepsilon)^3}}$$
+    ///
+    /// where $K$ is the number of experts.
+    ///
+    /// # Arguments
+    ///
+    /// * `predictor` - An object implementing ConfidencePredictor.
+    /// * `epsilon` - Target significance level in (0,1).
+    /// * `gammas` - Step sizes (positive) of the experts.
+    /// * `interval` - Length $I$ of the interval over which the error
+    ///                rate should be controlled locally.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use random_world::cp::*;
+    /// use random_world::ncm::*;
+    ///
+    /// let cp = CP::new(KNN::new(1), 2, None);
+    /// let gammas = vec![0.001, 0.002, 0.004, 0.008, 0.016, 0.032, 0.064, 0.128];
+    /// let mut aci = ACI::new_dtaci(cp, 0.1, gammas, 100);
+    /// ```
+    pub fn new_dtaci(predictor: P, epsilon: f64, gammas: Vec<f64>, interval: usize)
+            -> ACI<T, P> {
+        assert!(epsilon > 0. && epsilon < 1.);
+        assert!(!gammas.is_empty() && gammas.iter().all(|&gamma| gamma > 0.));
+        assert!(interval > 0);
+
+        let n_experts = gammas.len();
+        let interval = interval as f64;
+        let eta = (3. / interval).sqrt() *
+                  (((n_experts as f64 * interval).ln() + 2.) /
+                   ((1. - epsilon).powi(2) * epsilon.powi(3) +
+                    epsilon.powi(2) * (1. - epsilon).powi(3))).sqrt();
+
+        ACI {
+            predictor: predictor,
+            epsilon: epsilon,
+            gammas: gammas,
+            expert_epsilons: vec![epsilon; n_experts],
+            weights: vec![1. / n_experts as f64; n_experts],
+            eta: eta,
+            sigma: 1. / (2. * interval),
+            trajectory: vec![],
+            n_errors: 0,
+            last_prediction: None,
+            marker: PhantomData,
+        }
+    }
+
+    /// Returns the current effective significance level.
+    pub fn effective_epsilon(&self) -> f64 {
+        let total = self.weights.iter().sum::<f64>();
+
+        self.weights.iter()
+                    .zip(self.expert_epsilons.iter())
+                    .map(|(w, e)| w * e)
+                    .sum::<f64>() / total
+    }
+
+    /// Returns the effective significance levels used to predict
+    /// each of the examples revealed so far.
+    pub fn epsilon_trajectory(&self) -> &[f64] {
+        &self.trajectory
+    }
+
+    /// Returns the number of examples revealed so far whose label was
+    /// not in the prediction set.
+    pub fn cumulative_errors(&self) -> usize {
+        self.n_errors
+    }
+
+    /// Returns the error rate over the examples revealed so far.
+    ///
+    /// It is NaN if no example was revealed.
+    pub fn error_rate(&self) -> f64 {
+        self.n_errors as f64 / self.trajectory.len() as f64
+    }
+
+    /// Returns a reference to the underlying confidence predictor.
+    pub fn predictor(&self) -> &P {
+        &self.predictor
+    }
+
+    /// Returns a mutable reference to the underlying confidence
+    /// predictor.
+    ///
+    /// This allows, for example, to update the calibration set of
+    /// an ICP; the effective significance level can then be updated
+    /// with `observe()`.
+    pub fn predictor_mut(&mut self) -> &mut P {
+        &mut self.predictor
+    }
+
+    /// Consumes ACI, returning the underlying confidence predictor.
+    pub fn into_predictor(self) -> P {
+        self.predictor
+    }
+
+    /// Updates the effective significance level given the p-values of
+    /// (sequentially) predicted inputs and their true labels.
+    ///
+    /// The underlying predictor is not updated.
+    ///
+    /// # Arguments
+    ///
+    /// * `pvalues` - Matrix (Array2<f64>) of p-values, as returned by
+    ///               `predict_confidence()`, one row per input.
+    /// * `targets` - Vector (Array1<usize>) of true labels of the inputs.
+    ///
+    /// # Errors
+    ///
+    /// - if the number of rows of `pvalues` is not consistent with the
+    ///   number of labels in `targets`.
+    /// - if a label is not smaller than the number of columns of `pvalues`.
+    pub fn observe(&mut self, pvalues: &ArrayView2<f64>, targets: &ArrayView1<usize>)
+            -> Result<()> {
+        check_lengths(pvalues.rows(), targets.len())?;
+        check_labels(targets, pvalues.cols())?;
+
+        let n_experts = self.gammas.len() as f64;
+
+        for (p, y) in pvalues.outer_iter().zip(targets) {
+            // P-value of the true label: the prediction set at level
+            // theta contains the true label iff theta < beta.
+            let beta = p[*y];
+            let epsilon_t = self.effective_epsilon();
+            self.trajectory.push(epsilon_t);
+            if beta <= epsilon_t {
+                self.n_errors += 1;
+            }
+
+            // Reweight experts (DtACI).
+            if self.gammas.len() > 1 {
+                for (w, theta) in self.weights.iter_mut().zip(self.expert_epsilons.iter()) {
+                    *w *= (-self.eta * pinball_loss(beta, *theta, self.epsilon)).exp();
+                }
+                let total = self.weights.iter().sum::<f64>();
+                for w in self.weights.iter_mut() {
+                    *w = (1. - self.sigma) * *w / total + self.sigma / n_experts;
+                }
+            }
+
+            // Update experts (ACI).
+            for (theta, gamma) in self.expert_epsilons.iter_mut().zip(self.gammas.iter()) {
+                let err = if beta <= *theta { 1. } else { 0. };
+                *theta += gamma * (self.epsilon - err);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<T, P> ConfidencePredictor<T> for ACI<T, P>
+        where T: Clone + PartialEq, P: ConfidencePredictor<T> {
+    /// Sets the target significance level.
+    ///
+    /// The effective significance level keeps adapting from its current
+    /// value.
+    ///
+    /// # Arguments
+    ///
+    /// * `epsilon` - Significance level in [0,1].
+    fn set_epsilon(&mut self, epsilon: f64) {
+        assert!(epsilon >= 0. && epsilon <= 1.);
+
+        self.epsilon = epsilon;
+    }
+
+    /// Trains the underlying confidence predictor.
+    fn train(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView1<usize>)
+             -> Result<()> {
+        self.last_prediction = None;
+        self.predictor.train(inputs, targets)
+    }
+
+    /// Reveals the labels of the inputs of the last prediction, and
+    /// updates the effective significance level and the underlying
+    /// confidence predictor.
+    ///
+    /// If `inputs` were not the last predicted ones, their p-values are
+    /// computed again before updating the underlying predictor.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<T>) with values of type T of training
+    ///              vectors.
+    /// * `targets` - Vector (Array1<T>) of labels corresponding to the
+    ///               training vectors.
+    fn update(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView1<usize>)
+              -> Result<()> {
+        let pvalues = match self.last_prediction.take() {
+            Some((ref last_inputs, ref pvalues)) if last_inputs.view() == *inputs => {
+                pvalues.clone()
+            },
+            _ => self.predictor.predict_confidence(inputs)?,
+        };
+        self.observe(&pvalues.view(), targets)?;
+
+        self.predictor.update(inputs, targets)
+    }
+
+    /// Calibrates the underlying confidence predictor.
+    fn calibrate(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView1<usize>)
+                 -> Result<()> {
+        self.last_prediction = None;
+        self.predictor.calibrate(inputs, targets)
+    }
+
+    /// Returns candidate labels (region prediction) for test vectors,
+    /// at the effective significance level.
+    fn predict(&mut self, inputs: &ArrayView2<T>) -> Result<Array2<bool>> {
+        let epsilon = self.effective_epsilon();
+        let pvalues = self.predict_confidence(inputs)?;
+
+        Ok(pvalues.mapv(|p| p > epsilon))
+    }
+
+    /// Returns the p-values for test vectors, as computed by the
+    /// underlying confidence predictor.
+    fn predict_confidence(&mut self, inputs: &ArrayView2<T>) -> Result<Array2<f64>> {
+        let pvalues = self.predictor.predict_confidence(inputs)?;
+        self.last_prediction = Some((inputs.to_owned(), pvalues.clone()));
+
+        Ok(pvalues)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Confidence predictor returning p-values from a fixed sequence,
+    /// one row per prediction.
+    struct Sequence {
+        pvalues: Array2<f64>,
+        t: usize,
+        epsilon: f64,
+    }
+
+    impl ConfidencePredictor<f64> for Sequence {
+        fn set_epsilon(&mut self, epsilon: f64) {
+            self.epsilon = epsilon;
+        }
+        fn train(&mut self, _inputs: &ArrayView2<f64>, _targets: &ArrayView1<usize>)
+                 -> Result<()> {
+            Ok(())
+        }
+        fn update(&mut self, _inputs: &ArrayView2<f64>, _targets: &ArrayView1<usize>)
+                  -> Result<()> {
+            Ok(())
+        }
+        fn calibrate(&mut self, _inputs: &ArrayView2<f64>, _targets: &ArrayView1<usize>)
+                     -> Result<()> {
+            Ok(())
+        }
+        fn predict(&mut self, inputs: &ArrayView2<f64>) -> Result<Array2<bool>> {
+            let pvalues = self.predict_confidence(inputs)?;
+            Ok(pvalues.mapv(|p| p > self.epsilon))
+        }
+        fn predict_confidence(&mut self, _inputs: &ArrayView2<f64>) -> Result<Array2<f64>> {
+            let pvalues = self.pvalues.slice(s![self.t as isize..self.t as isize + 1, ..])
+                                      .to_owned();
+            self.t += 1;
+            Ok(pvalues)
+        }
+    }
+
+    /// Verify the ACI update of the effective significance level.
+    #[test]
+    fn aci() {
+        let predictor = Sequence {
+            pvalues: array![[0.5, 0.05],
+                            [0.3, 0.2],
+                            [0.02, 0.9]],
+            t: 0,
+            epsilon: 0.1,
+        };
+        let mut aci = ACI::new(predictor, 0.1, 0.5);
+        let x = array![[0.]];
+
+        // Error: the true label has p-value 0.05 <= 0.1.
+        assert!(aci.predict(&x.view()).unwrap() == array![[true, false]]);
+        aci.update(&x.view(), &array![1].view()).unwrap();
+        assert_relative_eq!(aci.effective_epsilon(), 0.1 + 0.5 * (0.1 - 1.));
+        // Negative significance level: all labels are predicted.
+        assert!(aci.predict(&x.view()).unwrap() == array![[true, true]]);
+        aci.update(&x.view(), &array![1].view()).unwrap();
+        assert_relative_eq!(aci.effective_epsilon(), -0.35 + 0.5 * 0.1);
+        aci.predict(&x.view()).unwrap();
+        aci.update(&x.view(), &array![0].view()).unwrap();
+
+        assert!(aci.cumulative_errors() == 1);
+        assert_relative_eq!(aci.error_rate(), 1. / 3.);
+        assert!(aci.epsilon_trajectory().len() == 3);
+        assert_relative_eq!(aci.epsilon_trajectory()[2], -0.3);
+    }
+
+    /// Verify that update() only reuses the p-values of the last
+    /// prediction if it reveals the labels of the same inputs.
+    #[test]
+    fn update_other_inputs() {
+        let predictor = Sequence {
+            pvalues: array![[0.5, 0.5],
+                            [0.05, 0.5]],
+            t: 0,
+            epsilon: 0.1,
+        };
+        let mut aci = ACI::new(predictor, 0.1, 0.5);
+
+        aci.predict(&array![[0.]].view()).unwrap();
+        // The p-values of [[1.]] are computed again: the true label
+        // has p-value 0.05 <= 0.1.
+        aci.update(&array![[1.]].view(), &array![0].view()).unwrap();
+        assert!(aci.cumulative_errors() == 1);
+        assert!(aci.predictor().t == 2);
+    }
+
+    /// Verify that DtACI moves weight towards the experts with smaller
+    /// pinball loss, keeping the effective significance level between
+    /// those of the experts.
+    #[test]
+    fn dtaci() {
+        let predictor = Sequence {
+            pvalues: Array2::from_shape_fn((50, 2), |(i, y)| {
+                if y == 0 { 0.01 + (i % 10) as f64 / 100. } else { 0.5 }
+            }),
+            t: 0,
+            epsilon: 0.1,
+        };
+        let mut aci = ACI::new_dtaci(predictor, 0.1, vec![0.01, 0.1], 10);
+        let x = array![[0.]];
+
+        for _ in 0..50 {
+            aci.predict_confidence(&x.view()).unwrap();
+            aci.update(&x.view(), &array![0].view()).unwrap();
+
+            let total = aci.weights.iter().sum::<f64>();
+            assert_relative_eq!(total, 1., epsilon = 1e-9);
+            let min = aci.expert_epsilons.iter().cloned().fold(f64::INFINITY, f64::min);
+            let max = aci.expert_epsilons.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+            let epsilon = aci.effective_epsilon();
+            assert!(epsilon >= min - 1e-9 && epsilon <= max + 1e-9);
+        }
+        assert!(aci.epsilon_trajectory().len() == 50);
+    }
+}
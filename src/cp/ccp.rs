@@ -0,0 +1,369 @@
+//! Cross-Conformal Predictors.
+use ndarray::prelude::*;
+use std::f64;
+use std::marker::PhantomData;
+
+use cp::ConfidencePredictor;
+use error::{Error, Result, check_lengths, check_labels};
+use ncm::NonconformityScorer;
+
+
+/// Returns the (sorted) indices of the examples in each of `n_folds`
+/// stratified folds.
+fn stratified_folds(targets: &ArrayView1<usize>, n_folds: usize) -> Vec<Vec<usize>> {
+    // Stable sort: examples with the same label keep their order.
+    let mut indices = (0..targets.len()).collect::<Vec<_>>();
+    indices.sort_by_key(|&i| targets[i]);
+
+    let mut folds = vec![vec![]; n_folds];
+    for (j, i) in indices.into_iter().enumerate() {
+        folds[j % n_folds].push(i);
+    }
+    for fold in &mut folds {
+        fold.sort();
+    }
+
+    folds
+}
+
+/// A Cross-Conformal Predictor (CCP), for some nonconformity scorer N
+/// and matrix element type T.
+///
+/// A CCP splits the training set into `n_folds` folds; for each fold,
+/// a nonconformity scorer (created by calling `new_ncm()`) is trained
+/// on the remaining folds and calibrated on the fold itself.
+/// The p-value of a test example `(x, y)` merges the rank counts
+/// of all the folds (Vovk, 2015):
+///
+/// $$p = \frac{\sum_k |\{i \in S_k : \alpha_{i,k} \geq \alpha_k\}| + 1}{n + 1}$$
+///
+/// where $S_k$ is the `k`-th fold, $\alpha_{i,k}$ the score of its
+/// `i`-th example, $\alpha_k$ the score of the test example according
+/// to the `k`-th scorer, and $n$ is the number of examples the test
+/// example is compared to.
+///
+/// Folds are stratified: the training examples are sorted by label
+/// (otherwise keeping their order), and assigned to the folds in turn,
+/// so that each fold has about the same number of examples of each
+/// label.
+///
+/// Unlike an ICP, a CCP uses all the training data both for training
+/// and for calibration.
+pub struct CCP<T: Sync, N: NonconformityScorer<T>, F: Fn() -> N> {
+    new_ncm: F,
+    ncms: Vec<N>,
+    n_folds: usize,
+    epsilon: Option<f64>,
+    n_labels: usize,
+    // Full training data, needed to re-train the folds on update().
+    train_inputs: Option<Array2<T>>,
+    train_targets: Option<Array1<usize>>,
+    marker: PhantomData<T>,
+}
+
+impl<T, N, F> CCP<T, N, F>
+        where T: Clone + Sync + Copy, N: NonconformityScorer<T>, F: Fn() -> N {
+    /// Constructs a new Cross-Conformal Predictor `CCP<T,N,F>`
+    /// from a factory of nonconformity scorers.
+    ///
+    /// # Arguments
+    ///
+    /// * `new_ncm` - Function returning a new (untrained) object
+    ///               implementing NonconformityScorer.
+    /// * `n_folds` - Number of folds (at least 2).
+    /// * `n_labels` - The number of labels.
+    /// * `epsilon` - Either Some() significance level in [0,1] or None.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use random_world::cp::*;
+    /// use random_world::ncm::*;
+    ///
+    /// let n_folds = 5;
+    /// let n_labels = 2;
+    /// let epsilon = 0.1;
+    /// let mut cp = CCP::new(|| KNN::new(2), n_folds, n_labels, Some(epsilon));
+    /// ```
+    pub fn new(new_ncm: F, n_folds: usize, n_labels: usize,
+               epsilon: Option<f64>) -> CCP<T, N, F> {
+        assert!(n_folds > 1);
+        assert!(n_labels > 0);
+
+        if let Some(e) = epsilon {
+            assert!(e >= 0. && e <= 1.);
+        }
+
+        CCP {
+            new_ncm: new_ncm,
+            ncms: vec![],
+            n_folds: n_folds,
+            epsilon: epsilon,
+            n_labels: n_labels,
+            train_inputs: None,
+            train_targets: None,
+            marker: PhantomData,
+        }
+    }
+
+    /// Trains and calibrates one nonconformity scorer per fold on
+    /// the current training data.
+    fn train_folds(&mut self) -> Result<()> {
+        let inputs = self.train_inputs.as_ref()
+                                      .ok_or(Error::NotTrained)?;
+        let targets = self.train_targets.as_ref()
+                                        .ok_or(Error::NotTrained)?;
+        let n = inputs.rows();
+        // Need at least one training example per fold.
+        if n < self.n_folds {
+            return Err(Error::TooFewExamples { expected: self.n_folds, found: n });
+        }
+
+        let folds = stratified_folds(&targets.view(), self.n_folds);
+        let mut ncms = Vec::with_capacity(self.n_folds);
+        for fold in &folds {
+            let proper = (0..n).filter(|i| fold.binary_search(i).is_err())
+                               .collect::<Vec<_>>();
+
+            let mut ncm = (self.new_ncm)();
+            ncm.train(&inputs.select(Axis(0), &proper).view(),
+                      &targets.select(Axis(0), &proper).view(), self.n_labels)?;
+            ncm.calibrate(&inputs.select(Axis(0), fold).view(),
+                          &targets.select(Axis(0), fold).view())?;
+            ncms.push(ncm);
+        }
+        self.ncms = ncms;
+
+        Ok(())
+    }
+}
+
+impl<T, N, F> ConfidencePredictor<T> for CCP<T, N, F>
+        where T: Clone + Sync + Copy, N: NonconformityScorer<T>, F: Fn() -> N {
+
+    /// Sets the significance level.
+    ///
+    /// # Arguments
+    ///
+    /// * `epsilon` - Significance level in [0,1].
+    fn set_epsilon(&mut self, epsilon: f64) {
+        assert!(epsilon >= 0. && epsilon <= 1.);
+
+        self.epsilon = Some(epsilon);
+    }
+
+    /// Trains a Cross-Conformal Predictor on a training set.
+    ///
+    /// The training set is split into folds; for each fold, a
+    /// nonconformity scorer is trained on the remaining folds, and
+    /// calibrated on the fold itself.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<T>) with values of type T of training
+    ///              vectors.
+    /// * `targets` - Vector (Array1<T>) of labels corresponding to the
+    ///               training vectors.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// #[macro_use(array)]
+    /// extern crate ndarray;
+    /// extern crate random_world;
+    ///
+    /// # fn main() {
+    /// use random_world::cp::*;
+    /// use random_world::ncm::*;
+    ///
+    /// let mut cp = CCP::new(|| KNN::new(1), 2, 2, None);
+    /// let train_inputs = array![[0., 0.],
+    ///                           [1., 1.],
+    ///                           [0., 1.],
+    ///                           [2., 2.]];
+    /// let train_targets = array![0, 1, 0, 1];
+    /// let test_inputs = array![[0., 0.5]];
+    ///
+    /// cp.train(&train_inputs.view(), &train_targets.view())
+    ///   .expect("Failed to train model");
+    /// let pvalues = cp.predict_confidence(&test_inputs.view())
+    ///                 .expect("Failed to predict");
+    /// assert!(pvalues == array![[1., 2./3.]]);
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - if the number of training examples is not consistent
+    ///   with the number of respective labels.
+    /// - if a label is not smaller than `n_labels`.
+    /// - if the number of training examples is smaller than the
+    ///   number of folds.
+    fn train(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView1<usize>)
+             -> Result<()> {
+
+        check_lengths(inputs.rows(), targets.len())?;
+        check_labels(targets, self.n_labels)?;
+
+        self.train_inputs = Some(inputs.to_owned());
+        self.train_targets = Some(targets.to_owned());
+
+        self.train_folds()
+    }
+
+    /// Updates a Cross-Conformal Predictor with more training data.
+    ///
+    /// The new examples are appended to the training set, and the
+    /// nonconformity scorers of all the folds are re-trained.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<T>) with values of type T of training
+    ///              vectors.
+    /// * `targets` - Vector (Array1<T>) of labels corresponding to the
+    ///               training vectors.
+    ///
+    /// # Errors
+    ///
+    /// - if the number of training examples is not consistent
+    ///   with the number of respective labels.
+    /// - if a label is not smaller than `n_labels`.
+    /// - if `train()` hasn't been called once before.
+    fn update(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView1<usize>)
+             -> Result<()> {
+
+        check_lengths(inputs.rows(), targets.len())?;
+        check_labels(targets, self.n_labels)?;
+
+        let train_inputs = match self.train_inputs {
+            Some(ref train_inputs) => {
+                if inputs.cols() != train_inputs.cols() {
+                    return Err(Error::DimensionMismatch { expected: train_inputs.cols(),
+                                                          found: inputs.cols() });
+                }
+                stack![Axis(0), *train_inputs, *inputs]
+            },
+            None => return Err(Error::NotTrained),
+        };
+        let train_targets = match self.train_targets {
+            Some(ref train_targets) => stack![Axis(0), *train_targets, *targets],
+            None => return Err(Error::NotTrained),
+        };
+        self.train_inputs = Some(train_inputs);
+        self.train_targets = Some(train_targets);
+
+        self.train_folds()
+    }
+
+    /// A Cross-Conformal Predictor calibrates its folds when training:
+    /// calling this method returns an error.
+    fn calibrate(&mut self, _inputs: &ArrayView2<T>, _targets: &ArrayView1<usize>)
+            -> Result<()> {
+
+        Err(Error::NotInductive)
+    }
+
+    /// Returns candidate labels (region prediction) for test vectors.
+    ///
+    /// The return value is a matrix of `bool` (`Array2<bool>`) with shape
+    /// `(n_inputs, n_labels)`, where `n_inputs = inputs.rows()` and
+    /// `n_labels` is the number of possible labels;
+    /// in such matrix, each column `y` corresponds to a label,
+    /// each row `i` to an input object, and the value at `[i,y]` is
+    /// true if the label conforms the distribution, false otherwise.
+    fn predict(&mut self, inputs: &ArrayView2<T>) -> Result<Array2<bool>> {
+        let epsilon = self.epsilon.ok_or(Error::NoEpsilon)?;
+
+        let pvalues = self.predict_confidence(inputs)?;
+
+        Ok(pvalues.mapv(|p| p > epsilon))
+    }
+
+    /// Returns the p-values for test vectors.
+    ///
+    /// The return value is a matrix of `f64` (`Array2<f64>`) with shape
+    /// `(n_inputs, n_labels)`, where `n_inputs = inputs.rows()` and
+    /// `n_labels` is the number of possible labels;
+    /// in such matrix, each column `y` corresponds to a label,
+    /// each row `i` to an input object, and the value at `[i,y]` is
+    /// the p-value obtained when assuming `y` as a label for the
+    /// `i`-th input object.
+    fn predict_confidence(&mut self, inputs: &ArrayView2<T>) -> Result<Array2<f64>> {
+        if self.ncms.is_empty() {
+            return Err(Error::NotTrained);
+        }
+
+        // Init pvalues with NaN to ease future debugging.
+        let mut pvalues = Array2::<f64>::from_elem((inputs.rows(), self.n_labels), f64::NAN);
+
+        for (i, x) in inputs.outer_iter().enumerate() {
+            // For randomized scores, each fold draws a random number
+            // for the test input, shared by its candidate labels.
+            let draws = self.ncms.iter()
+                                 .map(|ncm| ncm.test_draw())
+                                 .collect::<Vec<_>>();
+
+            for y in 0..self.n_labels {
+                // Merge the rank counts of all folds.
+                let mut ge = 0;
+                let mut n = 0;
+
+                for (ncm, u) in self.ncms.iter().zip(&draws) {
+                    let scores = ncm.scores_with_draw(&x, y, *u)?;
+                    let x_score = scores[0];
+
+                    n += scores.len() - 1;
+                    ge += scores.into_iter()
+                                .skip(1)
+                                .filter(|score| *score >= x_score)
+                                .count();
+                }
+
+                pvalues[[i,y]] = (ge as f64 + 1.) / (n as f64 + 1.);
+            }
+        }
+
+        Ok(pvalues)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ncm::KNN;
+
+    /// Verify that folds are stratified by label.
+    #[test]
+    fn folds() {
+        let folds = stratified_folds(&array![1, 0, 1, 0, 0, 1, 1].view(), 3);
+        // Labels 0 are at 1, 3, 4; labels 1 at 0, 2, 5, 6.
+        assert!(folds == vec![vec![0, 1, 6], vec![2, 3], vec![4, 5]]);
+    }
+
+    /// Verify the p-values of a CCP against hand-computed ones, and that
+    /// it cannot be trained on fewer examples than folds.
+    #[test]
+    fn pvalues() {
+        let train_inputs = array![[0.], [1.], [3.], [10.], [12.], [15.]];
+        let train_targets = array![0, 0, 0, 1, 1, 1];
+        let test_inputs = array![[2.],
+                                 [4.]];
+        // Folds: {0, 10}, {1, 12}, {3, 15}. The calibration scores of
+        // label 0 are 1, 1 and 2; those of label 1 are 2, 2 and 3.
+        // For [4.], the scores of label 0 are 1, 1 and 3.
+        let expected_pvalues = array![[1., 1. / 4.],
+                                      [3. / 4., 1. / 4.]];
+
+        let mut cp = CCP::new(|| KNN::new(1), 3, 2, None);
+        cp.train(&train_inputs.view(), &train_targets.view()).unwrap();
+        let pvalues = cp.predict_confidence(&test_inputs.view()).unwrap();
+        assert!(pvalues == expected_pvalues);
+
+        let mut cp = CCP::new(|| KNN::new(1), 3, 2, None);
+        match cp.train(&array![[0.], [1.]].view(), &array![0, 1].view()) {
+            Err(Error::TooFewExamples { expected: 3, found: 2 }) => {},
+            r => panic!("Unexpected result: {:?}", r),
+        }
+    }
+}
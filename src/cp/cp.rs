@@ -1,15 +1,61 @@
-
 //! Transductive deterministic or smooth Conformal Predictors.
 use pcg_rand::Pcg32;
 use rand::{Rng, SeedableRng};
-use rusty_machine::learning::LearningResult;
 use ndarray::prelude::*;
 use std::f64::NAN;
 use std::marker::PhantomData;
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
 
-use cp::ConfidencePredictor;
+use cp::{ConfidencePredictor, LikelihoodRatio, Taxonomy};
+use error::{Error, Result, check_lengths, check_labels, check_weights};
 use ncm::NonconformityScorer;
 
+/// Seed equivalent to `Pcg32::new_unseeded()`.
+const UNSEEDED: [u64; 2] = [0, 1442695040888963407];
+
+/// Multiplier of the LCG underlying `Pcg32`.
+const PCG_MULTIPLIER: u64 = 6364136223846793005;
+
+/// Number of LCG steps taken by `Pcg32` to draw an `f64`.
+const STEPS_PER_DRAW: u64 = 2;
+
+/// State of the PRNG of a smooth CP.
+///
+/// The PRNG is restored by seeding it with `seed`, and by skipping
+/// the `draws` random numbers it already drew.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct RngState {
+    seed: [u64; 2],
+    draws: u64,
+}
+
+impl RngState {
+    /// Returns the PRNG in this state.
+    ///
+    /// Rather than drawing `draws` numbers, the LCG underlying `Pcg32`
+    /// jumps ahead in O(log(draws)) steps (Brown, 1994).
+    fn rng(&self) -> Pcg32 {
+        // The LCG is: state = state * PCG_MULTIPLIER + inc.
+        let inc = self.seed[1] | 1;
+        let mut steps = self.draws.wrapping_mul(STEPS_PER_DRAW);
+        let (mut mult, mut plus) = (PCG_MULTIPLIER, inc);
+        let (mut acc_mult, mut acc_plus) = (1u64, 0u64);
+        while steps > 0 {
+            if steps & 1 == 1 {
+                acc_mult = acc_mult.wrapping_mul(mult);
+                acc_plus = acc_plus.wrapping_mul(mult).wrapping_add(plus);
+            }
+            plus = mult.wrapping_add(1).wrapping_mul(plus);
+            mult = mult.wrapping_mul(mult);
+            steps >>= 1;
+        }
+        let state = acc_mult.wrapping_mul(self.seed[0]).wrapping_add(acc_plus);
+
+        Pcg32::from_seed([state, self.seed[1]])
+    }
+}
 
 /// A Conformal Predictor, for some nonconformity scorer N and
 /// matrix element type T.
@@ -24,14 +70,32 @@ use ncm::NonconformityScorer;
 /// significance level `epsilon`;
 /// if CP is smooth, then:
 /// $Pr(y \notin Y) = \varepsilon$.
+///
+/// A CP can also be made Mondrian by setting a taxonomy (see
+/// `set_taxonomy()`); in this case, the above guarantees hold
+/// conditionally on each category of the taxonomy.
+/// Under covariate shift, a CP can be made weighted by setting the
+/// likelihood ratio of the test and training distributions (see
+/// `set_likelihood_ratio()`).
+///
+/// A trained CP can be stored and loaded with `utils::save_model()` and
+/// `utils::load_model()`; its taxonomy, likelihood ratio and number of
+/// threads are not stored, and should be set again after loading.
 pub struct CP<T: Sync, N: NonconformityScorer<T>> {
     ncm: N,
     epsilon: Option<f64>,
     smooth: bool,
     rng: Option<Pcg32>,
+    rng_state: Option<RngState>,
     n_labels: usize,
     // If calibrated is Some, this is an ICP, otherwise a TCP.
     calibrated: Option<bool>,
+    // If taxonomy is Some, this is a Mondrian CP.
+    taxonomy: Option<Box<Taxonomy<T>>>,
+    // If likelihood_ratio is Some, this is a weighted CP.
+    likelihood_ratio: Option<Box<LikelihoodRatio<T>>>,
+    // If pool is Some, predictions are computed in parallel.
+    pool: Option<ThreadPool>,
     // TODO: remove the following
     marker: PhantomData<T>,
 }
@@ -70,7 +134,11 @@ impl<T: Sync, N: NonconformityScorer<T>> CP<T, N> {
             smooth: false,
             n_labels: n_labels,
             rng: None,
+            rng_state: None,
             calibrated: None,
+            taxonomy: None,
+            likelihood_ratio: None,
+            pool: None,
             marker: PhantomData,
         }
     }
@@ -114,7 +182,14 @@ impl<T: Sync, N: NonconformityScorer<T>> CP<T, N> {
                 Some(seed) => Some(Pcg32::from_seed(seed)),
                 None => Some(Pcg32::new_unseeded())
             },
+            rng_state: Some(RngState {
+                seed: seed.unwrap_or(UNSEEDED),
+                draws: 0,
+            }),
             calibrated: None,
+            taxonomy: None,
+            likelihood_ratio: None,
+            pool: None,
             marker: PhantomData,
         }
     }
@@ -152,11 +227,376 @@ impl<T: Sync, N: NonconformityScorer<T>> CP<T, N> {
             smooth: false,
             n_labels: n_labels,
             rng: None,
+            rng_state: None,
             calibrated: Some(false),
+            taxonomy: None,
+            likelihood_ratio: None,
+            pool: None,
             marker: PhantomData,
         }
     }
 
+    /// Constructs a new smooth Inductive Conformal Predictor
+    /// `CP<T,N>` from a nonconformity score NonconformityScorer.
+    ///
+    /// Smooth ICP is exactly valid: its error rate equals the
+    /// significance level, which makes its p-values suitable for
+    /// testing exchangeability (see `exchangeability::Martingale`).
+    ///
+    /// # Arguments
+    ///
+    /// * `ncm` - An object implementing NonconformityScorer.
+    /// * `n_labels` - The number of labels.
+    /// * `epsilon` - Either Some() significance level in [0,1] or None.
+    /// * `seed` - Optionally, a slice of 2 elements is provided as seed
+    ///            to the random number generator.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use random_world::cp::*;
+    /// use random_world::ncm::*;
+    ///
+    /// let ncm = KNN::new(2);
+    /// let n_labels = 2;
+    /// let epsilon = 0.1;
+    /// let seed = [0, 0];
+    /// let mut cp = CP::new_smooth_inductive(ncm, n_labels, Some(epsilon), Some(seed));
+    /// ```
+    pub fn new_smooth_inductive(ncm: N, n_labels: usize, epsilon: Option<f64>,
+                                seed: Option<[u64; 2]>) -> CP<T, N> {
+        assert!(n_labels > 0);
+
+        if let Some(e) = epsilon {
+            assert!(e >= 0. && e <= 1.);
+        }
+
+        CP {
+            ncm: ncm,
+            epsilon: epsilon,
+            smooth: true,
+            n_labels: n_labels,
+            rng: match seed {
+                Some(seed) => Some(Pcg32::from_seed(seed)),
+                None => Some(Pcg32::new_unseeded())
+            },
+            rng_state: Some(RngState {
+                seed: seed.unwrap_or(UNSEEDED),
+                draws: 0,
+            }),
+            calibrated: Some(false),
+            taxonomy: None,
+            likelihood_ratio: None,
+            pool: None,
+            marker: PhantomData,
+        }
+    }
+
+    /// Makes this CP a Mondrian Conformal Predictor.
+    ///
+    /// Given a taxonomy, which maps each example `(x, y)` into a category,
+    /// the p-value of a test example is computed only with respect to
+    /// the training (or calibration, for an ICP) examples in its same
+    /// category.
+    /// This guarantees validity within each category (e.g., within each
+    /// label for label-conditional CP, whose taxonomy is `|_, y| y`).
+    /// It can be used with transductive, inductive, deterministic
+    /// and smooth CP.
+    ///
+    /// # Arguments
+    ///
+    /// * `taxonomy` - Function mapping an example `(x, y)` into its
+    ///                category.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// #[macro_use(array)]
+    /// extern crate ndarray;
+    /// extern crate random_world;
+    ///
+    /// # fn main() {
+    /// use random_world::cp::*;
+    /// use random_world::ncm::*;
+    ///
+    /// let ncm = KNN::new(2);
+    /// let n_labels = 2;
+    /// let mut cp = CP::new(ncm, n_labels, Some(0.1));
+    /// // Inputs whose first feature is at least 1 form one category;
+    /// // the remaining ones form another category.
+    /// cp.set_taxonomy(Box::new(|x, _| if x[0] >= 1. { 1 } else { 0 }));
+    ///
+    /// let train_inputs = array![[0., 0.],
+    ///                           [1., 0.],
+    ///                           [0., 1.],
+    ///                           [1., 1.],
+    ///                           [2., 2.],
+    ///                           [1., 2.]];
+    /// let train_targets = array![0, 0, 0, 1, 1, 1];
+    /// let test_inputs = array![[2., 1.]];
+    ///
+    /// cp.train(&train_inputs.view(), &train_targets.view()).unwrap();
+    /// let pvalues = cp.predict_confidence(&test_inputs.view())
+    ///                 .expect("Failed prediction");
+    /// assert!(pvalues == array![[0.5, 1.]]);
+    /// # }
+    /// ```
+    pub fn set_taxonomy(&mut self, taxonomy: Box<Taxonomy<T>>) {
+        self.taxonomy = Some(taxonomy);
+    }
+
+    /// Makes this a weighted Conformal Predictor (Tibshirani et al.,
+    /// 2019), which is valid under covariate shift.
+    ///
+    /// The likelihood ratio $w(x)$ of the test and training
+    /// distributions of the objects weighs the examples the test
+    /// example is compared to: the p-value of a test example $(x, y)$
+    /// with score $\alpha$ is
+    ///
+    /// $$p = \frac{\sum_{i : \alpha_i \geq \alpha} w(x_i) + w(x)}{\sum_i w(x_i) + w(x)}$$
+    ///
+    /// where $(x_i, y_i)$ are the training examples (for a transductive
+    /// CP) or the calibration examples (for an ICP), and $\alpha_i$
+    /// their scores. If $w$ is constant, this is a standard CP.
+    /// It can be used with transductive, inductive, deterministic
+    /// and smooth CP, and together with a taxonomy.
+    ///
+    /// The likelihood ratio can be estimated by a classifier, see
+    /// `classifier_likelihood_ratio()`.
+    ///
+    /// # Arguments
+    ///
+    /// * `likelihood_ratio` - Function returning the (non-negative)
+    ///                        likelihood ratio $w(x)$ of an object `x`.
+    ///                        Predicting returns an error if it is NaN,
+    ///                        negative or infinite for some object.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// #[macro_use(array)]
+    /// extern crate ndarray;
+    /// extern crate random_world;
+    ///
+    /// # fn main() {
+    /// use random_world::cp::*;
+    /// use random_world::ncm::*;
+    ///
+    /// let ncm = KNN::new(1);
+    /// let n_labels = 2;
+    /// let mut cp = CP::new_inductive(ncm, n_labels, None);
+    /// // Test objects are twice as likely to have a positive first
+    /// // feature.
+    /// cp.set_likelihood_ratio(Box::new(|x| if x[0] > 0. { 2. } else { 1. }));
+    ///
+    /// let train_inputs = array![[0., 0.],
+    ///                           [1., 1.]];
+    /// let train_targets = array![0, 1];
+    /// let calibration_inputs = array![[0., 1.],
+    ///                                 [1., 0.]];
+    /// let calibration_targets = array![0, 1];
+    /// let test_inputs = array![[2., 0.]];
+    ///
+    /// cp.train(&train_inputs.view(), &train_targets.view())
+    ///   .expect("Failed to train model");
+    /// cp.calibrate(&calibration_inputs.view(), &calibration_targets.view())
+    ///   .expect("Failed to calibrate model");
+    /// let pvalues = cp.predict_confidence(&test_inputs.view())
+    ///                 .expect("Failed to predict");
+    /// // Without likelihood ratio, the p-values would be [[0.5, 0.5]].
+    /// assert!(pvalues == array![[2./3., 0.5]]);
+    /// # }
+    /// ```
+    pub fn set_likelihood_ratio(&mut self, likelihood_ratio: Box<LikelihoodRatio<T>>) {
+        self.likelihood_ratio = Some(likelihood_ratio);
+    }
+
+    /// Returns a reference to the nonconformity scorer.
+    pub(crate) fn ncm(&self) -> &N {
+        &self.ncm
+    }
+
+    /// Updates a calibrated Inductive Conformal Predictor with more
+    /// calibration data.
+    ///
+    /// This allows to use ICP in on-line mode: after predicting a test
+    /// example, and once its label is revealed, the example is added to
+    /// the calibration set, while the proper training set stays fixed
+    /// (or is updated separately via `update()`).
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<T>) with values of type T of calibration
+    ///              vectors.
+    /// * `targets` - Vector (Array1<T>) of labels corresponding to the
+    ///               calibration vectors.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// #[macro_use(array)]
+    /// extern crate ndarray;
+    /// extern crate random_world;
+    ///
+    /// # fn main() {
+    /// use random_world::cp::*;
+    /// use random_world::ncm::*;
+    ///
+    /// let ncm = KNN::new(1);
+    /// let n_labels = 2;
+    /// let mut cp = CP::new_inductive(ncm, n_labels, None);
+    /// let train_inputs = array![[0., 0.],
+    ///                           [1., 1.]];
+    /// let train_targets = array![0, 1];
+    /// let calibration_inputs = array![[0., 1.]];
+    /// let calibration_targets = array![0];
+    /// let test_inputs = array![[2., 0.]];
+    ///
+    /// cp.train(&train_inputs.view(), &train_targets.view())
+    ///   .expect("Failed to train model");
+    /// cp.calibrate(&calibration_inputs.view(), &calibration_targets.view())
+    ///   .expect("Failed to calibrate model");
+    /// let pvalues = cp.predict_confidence(&test_inputs.view())
+    ///                 .expect("Failed to predict");
+    /// assert!(pvalues == array![[0.5, 1.]]);
+    ///
+    /// // Add a new calibration example once its label is revealed.
+    /// cp.update_calibration(&array![[1., 0.]].view(), &array![1].view())
+    ///   .expect("Failed to update calibration");
+    /// let pvalues = cp.predict_confidence(&test_inputs.view())
+    ///                 .expect("Failed to predict");
+    /// assert!(pvalues == array![[0.5, 0.5]]);
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - if the number of calibration examples is not consistent
+    ///   with the number of respective labels, or if a label is not
+    ///   smaller than `n_labels`.
+    /// - if this is not an ICP, or if `calibrate()` hasn't been
+    ///   called once before.
+    pub fn update_calibration(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView1<usize>)
+            -> Result<()> {
+
+        match self.calibrated {
+            None => return Err(Error::NotInductive),
+            Some(false) => return Err(Error::NotCalibrated),
+            Some(true) => {},
+        }
+        check_lengths(inputs.rows(), targets.len())?;
+        check_labels(targets, self.n_labels)?;
+
+        self.ncm.update_calibration(inputs, targets)
+    }
+
+    /// Sets the number of threads used for prediction.
+    ///
+    /// P-values are computed concurrently across test inputs and
+    /// candidate labels. The result is identical to that of serial
+    /// prediction; for smooth CP, random numbers are still drawn in
+    /// the same order (i.e., row by row, and label by label), and so
+    /// are those of randomized scores (one per row).
+    ///
+    /// # Arguments
+    ///
+    /// * `n_threads` - Number of threads. If 1, prediction is serial;
+    ///                 if 0, the number of threads is the number of CPUs.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use random_world::cp::*;
+    /// use random_world::ncm::*;
+    ///
+    /// let ncm = KNN::new(2);
+    /// let n_labels = 2;
+    /// let mut cp = CP::new(ncm, n_labels, Some(0.1));
+    /// cp.set_threads(4)
+    ///   .expect("Failed to create a thread pool");
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - if the thread pool cannot be created.
+    pub fn set_threads(&mut self, n_threads: usize) -> Result<()> {
+        self.pool = if n_threads == 1 {
+            None
+        } else {
+            Some(ThreadPoolBuilder::new().num_threads(n_threads)
+                                         .build()?)
+        };
+
+        Ok(())
+    }
+
+    /// Counts the scores greater than and equal to the score of a test
+    /// example `(x, y)`, and the number of scores it is compared to
+    /// (including itself).
+    ///
+    /// For a Mondrian CP, `categories[y]` contains the categories of the
+    /// examples visited by `visit_examples(y)`.
+    /// For a weighted CP, `weights[y]` contains their likelihood ratios,
+    /// and the counts are weighted.
+    /// For randomized scores, `u` is the random number drawn for `x`.
+    fn counts(&self, x: &ArrayView1<T>, y: usize, u: Option<f64>,
+              categories: &Option<Vec<Vec<usize>>>,
+              weights: &Option<Vec<Vec<f64>>>) -> Result<(f64, f64, f64)> {
+        match self.ncm.calibration_scores(y) {
+            // ICP with precomputed (sorted) calibration scores:
+            // a binary search is enough.
+            Some(calibration_scores) if self.taxonomy.is_none() &&
+                                        self.likelihood_ratio.is_none() => {
+                let x_score = self.ncm.score_with_draw(x, y, u)?;
+                let lt = calibration_scores.partition_point(|s| *s < x_score);
+                let le = calibration_scores.partition_point(|s| *s <= x_score);
+                let n_cal = calibration_scores.len();
+
+                Ok(((n_cal - le) as f64, (le - lt) as f64 + 1., (n_cal + 1) as f64))
+            },
+            _ => {
+                let scores = self.ncm.scores_with_draw(x, y, u)?;
+                let x_score = scores[0];
+
+                // Category of the test example for a Mondrian CP.
+                let category = self.taxonomy.as_ref()
+                                            .map(|taxonomy| taxonomy(x, y));
+
+                // Weight of the test example for a weighted CP.
+                let x_weight = match self.likelihood_ratio {
+                    Some(ref likelihood_ratio) => likelihood_ratio(x),
+                    None => 1.,
+                };
+                check_weights(&[x_weight])?;
+
+                let mut gt = 0.;
+                let mut eq = x_weight;
+                let mut n = x_weight;
+
+                for (j, score) in scores.into_iter().skip(1).enumerate() {
+                    // Mondrian CP only compares with examples in
+                    // the same category.
+                    if let (Some(c), Some(categories)) = (category, categories.as_ref()) {
+                        if categories[y][j] != c {
+                            continue;
+                        }
+                    }
+                    let weight = weights.as_ref()
+                                        .map_or(1., |weights| weights[y][j]);
+                    n += weight;
+                    // Keep track of greater than and equal.
+                    match () {
+                        _ if score > x_score => gt += weight,
+                        _ if score == x_score => eq += weight,
+                        _ => {},
+                    }
+                }
+
+                Ok((gt, eq, n))
+            },
+        }
+    }
+
 }
 
 impl<T, N> ConfidencePredictor<T> for CP<T, N>
@@ -216,16 +656,17 @@ impl<T, N> ConfidencePredictor<T> for CP<T, N>
     /// # }
     /// ```
     ///
-    /// # Panics
+    /// # Errors
     ///
     /// - if the number of training examples is not consistent
     ///   with the number of respective labels.
-    /// - If labels are not numbers starting from 0 and containing all
-    ///   numbers up to n_labels-1.
+    /// - if a label is not smaller than `n_labels`.
+    /// - if the CP was already trained.
     fn train(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView1<usize>)
-             -> LearningResult<()> {
+             -> Result<()> {
 
-        assert!(inputs.rows() == targets.len());
+        check_lengths(inputs.rows(), targets.len())?;
+        check_labels(targets, self.n_labels)?;
 
         self.ncm.train(inputs, targets, self.n_labels)
     }
@@ -309,33 +750,34 @@ impl<T, N> ConfidencePredictor<T> for CP<T, N>
     /// # }
     /// ```
     ///
-    /// # Panics
+    /// # Errors
     ///
     /// - if the number of training examples is not consistent
     ///   with the number of respective labels.
-    /// - if labels are not numbers starting from 0 and containing all
-    ///   numbers up to n_labels-1.
-    /// - if `train()` hasn't been called once before (i.e., if
-    ///   `self.train_inputs` is `None`.
+    /// - if a label is not smaller than `n_labels`.
+    /// - if `train()` hasn't been called once before.
     fn update(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView1<usize>)
-             -> LearningResult<()> {
+             -> Result<()> {
 
-        assert!(inputs.rows() == targets.len());
+        check_lengths(inputs.rows(), targets.len())?;
+        check_labels(targets, self.n_labels)?;
 
         self.ncm.update(inputs, targets)
     }
 
     fn calibrate(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView1<usize>)
-            -> LearningResult<()> {
+            -> Result<()> {
 
         if self.calibrated.is_none() {
-            panic!("Can only call calibrate() for an inductive CP");
+            return Err(Error::NotInductive);
         }
-        assert!(inputs.rows() == targets.len());
+        check_lengths(inputs.rows(), targets.len())?;
+        check_labels(targets, self.n_labels)?;
 
+        self.ncm.calibrate(inputs, targets)?;
         self.calibrated = Some(true);
 
-        self.ncm.calibrate(inputs, targets)
+        Ok(())
     }
 
     /// Returns candidate labels (region prediction) for test vectors.
@@ -381,10 +823,10 @@ impl<T, N> ConfidencePredictor<T> for CP<T, N>
     ///                         [false, true]]);
     /// # }
     /// ```
-    fn predict(&mut self, inputs: &ArrayView2<T>) -> LearningResult<Array2<bool>> {
-        let epsilon = self.epsilon.expect("Specify epsilon to perform a standard predict()");
+    fn predict(&mut self, inputs: &ArrayView2<T>) -> Result<Array2<bool>> {
+        let epsilon = self.epsilon.ok_or(Error::NoEpsilon)?;
 
-        let pvalues = self.predict_confidence(inputs).expect("Failed to predict p-values");
+        let pvalues = self.predict_confidence(inputs)?;
 
         let preds = Array::from_iter(pvalues.iter()
                                             .map(|p| *p > epsilon))
@@ -437,54 +879,148 @@ impl<T, N> ConfidencePredictor<T> for CP<T, N>
     ///                           [0.25, 1.]]);
     /// }
     /// ```
-    fn predict_confidence(&mut self, inputs: &ArrayView2<T>) -> LearningResult<Array2<f64>> {
+    fn predict_confidence(&mut self, inputs: &ArrayView2<T>) -> Result<Array2<f64>> {
         // ICP needs to be calibrated.
-        if let Some(calibrated) = self.calibrated {
-            if !calibrated {
-                panic!("Need to calibrate() an ICP before calling predict()");
-            }
+        if self.calibrated == Some(false) {
+            return Err(Error::NotCalibrated);
         }
 
         // Init pvalues with NaN to ease future debugging.
         let mut pvalues = Array2::<f64>::from_elem((inputs.rows(), self.n_labels), NAN);
 
-        // Compute a p-value for each test input and for each candidate label.
-        for (i, x) in inputs.outer_iter().enumerate() {
-            for y in 0..self.n_labels {
-                let scores = self.ncm.scores(&x, y);
-                let x_score = scores[0];
+        // For a Mondrian CP, determine (for each candidate label) the
+        // category of each example the test example is compared to.
+        let categories = match self.taxonomy {
+            Some(ref taxonomy) => {
+                let ncm = &self.ncm;
+                Some((0..self.n_labels).map(|y| {
+                                           let mut categories = vec![];
+                                           ncm.visit_examples(y, &mut |x, y| {
+                                               categories.push(taxonomy(x, y))
+                                           })?;
+                                           Ok(categories)
+                                       })
+                                       .collect::<Result<Vec<_>>>()?)
+            },
+            None => None,
+        };
 
-                let mut gt = 0.;
-                let mut eq = 1.;
+        // For a weighted CP, determine (for each candidate label) the
+        // likelihood ratio of each example the test example is compared to.
+        let weights = match self.likelihood_ratio {
+            Some(ref likelihood_ratio) => {
+                let ncm = &self.ncm;
+                Some((0..self.n_labels).map(|y| {
+                                           let mut weights = vec![];
+                                           ncm.visit_examples(y, &mut |x, _| {
+                                               weights.push(likelihood_ratio(x))
+                                           })?;
+                                           check_weights(&weights)?;
+                                           Ok(weights)
+                                       })
+                                       .collect::<Result<Vec<_>>>()?)
+            },
+            None => None,
+        };
 
-                let n = scores.len();
-                for score in scores.into_iter().skip(1) {
-                    // Keep track of greater than and equal.
-                    match () {
-                        _ if score > x_score => gt += 1.,
-                        _ if score == x_score => eq += 1.,
-                        _ => {},
-                    }
-                }
+        // For randomized scores, draw one random number for each test
+        // input (shared by its candidate labels), in the same order for
+        // serial and parallel prediction.
+        let draws = (0..inputs.rows()).map(|_| self.ncm.test_draw())
+                                      .collect::<Vec<_>>();
 
-                // Compute p-value.
-                let pvalue = if self.smooth {
-                    let tau = self.rng.as_mut()
-                                      .expect("Initialize as smooth CP to use")
-                                      .gen::<f64>();
-                    (gt + eq*tau) / (n as f64)
-                } else {
-                    (gt + eq) / (n as f64)
-                };
+        // Count greater and equal scores for each test input and for each
+        // candidate label (i.e., for each cell of the p-values matrix).
+        let n_labels = self.n_labels;
+        let n_cells = inputs.rows() * n_labels;
+        let counts = match self.pool {
+            Some(ref pool) => {
+                let cp = &*self;
+                let draws = &draws;
+                pool.install(|| (0..n_cells).into_par_iter()
+                                            .map(|c| cp.counts(&inputs.row(c / n_labels),
+                                                               c % n_labels,
+                                                               draws[c / n_labels],
+                                                               &categories, &weights))
+                                            .collect::<Result<Vec<_>>>())?
+            },
+            None => (0..n_cells).map(|c| self.counts(&inputs.row(c / n_labels),
+                                                     c % n_labels, draws[c / n_labels],
+                                                     &categories, &weights))
+                                .collect::<Result<Vec<_>>>()?,
+        };
 
-                pvalues[[i,y]] = pvalue;
-            }
+        // Compute p-values. For smooth CP, random numbers are drawn
+        // in the same order for serial and parallel prediction.
+        for (c, (gt, eq, n)) in counts.into_iter().enumerate() {
+            let pvalue = if self.smooth {
+                let tau = self.rng.as_mut()
+                                  .expect("Initialize as smooth CP to use")
+                                  .gen::<f64>();
+                if let Some(ref mut rng_state) = self.rng_state {
+                    rng_state.draws += 1;
+                }
+                (gt + eq*tau) / n
+            } else {
+                (gt + eq) / n
+            };
+
+            pvalues[[c / n_labels, c % n_labels]] = pvalue;
         }
 
         Ok(pvalues)
     }
 }
 
+/// Serialized representation of a Conformal Predictor.
+#[derive(Serialize, Deserialize)]
+struct CPState<N> {
+    ncm: N,
+    epsilon: Option<f64>,
+    smooth: bool,
+    n_labels: usize,
+    calibrated: Option<bool>,
+    rng: Option<RngState>,
+}
+
+impl<T, N> Serialize for CP<T, N>
+        where T: Sync, N: NonconformityScorer<T> + Serialize {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        CPState {
+            ncm: &self.ncm,
+            epsilon: self.epsilon,
+            smooth: self.smooth,
+            n_labels: self.n_labels,
+            calibrated: self.calibrated,
+            rng: self.rng_state,
+        }.serialize(serializer)
+    }
+}
+
+impl<'de, T, N> Deserialize<'de> for CP<T, N>
+        where T: Sync, N: NonconformityScorer<T> + Deserialize<'de> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<CP<T, N>, D::Error> {
+        let state = CPState::<N>::deserialize(deserializer)?;
+
+        // Restore the PRNG.
+        let rng = state.rng.map(|rng_state| rng_state.rng());
+
+        Ok(CP {
+            ncm: state.ncm,
+            epsilon: state.epsilon,
+            smooth: state.smooth,
+            rng: rng,
+            rng_state: state.rng,
+            n_labels: state.n_labels,
+            calibrated: state.calibrated,
+            taxonomy: None,
+            likelihood_ratio: None,
+            pool: None,
+            marker: PhantomData,
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -510,4 +1046,17 @@ mod tests {
         assert!(r == vec![0., 0.07996389124884251, 0.6688798670240814,
                           0.5106323435126732, 0.5024848655054046]);
     }
+
+    /// Verify that restoring the PRNG by jumping ahead gives the same
+    /// state as drawing the numbers again.
+    #[test]
+    fn rng_state() {
+        for &seed in &[[0, 0], [42, 7], UNSEEDED] {
+            let mut rng = Pcg32::from_seed(seed);
+            for draws in 0..100 {
+                let mut restored = RngState { seed: seed, draws: draws }.rng();
+                assert!(restored.gen::<f64>() == rng.gen::<f64>());
+            }
+        }
+    }
 }
\ No newline at end of file
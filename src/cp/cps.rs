@@ -0,0 +1,375 @@
+//! Split Conformal Predictive Systems.
+use std::f64;
+use pcg_rand::Pcg32;
+use rand::{Rng, SeedableRng};
+use ndarray::prelude::*;
+use rusty_machine::learning::SupModel;
+use rusty_machine::linalg::{Matrix, Vector};
+
+use cp::{ConfidencePredictor, CP};
+use cp::regression::{TargetScorer, append_targets};
+use error::{Error, Result};
+use ncm::{NonconformityScorer, RegressionScorer, SignedResidual};
+
+
+/// A randomized predictive distribution, output by a Conformal
+/// Predictive System for a test object.
+///
+/// It is a step function over candidate targets `y`: if
+/// $C_{(1)} \leq \dots \leq C_{(n)}$ are the sorted candidate points,
+/// then for $C_{(i)} < y < C_{(i+1)}$ its value is
+/// $Q(y) = \frac{i + \tau}{n + 1}$, where $\tau$ is uniform in [0,1].
+/// More generally, if `lt` points are smaller than `y` and `eq`
+/// are equal to it, then $Q(y) = \frac{lt + \tau (eq + 1)}{n + 1}$.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PredictiveDistribution {
+    // Candidate points C_(1), ..., C_(n), sorted in ascending order.
+    points: Vec<f64>,
+    // Randomization parameter in [0,1].
+    tau: f64,
+}
+
+impl PredictiveDistribution {
+    /// Constructs a predictive distribution from its points and
+    /// randomization parameter `tau`.
+    ///
+    /// # Arguments
+    ///
+    /// * `points` - Candidate points, sorted in ascending order.
+    /// * `tau` - Randomization parameter in [0,1].
+    pub fn new(points: Vec<f64>, tau: f64) -> PredictiveDistribution {
+        assert!(tau >= 0. && tau <= 1.);
+
+        PredictiveDistribution {
+            points: points,
+            tau: tau,
+        }
+    }
+
+    /// Returns the candidate points of the distribution, sorted in
+    /// ascending order.
+    pub fn points(&self) -> &[f64] {
+        &self.points
+    }
+
+    /// Returns the value of the (randomized) predictive CDF at `y`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use random_world::cp::*;
+    ///
+    /// let dist = PredictiveDistribution::new(vec![1., 2., 3.], 0.5);
+    /// assert!(dist.cdf(0.) == 0.125);
+    /// assert!(dist.cdf(2.) == 0.5);
+    /// assert!(dist.cdf(2.5) == 0.625);
+    /// ```
+    pub fn cdf(&self, y: f64) -> f64 {
+        let lt = self.points.iter()
+                            .take_while(|c| **c < y)
+                            .count();
+        let eq = self.points[lt..].iter()
+                                  .take_while(|c| **c == y)
+                                  .count();
+
+        (lt as f64 + self.tau * (eq as f64 + 1.)) / (self.points.len() as f64 + 1.)
+    }
+
+    /// Returns the `p`-quantile of the predictive distribution,
+    /// that is, the smallest `y` such that $Q(y) \geq p$ (or its
+    /// infimum).
+    ///
+    /// The quantile may be infinite for small or large values of `p`.
+    ///
+    /// # Arguments
+    ///
+    /// * `p` - Probability in [0,1].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use random_world::cp::*;
+    ///
+    /// let dist = PredictiveDistribution::new(vec![1., 2., 3.], 0.5);
+    /// assert!(dist.quantile(0.5) == 2.);
+    /// assert!(dist.quantile(0.1) == -std::f64::INFINITY);
+    /// assert!(dist.quantile(0.95) == std::f64::INFINITY);
+    /// ```
+    pub fn quantile(&self, p: f64) -> f64 {
+        assert!(p >= 0. && p <= 1.);
+
+        let n = self.points.len();
+        // Smallest i such that (i + tau) / (n + 1) >= p.
+        let i = (p * (n as f64 + 1.) - self.tau).ceil();
+
+        if i <= 0. {
+            f64::NEG_INFINITY
+        } else if i as usize > n {
+            f64::INFINITY
+        } else {
+            self.points[i as usize - 1]
+        }
+    }
+
+    /// Returns the Continuous Ranked Probability Score (CRPS) of
+    /// the predictive distribution for the true target `y`.
+    ///
+    /// The CRPS is $\int (Q(t) - 1_{t \geq y})^2 dt$; the smaller,
+    /// the better.
+    /// Because $Q$ does not reach 0 and 1 outside of the candidate
+    /// points (which would make the integral infinite), it is set to
+    /// 0 below $C_{(1)}$ and to 1 above $C_{(n)}$ (i.e., the mass of
+    /// the tails is moved to the extreme points).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use random_world::cp::*;
+    ///
+    /// let dist = PredictiveDistribution::new(vec![1., 2., 3.], 0.);
+    /// // Q is 1/4 in (1,2) and 1/2 in (2,3).
+    /// assert!(dist.crps(2.) == 1./16. + 1./4.);
+    /// ```
+    pub fn crps(&self, y: f64) -> f64 {
+        let n = self.points.len();
+        if n == 0 {
+            return f64::NAN;
+        }
+
+        // Tails.
+        let mut crps = (self.points[0] - y).max(0.) + (y - self.points[n-1]).max(0.);
+
+        for i in 1..n {
+            let (a, b) = (self.points[i-1], self.points[i]);
+            let q = (i as f64 + self.tau) / (n as f64 + 1.);
+            // Length of [a,b] below and above y.
+            let below = (y.min(b) - a).max(0.);
+            let above = (b - y.max(a)).max(0.);
+
+            crps += q.powi(2) * below + (1. - q).powi(2) * above;
+        }
+
+        crps
+    }
+}
+
+/// A Split Conformal Predictive System (SCPS), for some underlying
+/// regressor M.
+///
+/// An SCPS trains the regressor on a proper training set, and computes
+/// the nonconformity scores of the calibration examples, i.e., their
+/// signed residuals $\alpha_i = y_i - \hat{y}(x_i)$ (see
+/// `SignedResidual`).
+/// For a test object `x`, it outputs the predictive distribution
+/// with candidate points $\hat{y}(x) + \alpha_i$, which is
+/// calibrated in probability under the exchangeability assumption.
+/// This requires the score to increase with the target, which is why
+/// other regression nonconformity scorers (e.g., `AbsoluteResidual`)
+/// are not supported.
+///
+/// As for `ICPRegressor`, the residuals of the calibration examples
+/// are computed and kept sorted by an inductive `CP` with a single
+/// label.
+pub struct CPS<M: SupModel<Matrix<f64>, Vector<f64>> + Sync> {
+    cp: CP<f64, TargetScorer<SignedResidual<M>>>,
+    rng: Pcg32,
+}
+
+impl<M> CPS<M> where M: SupModel<Matrix<f64>, Vector<f64>> + Sync {
+    /// Constructs a new Split Conformal Predictive System `CPS<M>`
+    /// from a signed residual nonconformity scorer.
+    ///
+    /// # Arguments
+    ///
+    /// * `ncm` - Signed residual nonconformity scorer.
+    /// * `seed` - Optionally, a slice of 2 elements is provided as seed
+    ///            to the random number generator.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// extern crate rusty_machine;
+    /// extern crate random_world;
+    ///
+    /// # fn main() {
+    /// use rusty_machine::learning::lin_reg::LinRegressor;
+    /// use random_world::cp::*;
+    /// use random_world::ncm::*;
+    ///
+    /// let ncm = SignedResidual::new(LinRegressor::default());
+    /// let seed = [0, 0];
+    /// let mut cps = CPS::new(ncm, Some(seed));
+    /// # }
+    /// ```
+    pub fn new(ncm: SignedResidual<M>, seed: Option<[u64; 2]>) -> CPS<M> {
+        CPS {
+            cp: CP::new_inductive(TargetScorer::new(ncm), 1, None),
+            rng: match seed {
+                Some(seed) => Pcg32::from_seed(seed),
+                None => Pcg32::new_unseeded()
+            },
+        }
+    }
+
+    /// Trains the underlying regressor on the proper training set.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<f64>) of training vectors.
+    /// * `targets` - Vector (Array1<f64>) of real-valued targets
+    ///               corresponding to the training vectors.
+    pub fn train(&mut self, inputs: &ArrayView2<f64>, targets: &ArrayView1<f64>)
+                 -> Result<()> {
+        let inputs = append_targets(inputs, targets)?;
+
+        self.cp.train(&inputs.view(), &Array1::zeros(inputs.rows()).view())
+    }
+
+    /// Calibrates the Conformal Predictive System.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<f64>) of calibration vectors.
+    /// * `targets` - Vector (Array1<f64>) of real-valued targets
+    ///               corresponding to the calibration vectors.
+    pub fn calibrate(&mut self, inputs: &ArrayView2<f64>, targets: &ArrayView1<f64>)
+                     -> Result<()> {
+        let inputs = append_targets(inputs, targets)?;
+
+        self.cp.calibrate(&inputs.view(), &Array1::zeros(inputs.rows()).view())
+    }
+
+    /// Adds calibration examples, so that the distributions predicted
+    /// afterwards also use their residuals (e.g., on-line, as the true
+    /// targets of test objects are revealed).
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<f64>) of calibration vectors.
+    /// * `targets` - Vector (Array1<f64>) of real-valued targets
+    ///               corresponding to the calibration vectors.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// #[macro_use(array)]
+    /// extern crate ndarray;
+    /// extern crate rusty_machine;
+    /// extern crate random_world;
+    ///
+    /// # fn main() {
+    /// use rusty_machine::learning::lin_reg::LinRegressor;
+    /// use random_world::cp::*;
+    /// use random_world::ncm::*;
+    ///
+    /// let ncm = SignedResidual::new(LinRegressor::default());
+    /// let mut cps = CPS::new(ncm, Some([0, 0]));
+    ///
+    /// cps.train(&array![[0.], [1.], [2.], [3.]].view(), &array![0., 1., 2., 3.].view())
+    ///    .expect("Failed to train the model");
+    /// cps.calibrate(&array![[0.], [1.]].view(), &array![0.5, 0.75].view())
+    ///    .expect("Failed to calibrate the model");
+    /// cps.update_calibration(&array![[2.]].view(), &array![3.].view())
+    ///    .expect("Failed to update the calibration set");
+    ///
+    /// let dists = cps.predict_distribution(&array![[4.]].view())
+    ///                .expect("Failed to predict");
+    /// assert!(dists[0].points().len() == 3);
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - if the number of calibration examples is not consistent
+    ///   with the number of respective targets.
+    /// - if `calibrate()` hasn't been called once before.
+    pub fn update_calibration(&mut self, inputs: &ArrayView2<f64>, targets: &ArrayView1<f64>)
+                              -> Result<()> {
+        let inputs = append_targets(inputs, targets)?;
+
+        self.cp.update_calibration(&inputs.view(), &Array1::zeros(inputs.rows()).view())
+    }
+
+    /// Returns a predictive distribution for each test vector.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// #[macro_use(array)]
+    /// extern crate ndarray;
+    /// extern crate rusty_machine;
+    /// extern crate random_world;
+    ///
+    /// # fn main() {
+    /// use rusty_machine::learning::lin_reg::LinRegressor;
+    /// use random_world::cp::*;
+    /// use random_world::ncm::*;
+    ///
+    /// let ncm = SignedResidual::new(LinRegressor::default());
+    /// let mut cps = CPS::new(ncm, Some([0, 0]));
+    ///
+    /// let train_inputs = array![[0.], [1.], [2.], [3.]];
+    /// let train_targets = array![0., 1., 2., 3.];
+    /// let calibration_inputs = array![[0.], [1.], [2.]];
+    /// let calibration_targets = array![0.5, 0.75, 3.];
+    /// let test_inputs = array![[4.]];
+    ///
+    /// cps.train(&train_inputs.view(), &train_targets.view())
+    ///    .expect("Failed to train the model");
+    /// cps.calibrate(&calibration_inputs.view(), &calibration_targets.view())
+    ///    .expect("Failed to calibrate the model");
+    /// let dists = cps.predict_distribution(&test_inputs.view())
+    ///                .expect("Failed to predict");
+    ///
+    /// // The median of the distribution is 4.5.
+    /// assert!((dists[0].quantile(0.5) - 4.5).abs() < 1e-9);
+    /// # }
+    /// ```
+    pub fn predict_distribution(&mut self, inputs: &ArrayView2<f64>)
+                                -> Result<Vec<PredictiveDistribution>> {
+        let ncm = self.cp.ncm();
+        let scores = ncm.calibration_scores(0)
+                        .ok_or(Error::NotCalibrated)?;
+
+        let preds = ncm.scorer().predict(inputs)?;
+        let rng = &mut self.rng;
+
+        Ok(preds.iter()
+                .map(|y_hat| {
+                    let points = scores.iter()
+                                       .map(|s| y_hat + s)
+                                       .collect();
+                    PredictiveDistribution::new(points, rng.gen::<f64>())
+                })
+                .collect())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Verify the predictive CDF with ties among points.
+    #[test]
+    fn cdf_ties() {
+        let dist = PredictiveDistribution::new(vec![0., 1., 1., 2.], 0.5);
+
+        assert_relative_eq!(dist.cdf(-1.), 0.1);
+        assert_relative_eq!(dist.cdf(0.5), 0.3);
+        assert_relative_eq!(dist.cdf(1.), 0.5);
+        assert_relative_eq!(dist.cdf(3.), 0.9);
+    }
+
+    /// Verify CRPS when the true target falls within and outside
+    /// the candidate points.
+    #[test]
+    fn crps() {
+        let dist = PredictiveDistribution::new(vec![0., 1., 2.], 1.);
+
+        // Q is 1/2 in (0,1), and 3/4 in (1,2).
+        assert_relative_eq!(dist.crps(0.5), 0.25*0.5 + 0.25*0.5 + 0.0625);
+        assert_relative_eq!(dist.crps(-1.), 1. + 0.25 + 0.0625);
+        assert_relative_eq!(dist.crps(3.), 0.25 + 0.5625 + 1.);
+    }
+}
@@ -4,13 +4,158 @@
 //! A `ConfidencePredictor<T>` implements all methods to provide a confidence
 //! prediction for new input vectors.
 //! Examples of confidence predictors are inductive and transductive
-//! Conformal Predictors.
+//! Conformal Predictors, and Cross-Conformal Predictors.
+//! A `ConfidenceRegressor<T>` is their counterpart for real-valued targets,
+//! and outputs prediction intervals.
+//! A Conformal Predictive System (`CPS<M>`) outputs, instead, a full
+//! predictive distribution for each test vector.
+//! A `MultiLabelPredictor<T>` handles examples carrying a set of labels.
+//! Adaptive Conformal Inference (`ACI<T,P>`) wraps a confidence predictor
+//! to track a target error rate in on-line mode.
 pub mod cp;
+pub mod ccp;
+pub mod regression;
+pub mod cps;
+pub mod multilabel;
+pub mod aci;
 
 use ndarray::prelude::*;
-use rusty_machine::learning::LearningResult;
+use error::Result;
 
 pub use self::cp::CP;
+pub use self::ccp::CCP;
+pub use self::regression::ICPRegressor;
+pub use self::cps::{CPS, PredictiveDistribution};
+pub use self::multilabel::{PowersetCP, BinaryRelevanceCP};
+pub use self::aci::ACI;
+
+
+/// A taxonomy for Mondrian Conformal Predictors.
+///
+/// A taxonomy maps an example `(x, y)` into a category; a Mondrian CP
+/// computes the p-value of a test example only with respect to the
+/// examples falling into its same category.
+/// For instance, `|_, y| y` is the taxonomy of label-conditional CP.
+pub type Taxonomy<T> = dyn Fn(&ArrayView1<T>, usize) -> usize + Send + Sync;
+
+/// A likelihood ratio for weighted Conformal Predictors.
+///
+/// Under covariate shift, the likelihood ratio $w(x)$ of an object `x`
+/// is the ratio between the densities of the test and training
+/// distributions of the objects at `x`.
+pub type LikelihoodRatio<T> = dyn Fn(&ArrayView1<T>) -> f64 + Send + Sync;
+
+/// Returns the likelihood ratio estimated by a probabilistic classifier
+/// that discriminates test objects from training objects.
+///
+/// If the classifier was trained on `n_train` training objects (class 0)
+/// and `n_test` test objects (class 1), and $p(x)$ is its estimated
+/// probability that `x` is a test object, the likelihood ratio is
+/// estimated as
+///
+/// $$w(x) = \frac{p(x)}{1 - p(x)} \frac{n_{train}}{n_{test}}.$$
+///
+/// # Arguments
+///
+/// * `classifier` - Function returning the probability $p(x)$ in [0,1)
+///                  that an object `x` comes from the test distribution.
+///                  If $p(x) = 1$, the likelihood ratio is infinite, and
+///                  a weighted CP returns an error when predicting.
+/// * `n_train` - Number of training objects the classifier was trained on.
+/// * `n_test` - Number of test objects the classifier was trained on.
+///
+/// # Examples
+///
+/// ```
+/// use random_world::cp::*;
+/// use random_world::ncm::*;
+///
+/// let mut cp = CP::new_inductive(KNN::new(2), 2, Some(0.1));
+/// // E.g., a logistic regression fitted on the first feature.
+/// let likelihood_ratio = classifier_likelihood_ratio::<f64, _>(
+///                             |x| 1. / (1. + (-x[0]).exp()), 100, 50);
+/// cp.set_likelihood_ratio(likelihood_ratio);
+/// ```
+pub fn classifier_likelihood_ratio<T, F>(classifier: F, n_train: usize, n_test: usize)
+        -> Box<LikelihoodRatio<T>>
+        where F: Fn(&ArrayView1<T>) -> f64 + Send + Sync + 'static {
+    assert!(n_train > 0 && n_test > 0);
+
+    let prior_ratio = n_train as f64 / n_test as f64;
+    Box::new(move |x| {
+        let p = classifier(x);
+        p / (1. - p) * prior_ratio
+    })
+}
+
+/// A forced point prediction.
+///
+/// The forced prediction for a test object is the label with the
+/// largest p-value; its confidence is 1 minus the second largest
+/// p-value, and its credibility is the largest p-value.
+/// Confidence is the largest `1-epsilon` for which the prediction set
+/// contains at most one label, credibility the smallest `epsilon` for
+/// which the prediction set is empty.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ForcedPrediction {
+    /// Predicted label.
+    pub label: usize,
+    /// Confidence of the prediction.
+    pub confidence: f64,
+    /// Credibility of the prediction.
+    pub credibility: f64,
+}
+
+impl ForcedPrediction {
+    /// Computes the forced prediction from the p-values of the labels
+    /// for a test object.
+    ///
+    /// If more labels have the largest p-value, the smallest one
+    /// is predicted.
+    /// If there is only one label, the confidence is 1.
+    ///
+    /// # Arguments
+    ///
+    /// * `pvalues` - Vector of p-values, one per label.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// #[macro_use(array)]
+    /// extern crate ndarray;
+    /// extern crate random_world;
+    ///
+    /// # fn main() {
+    /// use random_world::cp::*;
+    ///
+    /// let pvalues = array![0.25, 0.75, 0.5];
+    /// let forced = ForcedPrediction::from_pvalues(&pvalues.view());
+    /// assert!(forced.label == 1);
+    /// assert!(forced.confidence == 0.5);
+    /// assert!(forced.credibility == 0.75);
+    /// # }
+    /// ```
+    pub fn from_pvalues(pvalues: &ArrayView1<f64>) -> ForcedPrediction {
+        assert!(!pvalues.is_empty());
+
+        let mut label = 0;
+        let mut second = 0.;
+        for (y, &p) in pvalues.iter().enumerate().skip(1) {
+            if p > pvalues[label] {
+                second = pvalues[label];
+                label = y;
+            } else if p > second {
+                second = p;
+            }
+        }
+
+        ForcedPrediction {
+            label: label,
+            confidence: 1. - second,
+            credibility: pvalues[label],
+        }
+    }
+}
 
 
 /// A Confidence Predictor (either transductive or inductive)
@@ -35,7 +180,7 @@ pub trait ConfidencePredictor<T> {
     /// # Examples
     ///
     /// Please, see [CP](/cp/cp/struct.CP.html).
-    fn train(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView1<usize>) -> LearningResult<()>;
+    fn train(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView1<usize>) -> Result<()>;
     /// Updates a Conformal Predictor with more training data.
     ///
     /// After calling `train()` once, `update()` allows to add
@@ -52,9 +197,9 @@ pub trait ConfidencePredictor<T> {
     /// # Examples
     ///
     /// Please, see [CP](/cp/cp/struct.CP.html).
-    fn update(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView1<usize>) -> LearningResult<()>;
+    fn update(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView1<usize>) -> Result<()>;
     /// Calibrates an Inductive CP.
-    fn calibrate(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView1<usize>) -> LearningResult<()>;
+    fn calibrate(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView1<usize>) -> Result<()>;
     /// Returns candidate labels (region prediction) for test vectors.
     ///
     /// The return value is a matrix of `bool` (`Array2<bool>`) with shape
@@ -67,7 +212,7 @@ pub trait ConfidencePredictor<T> {
     /// # Examples
     ///
     /// Please, see [CP](/cp/cp/struct.CP.html).
-    fn predict(&mut self, inputs: &ArrayView2<T>) -> LearningResult<Array2<bool>>;
+    fn predict(&mut self, inputs: &ArrayView2<T>) -> Result<Array2<bool>>;
     /// Returns the p-values for test vectors.
     ///
     /// The return value is a matrix of `f64` (`Array2<f64>`) with shape
@@ -81,11 +226,157 @@ pub trait ConfidencePredictor<T> {
     /// # Examples
     ///
     /// Please, see [CP](/cp/cp/struct.CP.html).
-    fn predict_confidence(&mut self, inputs: &ArrayView2<T>) -> LearningResult<Array2<f64>>;
+    fn predict_confidence(&mut self, inputs: &ArrayView2<T>) -> Result<Array2<f64>>;
+    /// Returns the forced predictions for test vectors.
+    ///
+    /// The return value is a vector with one `ForcedPrediction`
+    /// (label, confidence and credibility) per input object, computed
+    /// from the p-values returned by `predict_confidence()`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// #[macro_use(array)]
+    /// extern crate ndarray;
+    /// extern crate random_world;
+    ///
+    /// # fn main() {
+    /// use random_world::cp::*;
+    /// use random_world::ncm::*;
+    ///
+    /// let ncm = KNN::new(2);
+    /// let mut cp = CP::new(ncm, 2, None);
+    /// let train_inputs = array![[0., 0.],
+    ///                           [1., 0.],
+    ///                           [0., 1.],
+    ///                           [1., 1.],
+    ///                           [2., 2.],
+    ///                           [1., 2.]];
+    /// let train_targets = array![0, 0, 0, 1, 1, 1];
+    /// let test_inputs = array![[2., 1.]];
+    ///
+    /// cp.train(&train_inputs.view(), &train_targets.view())
+    ///   .expect("Failed to train");
+    /// let forced = cp.predict_forced(&test_inputs.view())
+    ///                .expect("Failed to predict");
+    /// assert!(forced[0].label == 1);
+    /// assert!(forced[0].confidence == 0.75);
+    /// assert!(forced[0].credibility == 1.);
+    /// # }
+    /// ```
+    fn predict_forced(&mut self, inputs: &ArrayView2<T>) -> Result<Vec<ForcedPrediction>> {
+        let pvalues = self.predict_confidence(inputs)?;
+
+        Ok(pvalues.outer_iter()
+                  .map(|p| ForcedPrediction::from_pvalues(&p))
+                  .collect())
+    }
+    /// Sets the significance level.
+    ///
+    /// # Arguments
+    ///
+    /// * `epsilon` - Significance level in [0,1].
+    fn set_epsilon(&mut self, epsilon: f64);
+}
+
+/// A multi-label Confidence Predictor.
+///
+/// Each example carries a set of labels out of `n_labels` possible ones,
+/// which is represented by a row of `bool` values whose `j`-th element
+/// is true if label `j` is in the set.
+/// This trait is parametrized over `T`, the element type.
+pub trait MultiLabelPredictor<T> {
+    /// Trains a multi-label Confidence Predictor on a training set.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<T>) with values of type T of training
+    ///              vectors.
+    /// * `targets` - Matrix (Array2<bool>) with shape `(n_inputs, n_labels)`
+    ///               of label sets corresponding to the training vectors.
+    fn train(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView2<bool>) -> Result<()>;
+    /// Updates a multi-label Confidence Predictor with more training data.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<T>) with values of type T of training
+    ///              vectors.
+    /// * `targets` - Matrix (Array2<bool>) with shape `(n_inputs, n_labels)`
+    ///               of label sets corresponding to the training vectors.
+    fn update(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView2<bool>) -> Result<()>;
+    /// Calibrates an inductive multi-label Confidence Predictor.
+    fn calibrate(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView2<bool>) -> Result<()>;
+    /// Returns the prediction sets of label sets for test vectors.
+    ///
+    /// The return value is a vector with one matrix of `bool`
+    /// (`Array2<bool>`) per input object; each row of the matrix is a
+    /// label set in the prediction set, in the same format as `targets`.
+    fn predict(&mut self, inputs: &ArrayView2<T>) -> Result<Vec<Array2<bool>>>;
+    /// Returns per-label p-values for test vectors.
+    ///
+    /// The return value is an array of `f64` (`Array3<f64>`) with shape
+    /// `(n_inputs, n_labels, 2)`; the value at `[i,j,0]` (respectively
+    /// `[i,j,1]`) is the p-value of the hypothesis that label `j` is not
+    /// in (respectively, is in) the label set of the `i`-th input object.
+    fn predict_confidence(&mut self, inputs: &ArrayView2<T>) -> Result<Array3<f64>>;
+    /// Sets the significance level.
+    ///
+    /// # Arguments
+    ///
+    /// * `epsilon` - Significance level in [0,1].
+    fn set_epsilon(&mut self, epsilon: f64);
+}
+
+/// A Confidence Predictor for real-valued targets (regression).
+///
+/// This trait is parametrized over `T`, the element type.
+/// It provides all the methods for making a confidence prediction,
+/// in the form of prediction intervals, for regression problems.
+pub trait ConfidenceRegressor<T> {
+    /// Trains a Confidence Regressor on its proper training set.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<T>) with values of type T of training
+    ///              vectors.
+    /// * `targets` - Vector (Array1<f64>) of real-valued targets
+    ///               corresponding to the training vectors.
+    ///
+    /// # Examples
+    ///
+    /// Please, see [ICPRegressor](/cp/regression/struct.ICPRegressor.html).
+    fn train(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView1<f64>) -> Result<()>;
+    /// Calibrates a Confidence Regressor.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<T>) with values of type T of calibration
+    ///              vectors.
+    /// * `targets` - Vector (Array1<f64>) of real-valued targets
+    ///               corresponding to the calibration vectors.
+    fn calibrate(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView1<f64>) -> Result<()>;
+    /// Returns prediction intervals for test vectors.
+    ///
+    /// The return value is a matrix of `f64` (`Array2<f64>`) with shape
+    /// `(n_inputs, 2)`, where `n_inputs = inputs.rows()`;
+    /// the `i`-th row is the interval `[lower, upper]` predicted for
+    /// the `i`-th input object.
+    ///
+    /// # Examples
+    ///
+    /// Please, see [ICPRegressor](/cp/regression/struct.ICPRegressor.html).
+    fn predict(&mut self, inputs: &ArrayView2<T>) -> Result<Array2<f64>>;
+    /// Returns the p-values of candidate targets for test vectors.
+    ///
+    /// The return value is a vector of `f64` (`Array1<f64>`), whose `i`-th
+    /// element is the p-value obtained when assuming `targets[i]` as the
+    /// target of the `i`-th input object.
+    fn predict_confidence(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView1<f64>)
+                          -> Result<Array1<f64>>;
     /// Sets the significance level.
     ///
     /// # Arguments
     ///
     /// * `epsilon` - Significance level in [0,1].
     fn set_epsilon(&mut self, epsilon: f64);
-}
\ No newline at end of file
+}
@@ -0,0 +1,636 @@
+//! Multi-label Conformal Predictors.
+use ndarray::prelude::*;
+
+use cp::{ConfidencePredictor, MultiLabelPredictor, CP};
+use error::{Error, Result, check_lengths};
+use ncm::NonconformityScorer;
+
+
+/// Largest number of labels supported by `PowersetCP`.
+const MAX_POWERSET_LABELS: usize = 16;
+
+/// Checks that label sets have `n_labels` columns.
+fn check_label_sets(targets: &ArrayView2<bool>, n_labels: usize) -> Result<()> {
+    if targets.cols() != n_labels {
+        return Err(Error::DimensionMismatch { expected: n_labels, found: targets.cols() });
+    }
+
+    Ok(())
+}
+
+/// Encodes each label set into an integer, whose `j`-th bit is set
+/// if label `j` is in the set.
+fn encode(targets: &ArrayView2<bool>) -> Array1<usize> {
+    Array::from_iter(targets.outer_iter()
+                            .map(|set| set.iter()
+                                          .enumerate()
+                                          .filter(|&(_, &in_set)| in_set)
+                                          .map(|(j, _)| 1 << j)
+                                          .sum()))
+}
+
+/// Decodes integers into a matrix of label sets, one per row.
+fn decode(sets: &[usize], n_labels: usize) -> Array2<bool> {
+    Array2::from_shape_fn((sets.len(), n_labels), |(i, j)| sets[i] & (1 << j) != 0)
+}
+
+/// A label powerset Conformal Predictor, for some nonconformity
+/// scorer N and matrix element type T.
+///
+/// Each label set is treated as a label of a (multi-class) Conformal
+/// Predictor with `2^n_labels` labels; the label set with labels
+/// `j_1, j_2, ...` corresponds to label $\sum_k 2^{j_k}$.
+/// Because the nonconformity scorer has to deal with `2^n_labels`
+/// labels, at most 16 labels are supported.
+///
+/// The prediction set contains the true label set with probability at
+/// least `1-epsilon`.
+/// Label sets that do not appear in the training (or calibration) data
+/// have no examples to be compared with, so they are always predicted.
+/// For smaller prediction sets, they can be excluded by calling
+/// `set_seen_only(true)`, which sets their p-value to 0; the prediction
+/// set then contains the true label set with probability at least
+/// `1-epsilon` minus the probability that the true label set was not
+/// seen.
+pub struct PowersetCP<T: Sync, N: NonconformityScorer<T>> {
+    cp: CP<T, N>,
+    n_labels: usize,
+    // seen[s] is true if label set s appeared in the training or
+    // calibration data.
+    seen: Vec<bool>,
+    seen_only: bool,
+}
+
+impl<T, N> PowersetCP<T, N>
+        where T: Clone + Sync + Copy, N: NonconformityScorer<T> + Sync {
+    /// Constructs a new (transductive) label powerset Conformal
+    /// Predictor `PowersetCP<T,N>`.
+    ///
+    /// # Arguments
+    ///
+    /// * `ncm` - An object implementing NonconformityScorer.
+    /// * `n_labels` - The number of labels (at most 16).
+    /// * `epsilon` - Either Some() significance level in [0,1] or None.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// #[macro_use(array)]
+    /// extern crate ndarray;
+    /// extern crate random_world;
+    ///
+    /// # fn main() {
+    /// use random_world::cp::*;
+    /// use random_world::ncm::*;
+    ///
+    /// let mut cp = PowersetCP::new(KNN::new(1), 2, Some(0.4));
+    /// // The empty label set is never seen.
+    /// let train_inputs = array![[0.], [0.1], [1.], [1.1], [2.], [2.1]];
+    /// let train_targets = array![[true, false],
+    ///                            [true, false],
+    ///                            [false, true],
+    ///                            [false, true],
+    ///                            [true, true],
+    ///                            [true, true]];
+    /// let test_inputs = array![[2.05]];
+    ///
+    /// cp.train(&train_inputs.view(), &train_targets.view())
+    ///   .expect("Failed to train");
+    /// // Unseen label sets have no examples to be compared with, and
+    /// // their p-value is 1.
+    /// let preds = cp.predict(&test_inputs.view())
+    ///               .expect("Failed to predict");
+    /// assert!(preds[0] == array![[false, false],
+    ///                            [true, true]]);
+    ///
+    /// // Only predict label sets that were seen.
+    /// cp.set_seen_only(true);
+    /// let preds = cp.predict(&test_inputs.view())
+    ///               .expect("Failed to predict");
+    /// assert!(preds[0] == array![[true, true]]);
+    /// # }
+    /// ```
+    pub fn new(ncm: N, n_labels: usize, epsilon: Option<f64>) -> PowersetCP<T, N> {
+        assert!(n_labels > 0 && n_labels <= MAX_POWERSET_LABELS);
+
+        PowersetCP {
+            cp: CP::new(ncm, 1 << n_labels, epsilon),
+            n_labels: n_labels,
+            seen: vec![false; 1 << n_labels],
+            seen_only: false,
+        }
+    }
+
+    /// Constructs a new inductive label powerset Conformal Predictor
+    /// `PowersetCP<T,N>`.
+    ///
+    /// # Arguments
+    ///
+    /// * `ncm` - An object implementing NonconformityScorer.
+    /// * `n_labels` - The number of labels (at most 16).
+    /// * `epsilon` - Either Some() significance level in [0,1] or None.
+    pub fn new_inductive(ncm: N, n_labels: usize, epsilon: Option<f64>)
+            -> PowersetCP<T, N> {
+        assert!(n_labels > 0 && n_labels <= MAX_POWERSET_LABELS);
+
+        PowersetCP {
+            cp: CP::new_inductive(ncm, 1 << n_labels, epsilon),
+            n_labels: n_labels,
+            seen: vec![false; 1 << n_labels],
+            seen_only: false,
+        }
+    }
+
+    /// Sets whether only the label sets seen in the training (or
+    /// calibration) data are predicted (default: false).
+    ///
+    /// This makes prediction sets smaller, at the cost of validity:
+    /// a true label set which was not seen is never covered.
+    ///
+    /// # Arguments
+    ///
+    /// * `seen_only` - Whether unseen label sets are never predicted.
+    pub fn set_seen_only(&mut self, seen_only: bool) {
+        self.seen_only = seen_only;
+    }
+
+    /// Returns the p-values of all the label sets for test vectors.
+    ///
+    /// The return value is a matrix of `f64` (`Array2<f64>`) with shape
+    /// `(n_inputs, 2^n_labels)`; the value at `[i,s]` is the p-value of
+    /// the label set encoded by `s` (see `PowersetCP`) for the `i`-th
+    /// input object.
+    /// If `set_seen_only(true)` was called, the p-value of the
+    /// label sets that were not seen is 0.
+    pub fn predict_label_sets_confidence(&mut self, inputs: &ArrayView2<T>)
+            -> Result<Array2<f64>> {
+        let mut pvalues = self.cp.predict_confidence(inputs)?;
+        if self.seen_only {
+            for (s, mut p_s) in pvalues.axis_iter_mut(Axis(1)).enumerate() {
+                if !self.seen[s] {
+                    p_s.fill(0.);
+                }
+            }
+        }
+
+        Ok(pvalues)
+    }
+
+    /// Marks the label sets `sets` as seen.
+    fn observe(&mut self, sets: &Array1<usize>) {
+        for &s in sets {
+            self.seen[s] = true;
+        }
+    }
+}
+
+impl<T, N> MultiLabelPredictor<T> for PowersetCP<T, N>
+        where T: Clone + Sync + Copy, N: NonconformityScorer<T> + Sync {
+    /// Sets the significance level.
+    ///
+    /// # Arguments
+    ///
+    /// * `epsilon` - Significance level in [0,1].
+    fn set_epsilon(&mut self, epsilon: f64) {
+        self.cp.set_epsilon(epsilon);
+    }
+
+    /// Trains a label powerset Conformal Predictor on a training set.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<T>) with values of type T of training
+    ///              vectors.
+    /// * `targets` - Matrix (Array2<bool>) with shape `(n_inputs, n_labels)`
+    ///               of label sets corresponding to the training vectors.
+    ///
+    /// # Errors
+    ///
+    /// - if the number of training examples is not consistent
+    ///   with the number of respective label sets.
+    /// - if `targets` does not have `n_labels` columns.
+    fn train(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView2<bool>)
+             -> Result<()> {
+        check_label_sets(targets, self.n_labels)?;
+
+        let sets = encode(targets);
+        self.cp.train(inputs, &sets.view())?;
+        self.observe(&sets);
+
+        Ok(())
+    }
+
+    /// Updates a label powerset Conformal Predictor with more training
+    /// data.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<T>) with values of type T of training
+    ///              vectors.
+    /// * `targets` - Matrix (Array2<bool>) with shape `(n_inputs, n_labels)`
+    ///               of label sets corresponding to the training vectors.
+    fn update(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView2<bool>)
+              -> Result<()> {
+        check_label_sets(targets, self.n_labels)?;
+
+        let sets = encode(targets);
+        self.cp.update(inputs, &sets.view())?;
+        self.observe(&sets);
+
+        Ok(())
+    }
+
+    /// Calibrates an inductive label powerset Conformal Predictor.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<T>) with values of type T of calibration
+    ///              vectors.
+    /// * `targets` - Matrix (Array2<bool>) with shape `(n_inputs, n_labels)`
+    ///               of label sets corresponding to the calibration vectors.
+    fn calibrate(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView2<bool>)
+                 -> Result<()> {
+        check_label_sets(targets, self.n_labels)?;
+
+        let sets = encode(targets);
+        self.cp.calibrate(inputs, &sets.view())?;
+        self.observe(&sets);
+
+        Ok(())
+    }
+
+    /// Returns the prediction sets of label sets for test vectors,
+    /// which contain the label sets whose p-value is larger than
+    /// `epsilon` (and, if `set_seen_only(true)` was called, that
+    /// were seen).
+    fn predict(&mut self, inputs: &ArrayView2<T>) -> Result<Vec<Array2<bool>>> {
+        let preds = self.cp.predict(inputs)?;
+
+        Ok(preds.outer_iter()
+                .map(|region| {
+                    let sets = region.iter()
+                                     .enumerate()
+                                     .filter(|&(s, &in_region)| {
+                                         in_region && (self.seen[s] || !self.seen_only)
+                                     })
+                                     .map(|(s, _)| s)
+                                     .collect::<Vec<_>>();
+                    decode(&sets, self.n_labels)
+                })
+                .collect())
+    }
+
+    /// Returns per-label p-values for test vectors.
+    ///
+    /// The p-value of label `j` being (not) in the label set is the
+    /// largest p-value of the label sets (not) containing `j`.
+    fn predict_confidence(&mut self, inputs: &ArrayView2<T>) -> Result<Array3<f64>> {
+        let pvalues = self.predict_label_sets_confidence(inputs)?;
+
+        let mut label_pvalues = Array3::<f64>::zeros((inputs.rows(), self.n_labels, 2));
+        for (mut label_p, p) in label_pvalues.outer_iter_mut().zip(pvalues.outer_iter()) {
+            for (s, &p_s) in p.iter().enumerate() {
+                for j in 0..self.n_labels {
+                    let in_set = (s >> j) & 1;
+                    if p_s > label_p[[j, in_set]] {
+                        label_p[[j, in_set]] = p_s;
+                    }
+                }
+            }
+        }
+
+        Ok(label_pvalues)
+    }
+}
+
+/// A binary relevance Conformal Predictor, for some nonconformity
+/// scorer N and matrix element type T.
+///
+/// A binary Conformal Predictor is trained for each label `j`, to
+/// predict whether `j` is in the label set.
+/// To guarantee family-wise validity, each binary predictor is
+/// used at significance level `epsilon/n_labels` (Bonferroni
+/// correction): the prediction set, which is the product of the
+/// per-label prediction sets, contains the true label set with
+/// probability at least `1-epsilon`.
+pub struct BinaryRelevanceCP<T: Sync, N: NonconformityScorer<T>> {
+    cps: Vec<CP<T, N>>,
+}
+
+impl<T, N> BinaryRelevanceCP<T, N>
+        where T: Clone + Sync + Copy, N: NonconformityScorer<T> + Sync {
+    /// Constructs a new (transductive) binary relevance Conformal
+    /// Predictor `BinaryRelevanceCP<T,N>` from a factory of
+    /// nonconformity scorers.
+    ///
+    /// # Arguments
+    ///
+    /// * `new_ncm` - Function returning a new (untrained) object
+    ///               implementing NonconformityScorer.
+    /// * `n_labels` - The number of labels.
+    /// * `epsilon` - Either Some() significance level in [0,1] or None.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// #[macro_use(array)]
+    /// extern crate ndarray;
+    /// extern crate random_world;
+    ///
+    /// # fn main() {
+    /// use random_world::cp::*;
+    /// use random_world::ncm::*;
+    ///
+    /// let mut cp = BinaryRelevanceCP::new(|| KNN::new(1), 2, Some(0.6));
+    /// let train_inputs = array![[0.], [0.1], [1.], [1.1], [2.], [2.1], [3.], [3.1]];
+    /// let train_targets = array![[true, false],
+    ///                            [true, false],
+    ///                            [false, true],
+    ///                            [false, true],
+    ///                            [true, true],
+    ///                            [true, true],
+    ///                            [false, false],
+    ///                            [false, false]];
+    /// let test_inputs = array![[2.05]];
+    ///
+    /// cp.train(&train_inputs.view(), &train_targets.view())
+    ///   .expect("Failed to train");
+    /// let preds = cp.predict(&test_inputs.view())
+    ///               .expect("Failed to predict");
+    /// assert!(preds[0] == array![[true, true]]);
+    /// # }
+    /// ```
+    pub fn new<F: Fn() -> N>(new_ncm: F, n_labels: usize, epsilon: Option<f64>)
+            -> BinaryRelevanceCP<T, N> {
+        assert!(n_labels > 0);
+
+        if let Some(e) = epsilon {
+            assert!(e >= 0. && e <= 1.);
+        }
+        let epsilon = epsilon.map(|e| e / n_labels as f64);
+
+        BinaryRelevanceCP {
+            cps: (0..n_labels).map(|_| CP::new(new_ncm(), 2, epsilon))
+                              .collect(),
+        }
+    }
+
+    /// Constructs a new inductive binary relevance Conformal Predictor
+    /// `BinaryRelevanceCP<T,N>` from a factory of nonconformity scorers.
+    ///
+    /// # Arguments
+    ///
+    /// * `new_ncm` - Function returning a new (untrained) object
+    ///               implementing NonconformityScorer.
+    /// * `n_labels` - The number of labels.
+    /// * `epsilon` - Either Some() significance level in [0,1] or None.
+    pub fn new_inductive<F: Fn() -> N>(new_ncm: F, n_labels: usize, epsilon: Option<f64>)
+            -> BinaryRelevanceCP<T, N> {
+        assert!(n_labels > 0);
+
+        if let Some(e) = epsilon {
+            assert!(e >= 0. && e <= 1.);
+        }
+        let epsilon = epsilon.map(|e| e / n_labels as f64);
+
+        BinaryRelevanceCP {
+            cps: (0..n_labels).map(|_| CP::new_inductive(new_ncm(), 2, epsilon))
+                              .collect(),
+        }
+    }
+
+    /// Applies `f` to the binary predictor of each label and to the
+    /// (binary) labels of the examples for that label.
+    fn for_each_label<F>(&mut self, targets: &ArrayView2<bool>, f: F) -> Result<()>
+            where F: Fn(&mut CP<T, N>, &ArrayView1<usize>) -> Result<()> {
+        check_label_sets(targets, self.cps.len())?;
+
+        for (j, cp) in self.cps.iter_mut().enumerate() {
+            let label_targets = targets.column(j)
+                                       .mapv(|in_set| in_set as usize);
+            f(cp, &label_targets.view())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<T, N> MultiLabelPredictor<T> for BinaryRelevanceCP<T, N>
+        where T: Clone + Sync + Copy, N: NonconformityScorer<T> + Sync {
+    /// Sets the (family-wise) significance level.
+    ///
+    /// # Arguments
+    ///
+    /// * `epsilon` - Significance level in [0,1].
+    fn set_epsilon(&mut self, epsilon: f64) {
+        assert!(epsilon >= 0. && epsilon <= 1.);
+
+        let n_labels = self.cps.len() as f64;
+        for cp in self.cps.iter_mut() {
+            cp.set_epsilon(epsilon / n_labels);
+        }
+    }
+
+    /// Trains a binary relevance Conformal Predictor on a training set.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<T>) with values of type T of training
+    ///              vectors.
+    /// * `targets` - Matrix (Array2<bool>) with shape `(n_inputs, n_labels)`
+    ///               of label sets corresponding to the training vectors.
+    ///
+    /// # Errors
+    ///
+    /// - if the number of training examples is not consistent
+    ///   with the number of respective label sets.
+    /// - if `targets` does not have `n_labels` columns.
+    fn train(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView2<bool>)
+             -> Result<()> {
+        check_lengths(inputs.rows(), targets.rows())?;
+
+        self.for_each_label(targets, |cp, label_targets| cp.train(inputs, label_targets))
+    }
+
+    /// Updates a binary relevance Conformal Predictor with more training
+    /// data.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<T>) with values of type T of training
+    ///              vectors.
+    /// * `targets` - Matrix (Array2<bool>) with shape `(n_inputs, n_labels)`
+    ///               of label sets corresponding to the training vectors.
+    fn update(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView2<bool>)
+              -> Result<()> {
+        check_lengths(inputs.rows(), targets.rows())?;
+
+        self.for_each_label(targets, |cp, label_targets| cp.update(inputs, label_targets))
+    }
+
+    /// Calibrates an inductive binary relevance Conformal Predictor.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<T>) with values of type T of calibration
+    ///              vectors.
+    /// * `targets` - Matrix (Array2<bool>) with shape `(n_inputs, n_labels)`
+    ///               of label sets corresponding to the calibration vectors.
+    fn calibrate(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView2<bool>)
+                 -> Result<()> {
+        check_lengths(inputs.rows(), targets.rows())?;
+
+        self.for_each_label(targets, |cp, label_targets| cp.calibrate(inputs, label_targets))
+    }
+
+    /// Returns the prediction sets of label sets for test vectors.
+    ///
+    /// The prediction set is the product of the per-label prediction
+    /// sets at significance level `epsilon/n_labels`; it is empty if
+    /// any per-label prediction set is.
+    fn predict(&mut self, inputs: &ArrayView2<T>) -> Result<Vec<Array2<bool>>> {
+        let n_labels = self.cps.len();
+        let regions = self.cps.iter_mut()
+                              .map(|cp| cp.predict(inputs))
+                              .collect::<Result<Vec<_>>>()?;
+
+        Ok((0..inputs.rows()).map(|i| {
+            // Product of the per-label prediction sets, where the first
+            // labels vary fastest.
+            let mut sets: Vec<Vec<bool>> = vec![vec![]];
+            for region in &regions {
+                sets = [false, true].iter()
+                                    .filter(|&&in_set| region[[i, in_set as usize]])
+                                    .flat_map(|&in_set| sets.iter()
+                                                            .map(move |set| {
+                                                                let mut set = set.clone();
+                                                                set.push(in_set);
+                                                                set
+                                                            }))
+                                    .collect();
+            }
+            Array2::from_shape_fn((sets.len(), n_labels), |(k, j)| sets[k][j])
+        }).collect())
+    }
+
+    /// Returns per-label p-values for test vectors, as computed by
+    /// the binary predictor of each label.
+    fn predict_confidence(&mut self, inputs: &ArrayView2<T>) -> Result<Array3<f64>> {
+        let mut pvalues = Array3::<f64>::zeros((inputs.rows(), self.cps.len(), 2));
+
+        for (j, cp) in self.cps.iter_mut().enumerate() {
+            pvalues.subview_mut(Axis(1), j)
+                   .assign(&cp.predict_confidence(inputs)?);
+        }
+
+        Ok(pvalues)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ncm::KNN;
+
+    /// Verify that label sets are encoded into integers and back.
+    #[test]
+    fn encode_decode() {
+        let targets = array![[false, false, false],
+                             [true, false, true],
+                             [false, true, true]];
+
+        let sets = encode(&targets.view());
+        assert!(sets == array![0, 5, 6]);
+        assert!(decode(&sets.to_vec(), 3) == targets);
+    }
+
+    /// Verify that per-label p-values of a label powerset CP are
+    /// the largest p-values of the respective label sets.
+    #[test]
+    fn powerset_label_pvalues() {
+        let train_inputs = array![[0.], [0.1], [1.], [1.1], [2.], [2.1]];
+        let train_targets = array![[true, false],
+                                   [true, false],
+                                   [false, true],
+                                   [false, true],
+                                   [true, true],
+                                   [true, true]];
+        let test_inputs = array![[0.05], [1.05]];
+
+        let mut cp = PowersetCP::new(KNN::new(1), 2, None);
+        cp.train(&train_inputs.view(), &train_targets.view()).unwrap();
+
+        let set_pvalues = cp.predict_label_sets_confidence(&test_inputs.view()).unwrap();
+        let pvalues = cp.predict_confidence(&test_inputs.view()).unwrap();
+
+        for i in 0..2 {
+            let p = set_pvalues.row(i);
+            assert!(pvalues[[i, 0, 0]] == p[0].max(p[2]));
+            assert!(pvalues[[i, 0, 1]] == p[1].max(p[3]));
+            assert!(pvalues[[i, 1, 0]] == p[0].max(p[1]));
+            assert!(pvalues[[i, 1, 1]] == p[2].max(p[3]));
+        }
+    }
+
+    /// Verify that label sets that were not seen are predicted unless
+    /// requested otherwise, and that seen ones are tracked on update().
+    #[test]
+    fn powerset_unseen() {
+        let train_inputs = array![[0.], [0.1], [1.], [1.1]];
+        let train_targets = array![[true, false],
+                                   [true, false],
+                                   [false, true],
+                                   [false, true]];
+        let test_inputs = array![[5.]];
+
+        let mut cp = PowersetCP::new(KNN::new(1), 2, Some(0.1));
+        cp.train(&train_inputs.view(), &train_targets.view()).unwrap();
+        let set_pvalues = cp.predict_label_sets_confidence(&test_inputs.view()).unwrap();
+        assert!(set_pvalues[[0, 0]] == 1.);
+        assert!(cp.predict(&test_inputs.view()).unwrap()[0].rows() == 4);
+
+        cp.set_seen_only(true);
+        let set_pvalues = cp.predict_label_sets_confidence(&test_inputs.view()).unwrap();
+        assert!(set_pvalues[[0, 0]] == 0. && set_pvalues[[0, 3]] == 0.);
+        assert!(cp.predict(&test_inputs.view()).unwrap()[0] == array![[true, false],
+                                                                      [false, true]]);
+
+        cp.update(&array![[3.]].view(), &array![[true, true]].view()).unwrap();
+        assert!(cp.predict(&test_inputs.view()).unwrap()[0].rows() == 3);
+    }
+
+    /// Verify that the prediction set of a binary relevance CP is
+    /// the product of the per-label prediction sets.
+    #[test]
+    fn binary_relevance_product() {
+        let train_inputs = array![[0.], [0.1], [1.], [1.1], [2.], [2.1]];
+        let train_targets = array![[true, false, true],
+                                   [true, false, false],
+                                   [false, true, true],
+                                   [false, true, false],
+                                   [true, true, true],
+                                   [true, true, false]];
+        let test_inputs = array![[0.05]];
+        let epsilon = 0.6;
+
+        let mut cp = BinaryRelevanceCP::new(|| KNN::new(1), 3, Some(epsilon));
+        cp.train(&train_inputs.view(), &train_targets.view()).unwrap();
+
+        let pvalues = cp.predict_confidence(&test_inputs.view()).unwrap();
+        let preds = cp.predict(&test_inputs.view()).unwrap();
+
+        // Each label set is in the prediction set iff each of its
+        // labels is in the respective per-label prediction set.
+        let mut expected = vec![];
+        for s in 0..8 {
+            let in_region = (0..3).all(|j| pvalues[[0, j, (s >> j) & 1]] > epsilon / 3.);
+            if in_region {
+                expected.push(s);
+            }
+        }
+        assert!(preds[0] == decode(&expected, 3));
+        // The third label is independent of the input.
+        assert!(preds[0].rows() > 1);
+    }
+}
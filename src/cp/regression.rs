@@ -0,0 +1,313 @@
+//! Inductive Conformal Predictors for regression.
+use std::f64;
+use ndarray::prelude::*;
+use ndarray::stack;
+
+use cp::{ConfidencePredictor, ConfidenceRegressor, CP};
+use error::{Error, Result, check_lengths, check_labels};
+use ncm::{NonconformityScorer, RegressionScorer, SortedCalibration, visit_inputs};
+use ncm::knn::split_inputs;
+
+
+/// Returns the objects `inputs` with their targets appended as the
+/// last column.
+pub(crate) fn append_targets(inputs: &ArrayView2<f64>, targets: &ArrayView1<f64>) -> Result<Array2<f64>> {
+    check_lengths(inputs.rows(), targets.len())?;
+    let targets = targets.view().into_shape((targets.len(), 1))?;
+
+    Ok(stack(Axis(1), &[inputs.view(), targets])?)
+}
+
+/// Nonconformity scorer through which a `CP` scores the examples of
+/// a regression nonconformity scorer N.
+///
+/// An example `(x, y)` with real-valued target `y` is passed to `CP`
+/// as the object `x` with `y` appended, and label 0.
+pub(crate) struct TargetScorer<N> {
+    ncm: N,
+    // Calibration objects (with their targets), and their scores.
+    calibration: Option<SortedCalibration<f64>>,
+}
+
+impl<N> TargetScorer<N> {
+    /// Wraps the regression scorer `ncm`.
+    pub fn new(ncm: N) -> TargetScorer<N> {
+        TargetScorer {
+            ncm: ncm,
+            calibration: None,
+        }
+    }
+
+    /// Returns a reference to the regression scorer.
+    pub fn scorer(&self) -> &N {
+        &self.ncm
+    }
+}
+
+impl<N: RegressionScorer<f64>> NonconformityScorer<f64> for TargetScorer<N> {
+    /// Trains the regression scorer.
+    fn train(&mut self, inputs: &ArrayView2<f64>, targets: &ArrayView1<usize>,
+             n_labels: usize) -> Result<()> {
+        check_lengths(inputs.rows(), targets.len())?;
+        check_labels(targets, n_labels)?;
+        let d = inputs.cols() as isize - 1;
+
+        self.ncm.train(&inputs.slice(s![.., ..d]), &inputs.column(d as usize))
+    }
+
+    /// Computes and sorts the scores of the calibration examples.
+    fn calibrate(&mut self, inputs: &ArrayView2<f64>, targets: &ArrayView1<usize>)
+             -> Result<()> {
+        let calibration_inputs = split_inputs(inputs, targets, 1)?;
+        self.calibration = Some(SortedCalibration::new(calibration_inputs,
+                                                       |x, y| self.score(x, y))?);
+
+        Ok(())
+    }
+
+    /// Regression scorers cannot be updated with more training examples.
+    fn update(&mut self, _inputs: &ArrayView2<f64>, _targets: &ArrayView1<usize>)
+        -> Result<()> {
+
+        Err(Error::Unsupported("update() of a regression scorer".to_string()))
+    }
+
+    /// Adds the scores of calibration examples.
+    fn update_calibration(&mut self, inputs: &ArrayView2<f64>, targets: &ArrayView1<usize>)
+        -> Result<()> {
+
+        let mut calibration = self.calibration.take()
+                                              .ok_or(Error::NotCalibrated)?;
+        let extended = calibration.extend(inputs, targets, |x, y| self.score(x, y));
+        self.calibration = Some(calibration);
+
+        extended
+    }
+
+    /// Returns the score of a test example, followed by the scores of
+    /// the calibration examples.
+    fn scores(&self, x: &ArrayView1<f64>, y: usize) -> Result<Vec<f64>> {
+        let calibration = self.calibration.as_ref()
+                                          .ok_or(Error::NotCalibrated)?;
+
+        calibration.with_score(self.score(x, y)?, y)
+    }
+
+    /// Returns the score of an example, whose target is the last
+    /// element of `x`.
+    fn score(&self, x: &ArrayView1<f64>, y: usize) -> Result<f64> {
+        if y > 0 {
+            return Err(Error::InvalidLabel { label: y, n_labels: 1 });
+        }
+        let d = x.len() as isize - 1;
+        let object = x.slice(s![..d]).into_shape((1, d as usize))?;
+        let scores = self.ncm.scores(&object, &x.slice(s![d..]))?;
+
+        Ok(scores[0])
+    }
+
+    /// Returns the sorted scores of the calibration examples.
+    fn calibration_scores(&self, y: usize) -> Option<&[f64]> {
+        self.calibration.as_ref()
+                        .and_then(|calibration| calibration.scores(y))
+    }
+
+    /// Visits the calibration examples.
+    fn visit_examples(&self, y: usize, visit: &mut dyn FnMut(&ArrayView1<f64>, usize))
+                      -> Result<()> {
+        let calibration = self.calibration.as_ref()
+                                          .ok_or(Error::NotCalibrated)?;
+
+        visit_inputs(calibration.inputs(), y, visit)
+    }
+}
+
+/// An Inductive Conformal Predictor for regression, for some
+/// nonconformity scorer N.
+///
+/// Let `[l, u]` be the prediction interval output by an ICPRegressor
+/// for a test input vector `x` with true target `y`. Then:
+/// $Pr(y \notin [l, u]) \leq \varepsilon$, where $\varepsilon$ is the
+/// specified significance level `epsilon`.
+///
+/// The examples are scored, and their p-values computed, by an
+/// inductive `CP` with a single label.
+pub struct ICPRegressor<N: RegressionScorer<f64> + Sync> {
+    cp: CP<f64, TargetScorer<N>>,
+    epsilon: Option<f64>,
+}
+
+impl<N: RegressionScorer<f64> + Sync> ICPRegressor<N> {
+    /// Constructs a new Inductive Conformal Predictor for regression
+    /// `ICPRegressor<N>` from a nonconformity score RegressionScorer.
+    ///
+    /// # Arguments
+    ///
+    /// * `ncm` - An object implementing RegressionScorer.
+    /// * `epsilon` - Either Some() significance level in [0,1] or None.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// extern crate rusty_machine;
+    /// extern crate random_world;
+    ///
+    /// # fn main() {
+    /// use rusty_machine::learning::lin_reg::LinRegressor;
+    /// use random_world::cp::*;
+    /// use random_world::ncm::*;
+    ///
+    /// let ncm = AbsoluteResidual::new(LinRegressor::default());
+    /// let epsilon = 0.1;
+    /// let mut cp = ICPRegressor::new(ncm, Some(epsilon));
+    /// # }
+    /// ```
+    pub fn new(ncm: N, epsilon: Option<f64>) -> ICPRegressor<N> {
+        if let Some(e) = epsilon {
+            assert!(e >= 0. && e <= 1.);
+        }
+        ICPRegressor {
+            cp: CP::new_inductive(TargetScorer::new(ncm), 1, None),
+            epsilon: epsilon,
+        }
+    }
+}
+
+impl<N> ConfidenceRegressor<f64> for ICPRegressor<N>
+        where N: RegressionScorer<f64> + Sync {
+
+    /// Sets the significance level.
+    ///
+    /// # Arguments
+    ///
+    /// * `epsilon` - Significance level in [0,1].
+    fn set_epsilon(&mut self, epsilon: f64) {
+        assert!(epsilon >= 0. && epsilon <= 1.);
+
+        self.epsilon = Some(epsilon);
+    }
+
+    /// Trains the nonconformity scorer on the proper training set.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<f64>) of training vectors.
+    /// * `targets` - Vector (Array1<f64>) of real-valued targets
+    ///               corresponding to the training vectors.
+    ///
+    /// # Errors
+    ///
+    /// - if the number of training examples is not consistent
+    ///   with the number of respective targets.
+    fn train(&mut self, inputs: &ArrayView2<f64>, targets: &ArrayView1<f64>)
+             -> Result<()> {
+        let inputs = append_targets(inputs, targets)?;
+
+        self.cp.train(&inputs.view(), &Array1::zeros(inputs.rows()).view())
+    }
+
+    /// Calibrates the ICPRegressor.
+    ///
+    /// Nonconformity scores of the calibration examples are computed
+    /// once, and stored for future predictions.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<f64>) of calibration vectors.
+    /// * `targets` - Vector (Array1<f64>) of real-valued targets
+    ///               corresponding to the calibration vectors.
+    ///
+    /// # Errors
+    ///
+    /// - if the number of calibration examples is not consistent
+    ///   with the number of respective targets.
+    /// - if a nonconformity score is NaN.
+    fn calibrate(&mut self, inputs: &ArrayView2<f64>, targets: &ArrayView1<f64>)
+                 -> Result<()> {
+        let inputs = append_targets(inputs, targets)?;
+
+        self.cp.calibrate(&inputs.view(), &Array1::zeros(inputs.rows()).view())
+    }
+
+    /// Returns prediction intervals for test vectors.
+    ///
+    /// The return value is a matrix of `f64` (`Array2<f64>`) with shape
+    /// `(n_inputs, 2)`, where `n_inputs = inputs.rows()`;
+    /// the `i`-th row is the interval `[lower, upper]` predicted for
+    /// the `i`-th input object.
+    /// The interval contains all the targets whose p-value is larger
+    /// than `epsilon`; if `epsilon` is too small with respect to the
+    /// number of calibration examples, the interval is
+    /// `[-inf, inf]`; if no target has a p-value larger than `epsilon`
+    /// (i.e., `epsilon = 1`), the interval is empty, and represented
+    /// as `[NaN, NaN]`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// #[macro_use(array)]
+    /// extern crate ndarray;
+    /// extern crate rusty_machine;
+    /// extern crate random_world;
+    ///
+    /// # fn main() {
+    /// use rusty_machine::learning::lin_reg::LinRegressor;
+    /// use random_world::cp::*;
+    /// use random_world::ncm::*;
+    ///
+    /// let ncm = AbsoluteResidual::new(LinRegressor::default());
+    /// let mut cp = ICPRegressor::new(ncm, Some(0.5));
+    ///
+    /// let train_inputs = array![[0.], [1.], [2.], [3.]];
+    /// let train_targets = array![0., 1., 2., 3.];
+    /// let calibration_inputs = array![[0.], [1.], [2.]];
+    /// let calibration_targets = array![0.5, 0.75, 3.];
+    /// let test_inputs = array![[4.]];
+    ///
+    /// cp.train(&train_inputs.view(), &train_targets.view())
+    ///   .expect("Failed to train the model");
+    /// cp.calibrate(&calibration_inputs.view(), &calibration_targets.view())
+    ///   .expect("Failed to calibrate the model");
+    /// let intervals = cp.predict(&test_inputs.view())
+    ///                   .expect("Failed to predict");
+    ///
+    /// // Residuals are [0.5, 0.25, 1.]: the interval is 4 +- 0.5.
+    /// assert!((intervals[[0,0]] - 3.5).abs() < 1e-9);
+    /// assert!((intervals[[0,1]] - 4.5).abs() < 1e-9);
+    /// # }
+    /// ```
+    fn predict(&mut self, inputs: &ArrayView2<f64>) -> Result<Array2<f64>> {
+        let epsilon = self.epsilon.ok_or(Error::NoEpsilon)?;
+        let ncm = self.cp.ncm();
+        let scores = ncm.calibration_scores(0)
+                        .ok_or(Error::NotCalibrated)?;
+        let n = scores.len();
+
+        // The interval contains a target y iff at least `m` calibration
+        // scores are larger than or equal to the score of (x, y).
+        let m = (epsilon * (n as f64 + 1.)).floor() as usize;
+
+        if m == 0 {
+            Ok(Array2::from_shape_fn((inputs.rows(), 2),
+                                     |(_, j)| if j == 0 { f64::NEG_INFINITY }
+                                              else { f64::INFINITY }))
+        } else if m > n {
+            Ok(Array2::from_elem((inputs.rows(), 2), f64::NAN))
+        } else {
+            ncm.scorer().intervals(inputs, scores[n - m])
+        }
+    }
+
+    /// Returns the p-values of candidate targets for test vectors.
+    ///
+    /// The return value is a vector of `f64` (`Array1<f64>`), whose `i`-th
+    /// element is the p-value obtained when assuming `targets[i]` as the
+    /// target of the `i`-th input object.
+    fn predict_confidence(&mut self, inputs: &ArrayView2<f64>, targets: &ArrayView1<f64>)
+                          -> Result<Array1<f64>> {
+        let inputs = append_targets(inputs, targets)?;
+        let pvalues = self.cp.predict_confidence(&inputs.view())?;
+
+        Ok(pvalues.column(0).to_owned())
+    }
+}
@@ -0,0 +1,251 @@
+//! Error type returned by the library.
+//!
+//! Methods of predictors and nonconformity scorers, and utility
+//! routines, return a `Result<T>` rather than panicking on bad input
+//! or misuse (e.g., predicting before calibrating an ICP).
+//!
+//! # Examples
+//!
+//! ```
+//! #[macro_use(array)]
+//! extern crate ndarray;
+//! extern crate random_world;
+//!
+//! # fn main() {
+//! use random_world::cp::*;
+//! use random_world::ncm::*;
+//! use random_world::error::Error;
+//!
+//! let mut cp = CP::new_inductive(KNN::new(1), 2, None);
+//! cp.train(&array![[0., 0.], [1., 1.]].view(), &array![0, 1].view())
+//!   .expect("Failed to train the model");
+//!
+//! // An ICP needs to be calibrated before predicting.
+//! match cp.predict_confidence(&array![[0., 1.]].view()) {
+//!     Err(Error::NotCalibrated) => println!("Calibrate the ICP first"),
+//!     _ => unreachable!(),
+//! }
+//! # }
+//! ```
+use std::error;
+use std::fmt;
+use std::io;
+use std::num::{ParseFloatError, ParseIntError};
+use std::result;
+use csv;
+use ndarray::ShapeError;
+use rayon::ThreadPoolBuildError;
+use rusty_machine::learning::error::Error as LearningError;
+use serde_json;
+
+/// A random-world error.
+#[derive(Debug)]
+pub enum Error {
+    /// The model was used before calling `train()`.
+    NotTrained,
+    /// `train()` was called on a model that was already trained.
+    /// Use `update()` to add training data.
+    AlreadyTrained,
+    /// An inductive predictor was used before calling `calibrate()`.
+    NotCalibrated,
+    /// `calibrate()` (or `update_calibration()`) was called on a
+    /// predictor that is not inductive.
+    NotInductive,
+    /// `predict()` was called without specifying a significance level.
+    NoEpsilon,
+    /// A significance level is not in the range `[0, 1]`.
+    InvalidEpsilon(f64),
+    /// A label is not in the range `[0, n_labels)`.
+    InvalidLabel {
+        /// The label.
+        label: usize,
+        /// Number of labels of the predictor.
+        n_labels: usize,
+    },
+    /// The dimensions of the inputs are not consistent, either with
+    /// each other (e.g., number of vectors and of labels) or with
+    /// those of the training data.
+    DimensionMismatch {
+        /// Expected dimension.
+        expected: usize,
+        /// Actual dimension.
+        found: usize,
+    },
+    /// There are fewer examples than needed (e.g., fewer training
+    /// examples than folds of a Cross-Conformal Predictor).
+    TooFewExamples {
+        /// Required number of examples.
+        expected: usize,
+        /// Actual number of examples.
+        found: usize,
+    },
+    /// A model does not support the number of labels of the problem
+    /// (e.g., a binary classifier used with more than two labels).
+    UnsupportedLabels {
+        /// Number of labels of the predictor.
+        n_labels: usize,
+    },
+    /// The inputs contain NaN values.
+    NaN,
+    /// A likelihood ratio of a weighted CP is negative or infinite.
+    InvalidWeight(f64),
+    /// A matrix that needs to be inverted (e.g., the covariance matrix
+    /// of the Mahalanobis distance) is singular.
+    SingularMatrix,
+    /// The training inputs have zero variance, so that a quantity
+    /// (e.g., a kernel bandwidth) cannot be estimated from them.
+    ZeroVariance,
+    /// A model does not support an operation (e.g., updating the
+    /// training data of a regression scorer).
+    Unsupported(String),
+    /// A file has wrong format.
+    Format(String),
+    /// An I/O error.
+    Io(io::Error),
+    /// An error in reading or writing a CSV file.
+    Csv(csv::Error),
+    /// An error in (de)serializing a model.
+    Json(serde_json::Error),
+    /// An error of an underlying rusty-machine model.
+    Learning(LearningError),
+    /// An error in creating a pool of prediction threads.
+    ThreadPool(ThreadPoolBuildError),
+}
+
+/// Result type returned by the library.
+pub type Result<T, E = Error> = result::Result<T, E>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::NotTrained => write!(f, "Call train() first"),
+            Error::AlreadyTrained => write!(f, "Can only train once; use update() \
+                                                to add training data"),
+            Error::NotCalibrated => write!(f, "Call calibrate() on an inductive \
+                                               predictor first"),
+            Error::NotInductive => write!(f, "Can only calibrate an inductive \
+                                              predictor"),
+            Error::NoEpsilon => write!(f, "Specify epsilon to perform a standard \
+                                           predict()"),
+            Error::InvalidEpsilon(epsilon) =>
+                write!(f, "Invalid significance level {} (must be in [0, 1])", epsilon),
+            Error::InvalidLabel { label, n_labels } =>
+                write!(f, "Invalid label {} (number of labels: {})", label, n_labels),
+            Error::DimensionMismatch { expected, found } =>
+                write!(f, "Dimension mismatch: expected {}, found {}", expected, found),
+            Error::TooFewExamples { expected, found } =>
+                write!(f, "Too few examples: expected at least {}, found {}", expected, found),
+            Error::UnsupportedLabels { n_labels } =>
+                write!(f, "Unsupported number of labels: {}", n_labels),
+            Error::NaN => write!(f, "Inputs cannot contain NaN values"),
+            Error::InvalidWeight(weight) =>
+                write!(f, "Invalid likelihood ratio {} (must be finite and non-negative)",
+                       weight),
+            Error::SingularMatrix => write!(f, "Matrix is singular"),
+            Error::ZeroVariance => write!(f, "Training inputs have zero variance"),
+            Error::Unsupported(ref msg) => write!(f, "Unsupported operation: {}", msg),
+            Error::Format(ref msg) => write!(f, "Wrong format: {}", msg),
+            Error::Io(ref e) => write!(f, "I/O error: {}", e),
+            Error::Csv(ref e) => write!(f, "CSV error: {}", e),
+            Error::Json(ref e) => write!(f, "Serialization error: {}", e),
+            Error::Learning(ref e) => write!(f, "Learning error: {}", e),
+            Error::ThreadPool(ref e) => write!(f, "Thread pool error: {}", e),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io(ref e) => Some(e),
+            Error::Csv(ref e) => Some(e),
+            Error::Json(ref e) => Some(e),
+            Error::Learning(ref e) => Some(e),
+            Error::ThreadPool(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl From<csv::Error> for Error {
+    fn from(e: csv::Error) -> Error {
+        Error::Csv(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Error {
+        Error::Json(e)
+    }
+}
+
+impl From<LearningError> for Error {
+    fn from(e: LearningError) -> Error {
+        Error::Learning(e)
+    }
+}
+
+impl From<ThreadPoolBuildError> for Error {
+    fn from(e: ThreadPoolBuildError) -> Error {
+        Error::ThreadPool(e)
+    }
+}
+
+impl From<ShapeError> for Error {
+    fn from(e: ShapeError) -> Error {
+        Error::Format(e.to_string())
+    }
+}
+
+impl From<ParseIntError> for Error {
+    fn from(e: ParseIntError) -> Error {
+        Error::Format(e.to_string())
+    }
+}
+
+impl From<ParseFloatError> for Error {
+    fn from(e: ParseFloatError) -> Error {
+        Error::Format(e.to_string())
+    }
+}
+
+/// Returns an error if `inputs` and `targets` have a different
+/// number of elements.
+pub(crate) fn check_lengths(n_inputs: usize, n_targets: usize) -> Result<()> {
+    if n_inputs != n_targets {
+        return Err(Error::DimensionMismatch { expected: n_inputs, found: n_targets });
+    }
+    Ok(())
+}
+
+/// Returns an error if any label is not in the range `[0, n_labels)`.
+pub(crate) fn check_labels<'a, I>(targets: I, n_labels: usize) -> Result<()>
+        where I: IntoIterator<Item=&'a usize> {
+    match targets.into_iter().find(|y| **y >= n_labels) {
+        Some(y) => Err(Error::InvalidLabel { label: *y, n_labels: n_labels }),
+        None => Ok(()),
+    }
+}
+
+/// Returns an error if any of `values` is NaN.
+pub(crate) fn check_nan(values: &[f64]) -> Result<()> {
+    if values.iter().any(|v| v.is_nan()) {
+        return Err(Error::NaN);
+    }
+    Ok(())
+}
+
+/// Returns an error if a likelihood ratio is NaN, negative or infinite.
+pub(crate) fn check_weights(weights: &[f64]) -> Result<()> {
+    check_nan(weights)?;
+    match weights.iter().find(|w| **w < 0. || w.is_infinite()) {
+        Some(w) => Err(Error::InvalidWeight(*w)),
+        None => Ok(()),
+    }
+}
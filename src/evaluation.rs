@@ -0,0 +1,323 @@
+//! Evaluation of confidence predictors.
+//!
+//! Validity and efficiency criteria are computed from the p-values
+//! output by `predict_confidence()` (a matrix with one row per test
+//! object and one column per label), and from the true labels of the
+//! test objects.
+//! The criteria are described in ALRW and in "Criteria of efficiency
+//! for conformal prediction" (Vovk et al., 2016).
+//! As in `predict()`, the prediction set at significance level
+//! `epsilon` contains the labels whose p-value is larger than `epsilon`.
+//!
+//! # Examples
+//!
+//! ```
+//! #[macro_use(array)]
+//! extern crate ndarray;
+//! extern crate random_world;
+//!
+//! # fn main() {
+//! use random_world::evaluation::*;
+//!
+//! let pvalues = array![[0.05, 0.5],
+//!                      [0.3, 0.2],
+//!                      [0.8, 0.01]];
+//! let targets = array![1, 1, 0];
+//!
+//! let criteria = evaluate_sets(&pvalues.view(), &targets.view(), 0.1)
+//!                              .expect("Failed to evaluate");
+//! assert!(criteria.error_rate == 0.);
+//! assert!(criteria.n == 4./3.);
+//! assert!(criteria.one_c == 2./3.);
+//!
+//! let criteria = evaluate_pvalues(&pvalues.view(), &targets.view())
+//!                                 .expect("Failed to evaluate");
+//! assert!((criteria.s - 0.62).abs() < 1e-9);
+//! # }
+//! ```
+use ndarray::prelude::*;
+
+use error::{Error, Result, check_lengths, check_labels};
+
+
+/// Criteria of the prediction sets at a significance level.
+///
+/// All the criteria are averages over the test objects.
+/// If there are no test objects, they are NaN.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SetCriteria {
+    /// Significance level.
+    pub epsilon: f64,
+    /// Empirical error rate: fraction of prediction sets not
+    /// containing the true label.
+    pub error_rate: f64,
+    /// N criterion: average size of the prediction sets.
+    pub n: f64,
+    /// OneC: fraction of singleton prediction sets.
+    pub one_c: f64,
+    /// Fraction of empty prediction sets.
+    pub empty: f64,
+    /// M criterion: fraction of prediction sets with more than one
+    /// label.
+    pub m: f64,
+    /// E criterion: average excess of the size of prediction sets
+    /// over 1 (i.e., $\max(|\Gamma| - 1, 0)$).
+    pub e: f64,
+    /// Observed excess (OE): average number of false labels in the
+    /// prediction sets.
+    pub observed_excess: f64,
+}
+
+/// Criteria computed from the p-values, independently of the
+/// significance level.
+///
+/// All the criteria are averages over the test objects; the smaller,
+/// the more efficient the predictor.
+/// If there are no test objects, they are NaN.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PValueCriteria {
+    /// S criterion: average sum of the p-values.
+    pub s: f64,
+    /// U criterion (unconfidence): average second largest p-value.
+    pub u: f64,
+    /// F criterion (fuzziness): average sum of the p-values but the
+    /// largest one.
+    pub f: f64,
+    /// Observed fuzziness (OF): average sum of the p-values of the
+    /// false labels.
+    pub observed_fuzziness: f64,
+}
+
+/// Computes validity and efficiency criteria of the prediction sets
+/// at significance level `epsilon`.
+///
+/// # Arguments
+///
+/// * `pvalues` - Matrix (Array2<f64>) of p-values, with one row per test
+///               object and one column per label.
+/// * `targets` - Vector (Array1<usize>) of true labels of the test
+///               objects.
+/// * `epsilon` - Significance level in [0,1].
+///
+/// # Errors
+///
+/// - if the number of rows of `pvalues` is not consistent with the
+///   number of labels in `targets`.
+/// - if a label is not smaller than the number of columns of `pvalues`.
+/// - if `epsilon` is not in [0,1].
+pub fn evaluate_sets(pvalues: &ArrayView2<f64>, targets: &ArrayView1<usize>,
+                     epsilon: f64) -> Result<SetCriteria> {
+    if !(0. ..=1.).contains(&epsilon) {
+        return Err(Error::InvalidEpsilon(epsilon));
+    }
+    check_lengths(pvalues.rows(), targets.len())?;
+    check_labels(targets, pvalues.cols())?;
+
+    let mut errors = 0;
+    let mut size = 0;
+    let mut one_c = 0;
+    let mut empty = 0;
+    let mut multi = 0;
+    let mut excess = 0;
+    let mut observed_excess = 0;
+
+    for (p, y) in pvalues.outer_iter().zip(targets) {
+        let set_size = p.iter()
+                        .filter(|&&p_y| p_y > epsilon)
+                        .count();
+        let error = p[*y] <= epsilon;
+
+        if error {
+            errors += 1;
+        }
+        size += set_size;
+        match set_size {
+            0 => empty += 1,
+            1 => one_c += 1,
+            _ => {
+                multi += 1;
+                excess += set_size - 1;
+            },
+        }
+        observed_excess += if error { set_size } else { set_size - 1 };
+    }
+
+    let n = targets.len() as f64;
+
+    Ok(SetCriteria {
+        epsilon: epsilon,
+        error_rate: errors as f64 / n,
+        n: size as f64 / n,
+        one_c: one_c as f64 / n,
+        empty: empty as f64 / n,
+        m: multi as f64 / n,
+        e: excess as f64 / n,
+        observed_excess: observed_excess as f64 / n,
+    })
+}
+
+/// Computes efficiency criteria from p-values, independently of the
+/// significance level.
+///
+/// # Arguments
+///
+/// * `pvalues` - Matrix (Array2<f64>) of p-values, with one row per test
+///               object and one column per label.
+/// * `targets` - Vector (Array1<usize>) of true labels of the test
+///               objects.
+///
+/// # Errors
+///
+/// - if the number of rows of `pvalues` is not consistent with the
+///   number of labels in `targets`.
+/// - if a label is not smaller than the number of columns of `pvalues`.
+pub fn evaluate_pvalues(pvalues: &ArrayView2<f64>, targets: &ArrayView1<usize>)
+        -> Result<PValueCriteria> {
+    check_lengths(pvalues.rows(), targets.len())?;
+    check_labels(targets, pvalues.cols())?;
+
+    let mut s = 0.;
+    let mut u = 0.;
+    let mut f = 0.;
+    let mut observed_fuzziness = 0.;
+
+    for (p, y) in pvalues.outer_iter().zip(targets) {
+        // Largest and second largest p-values.
+        let (largest, second) = p.iter()
+                                 .fold((0., 0.), |(largest, second), &p_y| {
+                                     if p_y > largest {
+                                         (p_y, largest)
+                                     } else if p_y > second {
+                                         (largest, p_y)
+                                     } else {
+                                         (largest, second)
+                                     }
+                                 });
+        let sum = p.iter().sum::<f64>();
+
+        s += sum;
+        u += second;
+        f += sum - largest;
+        observed_fuzziness += sum - p[*y];
+    }
+
+    let n = targets.len() as f64;
+
+    Ok(PValueCriteria {
+        s: s / n,
+        u: u / n,
+        f: f / n,
+        observed_fuzziness: observed_fuzziness / n,
+    })
+}
+
+/// Computes the criteria of the prediction sets for each significance
+/// level in `epsilons`.
+///
+/// Plotting `error_rate` against `epsilon` gives the calibration
+/// curve of the predictor; for a valid predictor, the curve is close
+/// to (or below) the diagonal.
+///
+/// # Arguments
+///
+/// * `pvalues` - Matrix (Array2<f64>) of p-values, with one row per test
+///               object and one column per label.
+/// * `targets` - Vector (Array1<usize>) of true labels of the test
+///               objects.
+/// * `epsilons` - Significance levels in [0,1].
+///
+/// # Examples
+///
+/// ```
+/// #[macro_use(array)]
+/// extern crate ndarray;
+/// extern crate random_world;
+///
+/// # fn main() {
+/// use random_world::evaluation::*;
+///
+/// let pvalues = array![[0.05, 0.5],
+///                      [0.3, 0.2],
+///                      [0.8, 0.01]];
+/// let targets = array![1, 1, 0];
+///
+/// let curve = calibration_curve(&pvalues.view(), &targets.view(),
+///                               &epsilon_grid(4))
+///                               .expect("Failed to evaluate");
+/// let error_rates = curve.iter()
+///                        .map(|c| c.error_rate)
+///                        .collect::<Vec<_>>();
+/// assert!(error_rates == vec![0., 1./3., 2./3., 2./3., 1.]);
+/// # }
+/// ```
+///
+/// # Errors
+///
+/// - as `evaluate_sets()`, for any of `epsilons`.
+pub fn calibration_curve(pvalues: &ArrayView2<f64>, targets: &ArrayView1<usize>,
+                         epsilons: &[f64]) -> Result<Vec<SetCriteria>> {
+    epsilons.iter()
+            .map(|epsilon| evaluate_sets(pvalues, targets, *epsilon))
+            .collect()
+}
+
+/// Returns `n_steps + 1` evenly spaced significance levels
+/// `0, 1/n_steps, ..., 1`.
+///
+/// # Arguments
+///
+/// * `n_steps` - Number of intervals the range [0,1] is split into.
+pub fn epsilon_grid(n_steps: usize) -> Vec<f64> {
+    assert!(n_steps > 0);
+
+    (0..n_steps + 1).map(|i| i as f64 / n_steps as f64)
+                    .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Verify set criteria on a small example, also when prediction
+    /// sets are empty or contain false labels.
+    #[test]
+    fn set_criteria() {
+        let pvalues = array![[0.05, 0.5, 0.3],
+                             [0.3, 0.2, 0.4],
+                             [0.01, 0.02, 0.08],
+                             [0.8, 0.01, 0.]];
+        let targets = array![1, 0, 2, 2];
+
+        let criteria = evaluate_sets(&pvalues.view(), &targets.view(), 0.1).unwrap();
+
+        assert_relative_eq!(criteria.error_rate, 0.5);
+        assert_relative_eq!(criteria.n, 6./4.);
+        assert_relative_eq!(criteria.one_c, 0.25);
+        assert_relative_eq!(criteria.empty, 0.25);
+        assert_relative_eq!(criteria.m, 0.5);
+        assert_relative_eq!(criteria.e, 3./4.);
+        // Sets: {1,2}, {0,1,2}, {}, {0}.
+        assert_relative_eq!(criteria.observed_excess, (1. + 2. + 0. + 1.) / 4.);
+
+        match evaluate_sets(&pvalues.view(), &targets.view(), 1.5) {
+            Err(Error::InvalidEpsilon(_)) => {},
+            r => panic!("Unexpected result: {:?}", r),
+        }
+    }
+
+    /// Verify p-value criteria on a small example.
+    #[test]
+    fn pvalue_criteria() {
+        let pvalues = array![[0.05, 0.5, 0.3],
+                             [0.3, 0.2, 0.4]];
+        let targets = array![1, 2];
+
+        let criteria = evaluate_pvalues(&pvalues.view(), &targets.view()).unwrap();
+
+        assert_relative_eq!(criteria.s, (0.85 + 0.9) / 2.);
+        assert_relative_eq!(criteria.u, (0.3 + 0.3) / 2.);
+        assert_relative_eq!(criteria.f, (0.35 + 0.5) / 2.);
+        assert_relative_eq!(criteria.observed_fuzziness, (0.35 + 0.5) / 2.);
+    }
+}
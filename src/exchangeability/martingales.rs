@@ -2,6 +2,20 @@
 use std::f64;
 use quadrature::integrate;
 use statrs::statistics::Variance;
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::ser::Error;
+
+/// Type of martingale, determining its update function.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+enum MartingaleKind {
+    /// Power martingale with parameter epsilon.
+    Power(f64),
+    /// Plug-in martingale with (optional) KDE bandwidth.
+    Plugin(Option<f64>),
+    /// Martingale with a custom update function.
+    Custom,
+}
+
 /// Exchangeability Martingale.
 ///
 /// A generic exchangeability martingale, as described for example
@@ -10,7 +24,14 @@ use statrs::statistics::Variance;
 /// [1] "Testing Exchangeability On-Line" (Vovk et al., 2003).
 /// [2] "Plug-in martingales for testing exchangeability on-line"
 ///     (Fedorova et al., 2012).
+///
+/// Power and Plug-in martingales can be stored and loaded with
+/// `utils::save_model()` and `utils::load_model()`, so as to continue
+/// testing from their current state; martingales with a custom
+/// update function cannot be stored.
 pub struct Martingale {
+    /// Type of martingale.
+    kind: MartingaleKind,
     /// Current value of the martingale.
     current: f64,
     /// Threshold to determine if the martingale is "large".
@@ -36,6 +57,7 @@ impl Default for Martingale {
     /// `Martingale::from_function()` constructor.
     fn default() -> Martingale {
         Martingale {
+            kind: MartingaleKind::Custom,
             current: 1.0,
             threshold: 100.0,
             pvalues: None,
@@ -65,6 +87,7 @@ impl Martingale {
         assert!(epsilon >= 0.0 && epsilon <= 1.0);
 
         Martingale {
+            kind: MartingaleKind::Power(epsilon),
             update_function: Box::new(move |pvalue, _| {
                                         epsilon*pvalue.powf(epsilon-1.0)
                                     }),
@@ -99,6 +122,7 @@ impl Martingale {
     /// ```
     pub fn new_plugin(bandwidth: Option<f64>) -> Martingale {
         Martingale {
+            kind: MartingaleKind::Plugin(bandwidth),
             pvalues: Some(vec![]),
             update_function: Box::new(move |pvalue, pvalues| {
                                        plugin_update(pvalue,
@@ -212,6 +236,53 @@ impl Martingale {
     }
 }
 
+/// Serialized representation of a martingale.
+#[derive(Serialize, Deserialize)]
+struct MartingaleState {
+    kind: MartingaleKind,
+    current: f64,
+    threshold: f64,
+    pvalues: Option<Vec<f64>>,
+}
+
+impl Serialize for Martingale {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if let MartingaleKind::Custom = self.kind {
+            return Err(S::Error::custom("Cannot store a martingale with a custom \
+                                         update function"));
+        }
+
+        MartingaleState {
+            kind: self.kind,
+            current: self.current,
+            threshold: self.threshold,
+            pvalues: self.pvalues.clone(),
+        }.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Martingale {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Martingale, D::Error> {
+        let state = MartingaleState::deserialize(deserializer)?;
+
+        // Rebuild the update function.
+        let mut martingale = match state.kind {
+            MartingaleKind::Power(epsilon) => Martingale::new_power(epsilon),
+            MartingaleKind::Plugin(bandwidth) => Martingale::new_plugin(bandwidth),
+            MartingaleKind::Custom => {
+                use serde::de::Error;
+                return Err(D::Error::custom("Cannot load a martingale with a custom \
+                                             update function"));
+            },
+        };
+        martingale.current = state.current;
+        martingale.threshold = state.threshold;
+        martingale.pvalues = state.pvalues;
+
+        Ok(martingale)
+    }
+}
+
 /// Update function for Plug-in martingales.
 ///
 /// As done in (Fedorova et al., 2012), the betting function is
@@ -276,6 +347,7 @@ fn kde(x: f64, x_previous: &[f64], bandwidth: Option<f64>) -> f64 {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use serde_json;
 
     /// Verify KDE with rule of thumb for bandwidth.
     #[test]
@@ -304,4 +376,30 @@ mod tests {
 
         assert_relative_eq!(update, 1.398942285770281);
     }
+
+    /// Verify that a stored and loaded Plug-in martingale continues
+    /// from the same state.
+    #[test]
+    fn serialize_plugin() {
+        let pvalues = [0.2, 0.6, 0.5, 0.8, 0.1];
+        let mut m = Martingale::new_plugin(Some(0.1));
+        for p in pvalues.iter() {
+            m.update(*p);
+        }
+
+        let json = serde_json::to_string(&m).unwrap();
+        let mut m_loaded: Martingale = serde_json::from_str(&json).unwrap();
+
+        assert!(m_loaded.current() == m.current());
+        assert!(m_loaded.update(0.3) == m.update(0.3));
+    }
+
+    /// Verify that martingales with a custom update function cannot
+    /// be stored.
+    #[test]
+    fn serialize_custom() {
+        let m = Martingale::from_function(Box::new(|_, _| 1.), false);
+
+        assert!(serde_json::to_string(&m).is_err());
+    }
 }
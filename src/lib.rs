@@ -70,10 +70,20 @@ extern crate csv;
 extern crate statrs;
 extern crate quadrature;
 extern crate lazysort;
+extern crate rayon;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate npyz;
 #[macro_use]
 extern crate approx;
 
+pub mod error;
 pub mod cp;
 pub mod ncm;
 pub mod utils;
-pub mod exchangeability;
\ No newline at end of file
+pub mod exchangeability;
+pub mod venn;
+pub mod evaluation;
+pub mod anomaly;
\ No newline at end of file
@@ -0,0 +1,469 @@
+//! Adaptive prediction sets (APS) and their regularized version (RAPS).
+use std::sync::Mutex;
+use pcg_rand::Pcg32;
+use rand::{Rng, SeedableRng};
+use ndarray::prelude::*;
+
+use error::{Error, Result, check_lengths, check_labels, check_nan};
+use ncm::{NonconformityScorer, SortedCalibration, visit_inputs};
+use ncm::knn::split_inputs;
+
+
+/// PRNG stream of randomized scores, so that they do not reuse the
+/// random numbers of a smooth CP with the same seed.
+const STREAM: u64 = 0x5241_5053;
+
+/// Regularized Adaptive Prediction Sets (RAPS) nonconformity measure,
+/// which includes Adaptive Prediction Sets (APS) as a special case.
+///
+/// Objects are vectors of label probabilities (e.g., the softmax
+/// outputs of a classifier). The score of label $y$ is the total
+/// probability of the labels ranked before $y$ (by decreasing
+/// probability, breaking ties by label), plus $u p_y$, plus the
+/// penalty $\lambda (o(y) - k_{reg})^+$, where $o(y)$ is the rank of
+/// $y$ (starting from 1).
+/// For randomized scores, $u$ is uniform in [0, 1), and it is the same
+/// for all the labels of an object; otherwise, $u = 1$.
+/// With $\lambda = 0$, the scores are those of APS.
+///
+/// Randomized scores draw $u$ from a seeded PRNG, once per example:
+/// calibration examples when they are added, and test objects when a
+/// CP predicts them (see `NonconformityScorer::test_draw()`), in the
+/// order of the test objects, whatever the number of prediction
+/// threads. Calling `score()` directly draws a new number each time.
+///
+/// As scores do not depend on other examples, this measure can only
+/// be used by an ICP: `train()` only sets the number of labels (it is
+/// typically called with no examples).
+///
+/// # Examples
+///
+/// ```
+/// #[macro_use(array)]
+/// extern crate ndarray;
+/// extern crate random_world;
+///
+/// # fn main() {
+/// use ndarray::prelude::*;
+/// use random_world::cp::*;
+/// use random_world::ncm::*;
+///
+/// let ncm = RAPS::new(1, 0.5, None).expect("Invalid parameters");
+/// let mut cp = CP::new_inductive(ncm, 3, Some(0.3));
+/// let calibration_inputs = array![[0.7, 0.2, 0.1],
+///                                 [0.6, 0.3, 0.1],
+///                                 [0.1, 0.8, 0.1],
+///                                 [0.2, 0.5, 0.3],
+///                                 [0.3, 0.3, 0.4],
+///                                 [0.1, 0.1, 0.8]];
+/// let calibration_targets = array![0, 0, 1, 2, 2, 2];
+///
+/// cp.train(&Array2::zeros((0, 3)).view(), &Array1::zeros(0).view())
+///   .expect("Failed to train");
+/// cp.calibrate(&calibration_inputs.view(), &calibration_targets.view())
+///   .expect("Failed to calibrate");
+/// let preds = cp.predict(&array![[0.5, 0.4, 0.1]].view())
+///               .expect("Failed to predict");
+/// assert!(preds == array![[true, true, false]]);
+/// # }
+/// ```
+pub struct RAPS {
+    k_reg: usize,
+    lambda: f64,
+    // PRNG of randomized scores, or None for deterministic ones.
+    rng: Option<Mutex<Pcg32>>,
+    n_labels: Option<usize>,
+    // Probability vectors of the calibration examples, and their scores.
+    calibration: Option<SortedCalibration<f64>>,
+}
+
+impl RAPS {
+    /// Constructs a RAPS nonconformity measure.
+    ///
+    /// # Arguments
+    ///
+    /// * `k_reg` - Number of top-ranked labels which are not penalized.
+    /// * `lambda` - Penalty for each further label (non-negative).
+    /// * `seed` - Seed for randomized scores, or None for deterministic
+    ///            scores.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use random_world::ncm::*;
+    ///
+    /// let ncm = RAPS::new(2, 0.01, Some(0)).expect("Invalid parameters");
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - if `lambda` is negative or NaN.
+    pub fn new(k_reg: usize, lambda: f64, seed: Option<u64>) -> Result<RAPS> {
+        if lambda.is_nan() || lambda < 0. {
+            return Err(Error::Format(format!("lambda must be non-negative, got {}",
+                                             lambda)));
+        }
+
+        Ok(RAPS::build(k_reg, lambda, seed))
+    }
+
+    /// Constructs an APS nonconformity measure, i.e., RAPS without
+    /// penalty.
+    ///
+    /// # Arguments
+    ///
+    /// * `seed` - Seed for randomized scores, or None for deterministic
+    ///            scores.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use random_world::ncm::*;
+    ///
+    /// let ncm = RAPS::new_aps(Some(0));
+    /// ```
+    pub fn new_aps(seed: Option<u64>) -> RAPS {
+        RAPS::build(0, 0., seed)
+    }
+
+    /// Constructs a RAPS nonconformity measure with valid parameters.
+    fn build(k_reg: usize, lambda: f64, seed: Option<u64>) -> RAPS {
+        RAPS {
+            k_reg: k_reg,
+            lambda: lambda,
+            rng: seed.map(|seed| Mutex::new(Pcg32::from_seed([seed, STREAM]))),
+            n_labels: None,
+            calibration: None,
+        }
+    }
+
+    /// Draws the random number $u$ of a new example, or returns 1
+    /// for deterministic scores.
+    fn draw(&self) -> f64 {
+        match self.rng {
+            Some(ref rng) => rng.lock()
+                                .expect("Poisoned PRNG lock")
+                                .gen::<f64>(),
+            None => 1.,
+        }
+    }
+
+    /// Computes the RAPS score of label `y` for probability vector `x`,
+    /// given the random number `u`.
+    fn raps_score(&self, x: &ArrayView1<f64>, y: usize, u: f64) -> Result<f64> {
+        let n_labels = self.n_labels.ok_or(Error::NotTrained)?;
+        if y >= n_labels {
+            return Err(Error::InvalidLabel { label: y, n_labels: n_labels });
+        }
+        self.check_dimension(x.len())?;
+        check_nan(&x.to_vec())?;
+
+        // Labels ranked before y.
+        let p_y = x[y];
+        let (rank, before) = x.iter()
+                              .enumerate()
+                              .filter(|&(j, &p)| p > p_y || (p == p_y && j < y))
+                              .fold((1usize, 0.), |(rank, before), (_, &p)| {
+                                  (rank + 1, before + p)
+                              });
+        let penalty = self.lambda * rank.saturating_sub(self.k_reg) as f64;
+
+        Ok(before + u * p_y + penalty)
+    }
+
+    /// Returns an error if probability vectors do not have one
+    /// element per label.
+    fn check_dimension(&self, len: usize) -> Result<()> {
+        let n_labels = self.n_labels.ok_or(Error::NotTrained)?;
+        if len != n_labels {
+            return Err(Error::DimensionMismatch { expected: n_labels, found: len });
+        }
+
+        Ok(())
+    }
+}
+
+impl NonconformityScorer<f64> for RAPS {
+    /// Sets the number of labels.
+    ///
+    /// Training examples are not used, but they should be probability
+    /// vectors, as any other input.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<f64>) of probability vectors.
+    /// * `targets` - Vector (Array1<usize>) of labels corresponding to the
+    ///               probability vectors.
+    /// * `n_labels` - Number of unique labels in the classification problem.
+    fn train(&mut self, inputs: &ArrayView2<f64>, targets: &ArrayView1<usize>,
+             n_labels: usize) -> Result<()> {
+        if self.n_labels.is_some() {
+            return Err(Error::AlreadyTrained);
+        }
+        check_lengths(inputs.rows(), targets.len())?;
+        check_labels(targets, n_labels)?;
+        self.n_labels = Some(n_labels);
+        self.check_dimension(inputs.cols())
+    }
+
+    /// Computes the scores of the calibration examples.
+    ///
+    /// For randomized scores, a random number is drawn for each
+    /// calibration example.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<f64>) of probability vectors of the
+    ///              calibration examples.
+    /// * `targets` - Vector (Array1<usize>) of labels corresponding to the
+    ///               probability vectors.
+    fn calibrate(&mut self, inputs: &ArrayView2<f64>, targets: &ArrayView1<usize>)
+             -> Result<()> {
+        let n_labels = self.n_labels.ok_or(Error::NotTrained)?;
+        self.check_dimension(inputs.cols())?;
+        let calibration_inputs = split_inputs(inputs, targets, n_labels)?;
+        self.calibration = Some(SortedCalibration::new(calibration_inputs, |x, y| {
+                                    self.raps_score(x, y, self.draw())
+                                })?);
+
+        Ok(())
+    }
+
+    /// Only checks the probability vectors: the score of an object
+    /// only depends on its own label probabilities.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<f64>) of probability vectors.
+    /// * `targets` - Vector (Array1<usize>) of labels corresponding to the
+    ///               probability vectors.
+    fn update(&mut self, inputs: &ArrayView2<f64>, targets: &ArrayView1<usize>)
+        -> Result<()> {
+
+        let n_labels = self.n_labels.ok_or(Error::NotTrained)?;
+        self.check_dimension(inputs.cols())?;
+        check_lengths(inputs.rows(), targets.len())?;
+        check_labels(targets, n_labels)
+    }
+
+    /// Adds calibration examples, drawing a random number for each
+    /// of them if scores are randomized.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<f64>) of probability vectors of the
+    ///              calibration examples.
+    /// * `targets` - Vector (Array1<usize>) of labels corresponding to the
+    ///               probability vectors.
+    fn update_calibration(&mut self, inputs: &ArrayView2<f64>, targets: &ArrayView1<usize>)
+        -> Result<()> {
+
+        self.check_dimension(inputs.cols())?;
+        let mut calibration = self.calibration.take()
+                                              .ok_or(Error::NotCalibrated)?;
+        let extended = calibration.extend(inputs, targets, |x, y| self.raps_score(x, y, self.draw()));
+        self.calibration = Some(calibration);
+
+        extended
+    }
+
+    /// Returns the score of a test example, followed by the scores of
+    /// the calibration examples with label `y`.
+    ///
+    /// # Arguments
+    ///
+    /// * `x` - Probability vector of the test object.
+    /// * `y` - (Candidate) label for the test object.
+    ///
+    /// # Errors
+    ///
+    /// - if the scorer was not calibrated.
+    fn scores(&self, x: &ArrayView1<f64>, y: usize) -> Result<Vec<f64>> {
+        self.scores_with_draw(x, y, None)
+    }
+
+    /// Computes the RAPS score of label `y` for probability vector `x`
+    /// of a test object.
+    ///
+    /// # Arguments
+    ///
+    /// * `x` - Probability vector of the object.
+    /// * `y` - (Candidate) label for the object.
+    fn score(&self, x: &ArrayView1<f64>, y: usize) -> Result<f64> {
+        self.score_with_draw(x, y, None)
+    }
+
+    /// Draws the random number $u$ of a test object, or returns None
+    /// for deterministic scores.
+    fn test_draw(&self) -> Option<f64> {
+        self.rng.as_ref()
+                .map(|_| self.draw())
+    }
+
+    /// Computes the RAPS score of label `y` for probability vector `x`
+    /// of a test object, given its random number `u`.
+    ///
+    /// # Arguments
+    ///
+    /// * `x` - Probability vector of the object.
+    /// * `y` - (Candidate) label for the object.
+    /// * `u` - Random number returned by `test_draw()`; if None, a new
+    ///         one is drawn.
+    fn score_with_draw(&self, x: &ArrayView1<f64>, y: usize, u: Option<f64>) -> Result<f64> {
+        self.raps_score(x, y, u.unwrap_or_else(|| self.draw()))
+    }
+
+    /// Returns the score of a test example, given its random number
+    /// `u`, followed by the scores of the calibration examples with
+    /// label `y`.
+    ///
+    /// # Arguments
+    ///
+    /// * `x` - Probability vector of the test object.
+    /// * `y` - (Candidate) label for the test object.
+    /// * `u` - Random number returned by `test_draw()`; if None, a new
+    ///         one is drawn.
+    fn scores_with_draw(&self, x: &ArrayView1<f64>, y: usize, u: Option<f64>)
+                        -> Result<Vec<f64>> {
+        let calibration = self.calibration.as_ref()
+                                          .ok_or(Error::NotCalibrated)?;
+
+        calibration.with_score(self.score_with_draw(x, y, u)?, y)
+    }
+
+    /// Returns the scores of the calibration examples with label `y`,
+    /// sorted in ascending order.
+    ///
+    /// Returns None if the scorer was not calibrated.
+    ///
+    /// # Arguments
+    ///
+    /// * `y` - (Candidate) label for the test object.
+    fn calibration_scores(&self, y: usize) -> Option<&[f64]> {
+        self.calibration.as_ref()
+                        .and_then(|calibration| calibration.scores(y))
+    }
+
+    /// Visits the calibration examples with label `y`.
+    ///
+    /// # Arguments
+    ///
+    /// * `y` - (Candidate) label for the test object.
+    /// * `visit` - Function called on each example.
+    fn visit_examples(&self, y: usize, visit: &mut dyn FnMut(&ArrayView1<f64>, usize))
+                      -> Result<()> {
+        let calibration = self.calibration.as_ref()
+                                          .ok_or(Error::NotCalibrated)?;
+
+        visit_inputs(calibration.inputs(), y, visit)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cp::{ConfidencePredictor, CP};
+
+    /// Verify APS and RAPS scores, deterministic and randomized.
+    #[test]
+    fn scores() {
+        let x = array![0.2, 0.5, 0.3];
+
+        let mut aps = RAPS::new_aps(None);
+        aps.train(&Array2::zeros((0, 3)).view(), &Array1::zeros(0).view(), 3).unwrap();
+        assert_relative_eq!(aps.score(&x.view(), 1).unwrap(), 0.5);
+        assert_relative_eq!(aps.score(&x.view(), 2).unwrap(), 0.8);
+        assert_relative_eq!(aps.score(&x.view(), 0).unwrap(), 1.);
+        // Ties are broken by label.
+        assert_relative_eq!(aps.score(&array![0.4, 0.4, 0.2].view(), 1).unwrap(), 0.8);
+        assert!(aps.test_draw().is_none());
+
+        let mut raps = RAPS::new(1, 0.1, None).unwrap();
+        raps.train(&Array2::zeros((0, 3)).view(), &Array1::zeros(0).view(), 3).unwrap();
+        assert_relative_eq!(raps.score(&x.view(), 1).unwrap(), 0.5);
+        assert_relative_eq!(raps.score(&x.view(), 2).unwrap(), 0.9);
+        assert_relative_eq!(raps.score(&x.view(), 0).unwrap(), 1.2);
+
+        match raps.score(&array![0.5, 0.5].view(), 0) {
+            Err(Error::DimensionMismatch { expected: 3, found: 2 }) => {},
+            r => panic!("Unexpected result: {:?}", r),
+        }
+        match RAPS::new(1, -0.1, None) {
+            Err(Error::Format(_)) => {},
+            _ => panic!("Negative lambda accepted"),
+        }
+    }
+
+    /// Verify that randomized scores draw one number per calibration
+    /// example, and one per test object shared by all its labels.
+    #[test]
+    fn randomized() {
+        let mut rng = Pcg32::from_seed([42, STREAM]);
+        let u = rng.gen_iter::<f64>().take(6).collect::<Vec<_>>();
+
+        let mut raps = RAPS::new(1, 0.1, Some(42)).unwrap();
+        raps.train(&Array2::zeros((0, 3)).view(), &Array1::zeros(0).view(), 3).unwrap();
+        raps.calibrate(&array![[0.2, 0.5, 0.3], [0.6, 0.3, 0.1]].view(),
+                       &array![1, 1].view()).unwrap();
+        let mut expected = [u[0] * 0.5, 0.6 + u[1] * 0.3 + 0.1];
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert!(raps.calibration_scores(1).unwrap() == &expected[..]);
+        raps.update_calibration(&array![[0.1, 0.1, 0.8]].view(), &array![2].view()).unwrap();
+        assert!(raps.calibration_scores(2).unwrap() == &[u[2] * 0.8][..]);
+
+        // The next test object draws u[3] for all its labels, and an
+        // identical object draws u[4].
+        let x = array![0.2, 0.5, 0.3];
+        let draw = raps.test_draw();
+        assert!(draw == Some(u[3]));
+        assert_relative_eq!(raps.score_with_draw(&x.view(), 2, draw).unwrap(),
+                            0.5 + u[3] * 0.3 + 0.1);
+        assert_relative_eq!(raps.score_with_draw(&x.view(), 1, draw).unwrap(), u[3] * 0.5);
+        assert_relative_eq!(raps.score_with_draw(&x.view(), 0, draw).unwrap(),
+                            0.8 + u[3] * 0.2 + 0.2);
+        assert!(raps.test_draw() == Some(u[4]));
+        // Without a draw, a new number is drawn.
+        assert_relative_eq!(raps.score(&x.view(), 1).unwrap(), u[5] * 0.5);
+    }
+
+    /// Verify that an ICP draws one number per test object, in order,
+    /// with serial and parallel prediction.
+    #[test]
+    fn randomized_cp() {
+        let calibration_inputs = array![[0.7, 0.2, 0.1],
+                                        [0.6, 0.3, 0.1],
+                                        [0.1, 0.8, 0.1],
+                                        [0.2, 0.5, 0.3],
+                                        [0.3, 0.3, 0.4],
+                                        [0.1, 0.1, 0.8]];
+        let calibration_targets = array![0, 0, 1, 2, 2, 2];
+        // Identical test objects.
+        let inputs = array![[0.5, 0.4, 0.1],
+                            [0.5, 0.4, 0.1],
+                            [0.5, 0.4, 0.1],
+                            [0.5, 0.4, 0.1]];
+
+        // The calibration examples draw the first 6 numbers.
+        let mut rng = Pcg32::from_seed([7, STREAM]);
+        let u = rng.gen_iter::<f64>().skip(6).take(4).collect::<Vec<_>>();
+        let mut raps = RAPS::new(1, 0.5, Some(7)).unwrap();
+        raps.train(&Array2::zeros((0, 3)).view(), &Array1::zeros(0).view(), 3).unwrap();
+        raps.calibrate(&calibration_inputs.view(), &calibration_targets.view()).unwrap();
+        let expected = Array2::from_shape_fn((4, 3), |(i, y)| {
+            let score = raps.raps_score(&inputs.row(i), y, u[i]).unwrap();
+            let scores = raps.calibration_scores(y).unwrap();
+            let ge = scores.iter().filter(|s| **s >= score).count();
+            (ge as f64 + 1.) / (scores.len() as f64 + 1.)
+        });
+
+        for &n_threads in &[1, 4] {
+            let ncm = RAPS::new(1, 0.5, Some(7)).unwrap();
+            let mut cp = CP::new_inductive(ncm, 3, None);
+            cp.set_threads(n_threads).unwrap();
+            cp.train(&Array2::zeros((0, 3)).view(), &Array1::zeros(0).view()).unwrap();
+            cp.calibrate(&calibration_inputs.view(), &calibration_targets.view()).unwrap();
+            assert!(cp.predict_confidence(&inputs.view()).unwrap() == expected);
+        }
+    }
+}
@@ -0,0 +1,574 @@
+//! Nonconformity measures from rusty-machine classifiers.
+use std::f64;
+use std::sync::OnceLock;
+use ndarray::prelude::*;
+use rusty_machine::learning::SupModel;
+use rusty_machine::learning::logistic_reg::{LogisticRegressor, BaseLogisticRegressor};
+use rusty_machine::learning::naive_bayes::{NaiveBayes, Distribution};
+use rusty_machine::learning::nnet::{NeuralNet, BaseNeuralNet, Criterion};
+use rusty_machine::learning::optim::OptimAlgorithm;
+use rusty_machine::learning::svm::SVM;
+use rusty_machine::learning::toolkit::kernel::Kernel;
+use rusty_machine::linalg::{Matrix, Vector, BaseMatrix};
+
+use error::{Error, Result, check_lengths, check_labels, check_nan};
+use ncm::{NonconformityScorer, SortedCalibration, check_dimension, inputs_y, visit_inputs};
+use ncm::knn::split_inputs;
+use ncm::residual::to_matrix;
+
+
+/// A classifier whose outputs define nonconformity scores.
+///
+/// It is implemented for rusty-machine's `LogisticRegressor` and
+/// `SVM` (binary classification), and for `NaiveBayes` and
+/// `NeuralNet` (multi-class classification).
+pub trait ScoringClassifier {
+    /// Trains the classifier.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix of training vectors.
+    /// * `targets` - Labels of the training vectors, in `[0, n_labels)`.
+    /// * `n_labels` - Number of labels.
+    fn train_labels(&mut self, inputs: &Matrix<f64>, targets: &[usize], n_labels: usize)
+                    -> Result<()>;
+    /// Returns the nonconformity scores of inputs, with one row per
+    /// input and one column per (candidate) label.
+    ///
+    /// The smaller the score of a label, the more the classifier
+    /// considers it likely.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix of vectors.
+    /// * `n_labels` - Number of labels.
+    fn label_scores(&self, inputs: &Matrix<f64>, n_labels: usize) -> Result<Array2<f64>>;
+}
+
+/// Returns an error unless the classification problem is binary.
+fn check_binary(n_labels: usize) -> Result<()> {
+    if n_labels != 2 {
+        return Err(Error::UnsupportedLabels { n_labels: n_labels });
+    }
+    Ok(())
+}
+
+/// Returns the one-hot encoding of `targets`.
+fn one_hot(targets: &[usize], n_labels: usize) -> Matrix<f64> {
+    let mut data = vec![0.; targets.len() * n_labels];
+    for (i, y) in targets.iter().enumerate() {
+        data[i * n_labels + y] = 1.;
+    }
+    Matrix::new(targets.len(), n_labels, data)
+}
+
+/// Returns the scores of binary classifiers, given their predictions
+/// `preds` and the prediction `low` (respectively, `high`) which
+/// corresponds to label 0 (label 1).
+///
+/// The score of a label is the distance between the prediction and
+/// the value corresponding to that label, scaled to [0, 1].
+fn binary_scores(preds: &Vector<f64>, low: f64, high: f64) -> Array2<f64> {
+    let mut scores = Array2::zeros((preds.size(), 2));
+    for (mut s, p) in scores.outer_iter_mut().zip(preds.iter()) {
+        s[0] = (p - low).abs() / (high - low);
+        s[1] = (high - p).abs() / (high - low);
+    }
+    scores
+}
+
+impl<A> ScoringClassifier for LogisticRegressor<A>
+        where A: OptimAlgorithm<BaseLogisticRegressor> {
+    /// Trains the logistic regressor to predict the probability of
+    /// label 1 (binary classification only).
+    fn train_labels(&mut self, inputs: &Matrix<f64>, targets: &[usize], n_labels: usize)
+                    -> Result<()> {
+        check_binary(n_labels)?;
+        let targets = Vector::new(targets.iter()
+                                         .map(|&y| y as f64)
+                                         .collect::<Vec<_>>());
+        self.train(inputs, &targets)?;
+
+        Ok(())
+    }
+
+    /// The score of label 1 is one minus its predicted probability
+    /// $p$, and that of label 0 is $p$.
+    fn label_scores(&self, inputs: &Matrix<f64>, n_labels: usize) -> Result<Array2<f64>> {
+        check_binary(n_labels)?;
+        Ok(binary_scores(&self.predict(inputs)?, 0., 1.))
+    }
+}
+
+impl<K: Kernel> ScoringClassifier for SVM<K> {
+    /// Trains the SVM, with targets -1 for label 0 and +1 for label 1
+    /// (binary classification only).
+    fn train_labels(&mut self, inputs: &Matrix<f64>, targets: &[usize], n_labels: usize)
+                    -> Result<()> {
+        check_binary(n_labels)?;
+        let targets = Vector::new(targets.iter()
+                                         .map(|&y| if y == 1 { 1. } else { -1. })
+                                         .collect::<Vec<_>>());
+        self.train(inputs, &targets)?;
+
+        Ok(())
+    }
+
+    /// The score of a label is 0 if the SVM predicts it, 1 otherwise.
+    fn label_scores(&self, inputs: &Matrix<f64>, n_labels: usize) -> Result<Array2<f64>> {
+        check_binary(n_labels)?;
+        Ok(binary_scores(&self.predict(inputs)?, -1., 1.))
+    }
+}
+
+impl<T: Distribution> ScoringClassifier for NaiveBayes<T> {
+    /// Trains the Naive Bayes classifier.
+    fn train_labels(&mut self, inputs: &Matrix<f64>, targets: &[usize], n_labels: usize)
+                    -> Result<()> {
+        self.train(inputs, &one_hot(targets, n_labels))?;
+
+        Ok(())
+    }
+
+    /// The score of a label is its negative posterior probability.
+    fn label_scores(&self, inputs: &Matrix<f64>, n_labels: usize) -> Result<Array2<f64>> {
+        let log_probs = self.get_log_probs(inputs)?;
+        if log_probs.cols() != n_labels {
+            return Err(Error::DimensionMismatch { expected: n_labels,
+                                                  found: log_probs.cols() });
+        }
+
+        let mut scores = Array2::zeros((log_probs.rows(), n_labels));
+        for (mut s, row) in scores.outer_iter_mut().zip(log_probs.data().chunks(n_labels)) {
+            // Normalize the joint log-likelihoods.
+            let max = row.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+            let total = row.iter().map(|l| (l - max).exp()).sum::<f64>();
+            for (s_y, l) in s.iter_mut().zip(row.iter()) {
+                *s_y = -(l - max).exp() / total;
+            }
+        }
+
+        Ok(scores)
+    }
+}
+
+impl<'a, T, A> ScoringClassifier for NeuralNet<'a, T, A>
+        where T: Criterion, A: OptimAlgorithm<BaseNeuralNet<'a, T>> {
+    /// Trains the neural network on one-hot encoded labels; its last
+    /// layer should have `n_labels` units.
+    fn train_labels(&mut self, inputs: &Matrix<f64>, targets: &[usize], n_labels: usize)
+                    -> Result<()> {
+        self.train(inputs, &one_hot(targets, n_labels))?;
+
+        Ok(())
+    }
+
+    /// The score of a label is the negative output of its unit.
+    fn label_scores(&self, inputs: &Matrix<f64>, n_labels: usize) -> Result<Array2<f64>> {
+        let outputs = self.predict(inputs)?;
+        if outputs.cols() != n_labels {
+            return Err(Error::DimensionMismatch { expected: n_labels,
+                                                  found: outputs.cols() });
+        }
+
+        Ok(Array::from_shape_vec((outputs.rows(), n_labels), outputs.into_vec())?
+                 .mapv(|o| -o))
+    }
+}
+
+/// A nonconformity measure defined by a scoring classifier, such as
+/// rusty-machine's logistic regression, SVM, Naive Bayes or neural
+/// networks.
+///
+/// In the inductive setting (ICP), the classifier is trained once on
+/// the proper training set, and the score of an example $(x, y)$ is
+/// the score the classifier assigns to label $y$ for object $x$.
+/// In the transductive setting, for each test object $x$ and candidate
+/// label $y$, a new classifier is trained on the training examples
+/// and $(x, y)$, and used to score all of them; this requires training
+/// `n_labels` classifiers per test object.
+///
+/// Classifiers are constructed by calling `new_model()`.
+///
+/// # Examples
+///
+/// ```
+/// #[macro_use(array)]
+/// extern crate ndarray;
+/// extern crate rusty_machine;
+/// extern crate random_world;
+///
+/// # fn main() {
+/// use rusty_machine::learning::logistic_reg::LogisticRegressor;
+/// use random_world::cp::*;
+/// use random_world::ncm::*;
+///
+/// let ncm = ClassifierScorer::new(LogisticRegressor::default);
+/// let mut cp = CP::new_inductive(ncm, 2, Some(0.4));
+/// let train_inputs = array![[0.], [1.], [2.], [8.], [9.], [10.]];
+/// let train_targets = array![0, 0, 0, 1, 1, 1];
+/// let calibration_inputs = array![[1.5], [0.5], [9.5], [8.5]];
+/// let calibration_targets = array![0, 0, 1, 1];
+///
+/// cp.train(&train_inputs.view(), &train_targets.view())
+///   .expect("Failed to train");
+/// cp.calibrate(&calibration_inputs.view(), &calibration_targets.view())
+///   .expect("Failed to calibrate");
+/// let preds = cp.predict(&array![[0.2], [9.8]].view())
+///               .expect("Failed to predict");
+/// assert!(preds == array![[true, false],
+///                         [false, true]]);
+/// # }
+/// ```
+pub struct ClassifierScorer<M, F: Fn() -> M> {
+    new_model: F,
+    n_labels: Option<usize>,
+    // train_inputs[y] contains all training inputs with label y.
+    train_inputs: Option<Vec<Array2<f64>>>,
+    // Classifier trained on train_inputs. It is trained when first
+    // needed, and discarded by update().
+    model: OnceLock<M>,
+    // Calibration examples and their scores, for an ICP.
+    calibration: Option<SortedCalibration<f64>>,
+}
+
+impl<M, F> ClassifierScorer<M, F>
+        where M: ScoringClassifier, F: Fn() -> M {
+    /// Constructs a nonconformity measure from a scoring classifier.
+    ///
+    /// # Arguments
+    ///
+    /// * `new_model` - Function returning a new (untrained) classifier.
+    pub fn new(new_model: F) -> ClassifierScorer<M, F> {
+        ClassifierScorer {
+            new_model: new_model,
+            n_labels: None,
+            train_inputs: None,
+            model: OnceLock::new(),
+            calibration: None,
+        }
+    }
+
+    /// Trains a new classifier on the training examples and, if
+    /// specified, on an additional example `(x, y)`.
+    fn fit(&self, example: Option<(&ArrayView1<f64>, usize)>) -> Result<M> {
+        let n_labels = self.n_labels.ok_or(Error::NotTrained)?;
+        let train_inputs = self.train_inputs.as_ref()
+                                            .ok_or(Error::NotTrained)?;
+
+        let mut data = vec![];
+        let mut targets = vec![];
+        if let Some((x, y)) = example {
+            data.extend(x.iter());
+            targets.push(y);
+        }
+        for (y, inputs_y) in train_inputs.iter().enumerate() {
+            data.extend(inputs_y.iter());
+            targets.extend(vec![y; inputs_y.rows()]);
+        }
+        let d = train_inputs.first()
+                            .map_or(0, |inputs_0| inputs_0.cols());
+        let inputs = Matrix::new(targets.len(), d, data);
+
+        let mut model = (self.new_model)();
+        model.train_labels(&inputs, &targets, n_labels)?;
+
+        Ok(model)
+    }
+
+    /// Returns the classifier trained on the training examples,
+    /// training it if needed.
+    fn model(&self) -> Result<&M> {
+        if let Some(model) = self.model.get() {
+            return Ok(model);
+        }
+        let model = self.fit(None)?;
+
+        Ok(self.model.get_or_init(|| model))
+    }
+
+    /// Returns the training inputs with label `y`, after checking that
+    /// the test object `x` can be compared to them.
+    fn train_inputs_y(&self, x: &ArrayView1<f64>, y: usize) -> Result<&Array2<f64>> {
+        let train_inputs = self.train_inputs.as_ref()
+                                            .ok_or(Error::NotTrained)?;
+        let train_inputs_y = inputs_y(train_inputs, x, y)?;
+        check_nan(&x.to_vec())?;
+
+        Ok(train_inputs_y)
+    }
+
+    /// Returns the score of label `y` for object `x`, computed by the
+    /// classifier trained on the training examples.
+    fn label_score(&self, x: &ArrayView1<f64>, y: usize) -> Result<f64> {
+        self.train_inputs_y(x, y)?;
+        let n_labels = self.n_labels.ok_or(Error::NotTrained)?;
+
+        let input = x.into_shape((1, x.len()))
+                     .expect("Unexpected reshaping error");
+        let score = self.model()?
+                        .label_scores(&to_matrix(&input), n_labels)?[[0, y]];
+        check_nan(&[score])?;
+
+        Ok(score)
+    }
+
+    /// Recomputes the nonconformity scores of the calibration inputs,
+    /// if any, which depend on the trained classifier.
+    fn sort_calibration(&mut self) -> Result<()> {
+        if let Some(calibration) = self.calibration.take() {
+            self.calibration = Some(calibration.rescore(|x, y| self.label_score(x, y))?);
+        }
+
+        Ok(())
+    }
+}
+
+impl<M, F> NonconformityScorer<f64> for ClassifierScorer<M, F>
+        where M: ScoringClassifier + Send + Sync, F: Fn() -> M + Sync {
+    /// Stores the training examples.
+    ///
+    /// Classifiers are trained when needed by the other methods.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<f64>) of training vectors.
+    /// * `targets` - Vector (Array1<usize>) of labels corresponding to the
+    ///               training vectors.
+    /// * `n_labels` - Number of unique labels in the classification problem.
+    fn train(&mut self, inputs: &ArrayView2<f64>, targets: &ArrayView1<usize>,
+             n_labels: usize) -> Result<()> {
+        if self.train_inputs.is_some() {
+            return Err(Error::AlreadyTrained);
+        }
+        self.train_inputs = Some(split_inputs(inputs, targets, n_labels)?);
+        self.n_labels = Some(n_labels);
+
+        Ok(())
+    }
+
+    /// Trains the classifier on the proper training set, and computes
+    /// the scores of the calibration examples.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<f64>) of calibration vectors.
+    /// * `targets` - Vector (Array1<usize>) of labels corresponding to the
+    ///               calibration vectors.
+    fn calibrate(&mut self, inputs: &ArrayView2<f64>, targets: &ArrayView1<usize>)
+             -> Result<()> {
+        let n_labels = self.n_labels.ok_or(Error::NotTrained)?;
+        check_dimension(&self.train_inputs, inputs.cols())?;
+
+        let calibration_inputs = split_inputs(inputs, targets, n_labels)?;
+        self.calibration = Some(SortedCalibration::new(calibration_inputs,
+                                                       |x, y| self.score(x, y))?);
+
+        Ok(())
+    }
+
+    /// Adds training examples.
+    ///
+    /// For an ICP, the classifier is retrained and the calibration
+    /// scores are recomputed.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<f64>) of training vectors.
+    /// * `targets` - Vector (Array1<usize>) of labels corresponding to the
+    ///               training vectors.
+    fn update(&mut self, inputs: &ArrayView2<f64>, targets: &ArrayView1<usize>)
+        -> Result<()> {
+
+        let n_labels = self.n_labels.ok_or(Error::NotTrained)?;
+        check_dimension(&self.train_inputs, inputs.cols())?;
+        check_lengths(inputs.rows(), targets.len())?;
+        check_labels(targets, n_labels)?;
+
+        let train_inputs = self.train_inputs.as_mut()
+                                            .ok_or(Error::NotTrained)?;
+        for (x, y) in inputs.outer_iter().zip(targets) {
+            train_inputs[*y] = stack![Axis(0), train_inputs[*y],
+                                      x.into_shape((1, x.len()))
+                                       .expect("Unexpected reshaping error")];
+        }
+        self.model = OnceLock::new();
+
+        self.sort_calibration()
+    }
+
+    /// Adds calibration examples.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<f64>) of calibration vectors.
+    /// * `targets` - Vector (Array1<usize>) of labels corresponding to the
+    ///               calibration vectors.
+    fn update_calibration(&mut self, inputs: &ArrayView2<f64>, targets: &ArrayView1<usize>)
+        -> Result<()> {
+
+        check_dimension(&self.train_inputs, inputs.cols())?;
+        let mut calibration = self.calibration.take()
+                                              .ok_or(Error::NotCalibrated)?;
+        let extended = calibration.extend(inputs, targets, |x, y| self.score(x, y));
+        self.calibration = Some(calibration);
+
+        extended
+    }
+
+    /// Computes the nonconformity scores of training inputs and of a new
+    /// test example.
+    ///
+    /// For a transductive CP, a classifier is trained on the training
+    /// examples and on (x, y), and it computes the scores
+    /// (a_1, ..., a_{n-1}) of the training examples with label y, and
+    /// a_n of (x, y). They are returned in the following order:
+    ///     (a_n, a_1, a_2, ..., a_{n-1}).
+    ///
+    /// # Arguments
+    ///
+    /// * `x` - Test object.
+    /// * `y` - (Candidate) label for the test object.
+    fn scores(&self, x: &ArrayView1<f64>, y: usize) -> Result<Vec<f64>> {
+        let train_inputs_y = self.train_inputs_y(x, y)?;
+
+        // ICP: calibration scores were computed when calibrating.
+        if let Some(calibration) = self.calibration.as_ref() {
+            return calibration.with_score(self.score(x, y)?, y);
+        }
+
+        // TCP: retrain, including the test example.
+        let n_labels = self.n_labels.ok_or(Error::NotTrained)?;
+        let model = self.fit(Some((x, y)))?;
+        let inputs = stack![Axis(0), x.into_shape((1, x.len()))
+                                      .expect("Unexpected reshaping error"),
+                            *train_inputs_y];
+        let scores = model.label_scores(&to_matrix(&inputs.view()), n_labels)?
+                          .column(y)
+                          .to_vec();
+        check_nan(&scores)?;
+
+        Ok(scores)
+    }
+
+    /// Computes the nonconformity score of a new example with respect
+    /// to the classifier trained on the (proper) training set.
+    ///
+    /// # Arguments
+    ///
+    /// * `x` - Test object.
+    /// * `y` - (Candidate) label for the test object.
+    fn score(&self, x: &ArrayView1<f64>, y: usize) -> Result<f64> {
+        self.label_score(x, y)
+    }
+
+    /// Returns the nonconformity scores of the calibration examples
+    /// with label `y`, sorted in ascending order.
+    ///
+    /// Returns None if the scorer was not calibrated.
+    ///
+    /// # Arguments
+    ///
+    /// * `y` - (Candidate) label for the test object.
+    fn calibration_scores(&self, y: usize) -> Option<&[f64]> {
+        self.calibration.as_ref()
+                        .and_then(|calibration| calibration.scores(y))
+    }
+
+    /// Visits the examples whose nonconformity scores are returned
+    /// by `scores()`.
+    ///
+    /// These are the calibration examples with label `y` for an ICP,
+    /// and the training examples with label `y` otherwise.
+    ///
+    /// # Arguments
+    ///
+    /// * `y` - (Candidate) label for the test object.
+    /// * `visit` - Function called on each example.
+    fn visit_examples(&self, y: usize, visit: &mut dyn FnMut(&ArrayView1<f64>, usize))
+                      -> Result<()> {
+        let inputs = match self.calibration {
+            Some(ref calibration) => calibration.inputs(),
+            None => self.train_inputs.as_ref()
+                                     .ok_or(Error::NotTrained)?,
+        };
+
+        visit_inputs(inputs, y, visit)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusty_machine::learning::naive_bayes::Gaussian;
+
+    /// Verify that ICP scores are those of the classifier trained on
+    /// the proper training set.
+    #[test]
+    fn inductive() {
+        let mut ncm = ClassifierScorer::new(LogisticRegressor::default);
+        let train_inputs = array![[0.], [1.], [2.], [8.], [9.], [10.]];
+        let train_targets = array![0, 0, 0, 1, 1, 1];
+        let calibration_inputs = array![[1.5], [0.5], [9.5]];
+        let calibration_targets = array![0, 0, 1];
+
+        ncm.train(&train_inputs.view(), &train_targets.view(), 2).unwrap();
+        ncm.calibrate(&calibration_inputs.view(), &calibration_targets.view()).unwrap();
+
+        let mut model = LogisticRegressor::default();
+        model.train(&to_matrix(&train_inputs.view()),
+                    &Vector::new(vec![0., 0., 0., 1., 1., 1.])).unwrap();
+        let p = model.predict(&to_matrix(&array![[0.5], [1.5], [9.5], [3.]].view()))
+                     .unwrap();
+
+        // Calibration scores are sorted.
+        assert!(ncm.calibration_scores(0).unwrap() == &[p[0], p[1]][..]);
+        assert!(ncm.calibration_scores(1).unwrap() == &[1. - p[2]][..]);
+        assert!(ncm.scores(&array![3.].view(), 1).unwrap() == vec![1. - p[3], 1. - p[2]]);
+
+        // Only binary classification.
+        let mut ncm = ClassifierScorer::new(LogisticRegressor::default);
+        ncm.train(&train_inputs.view(), &array![0, 0, 1, 1, 2, 2].view(), 3).unwrap();
+        match ncm.score(&array![3.].view(), 0) {
+            Err(Error::UnsupportedLabels { n_labels: 3 }) => {},
+            r => panic!("Unexpected result: {:?}", r),
+        }
+    }
+
+    /// Verify that transductive scores are computed by a classifier
+    /// trained on the training examples and the test example.
+    #[test]
+    fn transductive() {
+        let mut ncm = ClassifierScorer::new(NaiveBayes::<Gaussian>::new);
+        let train_inputs = array![[0., 1.], [1., 0.], [1., 2.],
+                                  [5., 5.], [6., 4.], [5., 6.],
+                                  [0., 9.], [1., 8.], [2., 9.]];
+        let train_targets = array![0, 0, 0, 1, 1, 1, 2, 2, 2];
+        let x = array![1., 1.];
+
+        ncm.train(&train_inputs.view(), &train_targets.view(), 3).unwrap();
+        ncm.update(&array![[6., 6.]].view(), &array![1].view()).unwrap();
+
+        for y in 0..3 {
+            let mut model = NaiveBayes::<Gaussian>::new();
+            let inputs = stack![Axis(0), x.clone().into_shape((1, 2)).unwrap(),
+                                train_inputs.slice(s![..3, ..]),
+                                train_inputs.slice(s![3..6, ..]),
+                                array![[6., 6.]],
+                                train_inputs.slice(s![6.., ..])];
+            let targets = [y, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2];
+            model.train_labels(&to_matrix(&inputs.view()), &targets, 3).unwrap();
+            let expected = model.label_scores(&to_matrix(&inputs.view()), 3).unwrap();
+            let expected = targets.iter()
+                                  .enumerate()
+                                  .filter(|&(i, t)| i == 0 || *t == y)
+                                  .map(|(i, _)| expected[[i, y]])
+                                  .collect::<Vec<_>>();
+
+            assert!(ncm.scores(&x.view(), y).unwrap() == expected);
+        }
+        // The most likely label has the smallest score.
+        assert!(ncm.score(&x.view(), 0).unwrap() < ncm.score(&x.view(), 1).unwrap());
+    }
+}
@@ -0,0 +1,318 @@
+//! Distance functions for nearest neighbors nonconformity measures.
+//!
+//! Distances are defined for vectors of any element type implementing
+//! `Feature` (floating point numbers, integers and `bool`), whose
+//! values are converted into `f64` when computing the distance.
+use std::f64;
+use ndarray::prelude::*;
+
+use error::{Error, Result};
+
+
+/// Element type of input vectors.
+///
+/// A `Feature` can be compared for equality (e.g., for the Hamming
+/// distance) and converted into `f64` (for all other distances).
+pub trait Feature: Copy + Sync + PartialEq {
+    /// Returns the value of the feature as `f64`.
+    fn to_f64(self) -> f64;
+}
+
+macro_rules! impl_feature {
+    ($($t:ty),*) => {
+        $(impl Feature for $t {
+            fn to_f64(self) -> f64 {
+                self as f64
+            }
+        })*
+    }
+}
+
+impl_feature!(f64, f32, i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl Feature for bool {
+    /// Returns 1 for `true`, 0 for `false`.
+    fn to_f64(self) -> f64 {
+        if self { 1. } else { 0. }
+    }
+}
+
+/// Distance between two vectors of `T` values.
+///
+/// Distances are assumed to be symmetric.
+#[derive(Serialize, Deserialize, Default)]
+pub enum Distance<T> {
+    /// Euclidean distance (default).
+    #[default]
+    Euclidean,
+    /// Manhattan (L1) distance.
+    Manhattan,
+    /// Chebyshev (L-infinity) distance.
+    Chebyshev,
+    /// Minkowski distance of order p >= 1.
+    Minkowski(f64),
+    /// Cosine distance, i.e., one minus the cosine similarity.
+    /// It is NaN if either vector is null.
+    Cosine,
+    /// Mahalanobis distance, with respect to the covariance matrix of
+    /// the training inputs.
+    Mahalanobis,
+    /// Hamming distance, i.e., the number of elements that differ.
+    Hamming,
+    /// A user-defined distance function.
+    /// Nonconformity measures with a custom distance cannot be stored.
+    #[serde(skip)]
+    Custom(fn(&ArrayView1<T>, &ArrayView1<T>) -> f64),
+}
+
+impl<T> Clone for Distance<T> {
+    fn clone(&self) -> Distance<T> {
+        match *self {
+            Distance::Euclidean => Distance::Euclidean,
+            Distance::Manhattan => Distance::Manhattan,
+            Distance::Chebyshev => Distance::Chebyshev,
+            Distance::Minkowski(p) => Distance::Minkowski(p),
+            Distance::Cosine => Distance::Cosine,
+            Distance::Mahalanobis => Distance::Mahalanobis,
+            Distance::Hamming => Distance::Hamming,
+            Distance::Custom(f) => Distance::Custom(f),
+        }
+    }
+}
+
+impl<T: Feature> Distance<T> {
+    /// Returns true for Minkowski distances (Euclidean, Manhattan,
+    /// Chebyshev and Minkowski-p), for which the difference along
+    /// any coordinate is a lower bound of the distance.
+    pub(crate) fn is_minkowski(&self) -> bool {
+        matches!(*self, Distance::Euclidean | Distance::Manhattan | Distance::Chebyshev |
+                        Distance::Minkowski(_))
+    }
+
+    /// Computes the distance between `v1` and `v2`.
+    ///
+    /// # Arguments
+    ///
+    /// * `v1`, `v2` - Vectors.
+    /// * `inv_covariance` - Inverse covariance matrix, only used
+    ///                      (and required) by the Mahalanobis distance.
+    pub(crate) fn compute(&self, v1: &ArrayView1<T>, v2: &ArrayView1<T>,
+                          inv_covariance: Option<&Array2<f64>>) -> f64 {
+        match *self {
+            Distance::Euclidean => euclidean_distance(v1, v2),
+            Distance::Manhattan => manhattan_distance(v1, v2),
+            Distance::Chebyshev => chebyshev_distance(v1, v2),
+            Distance::Minkowski(p) => minkowski_distance(v1, v2, p),
+            Distance::Cosine => cosine_distance(v1, v2),
+            Distance::Mahalanobis => {
+                let inv_covariance = inv_covariance.expect("Covariance matrix not estimated");
+                mahalanobis_distance(v1, v2, inv_covariance)
+            },
+            Distance::Hamming => hamming_distance(v1, v2),
+            Distance::Custom(f) => f(v1, v2),
+        }
+    }
+}
+
+/// Returns the Euclidean distance between two vectors.
+pub fn euclidean_distance<T: Feature>(v1: &ArrayView1<T>, v2: &ArrayView1<T>) -> f64 {
+    v1.iter()
+      .zip(v2.iter())
+      .map(|(x,y)| (x.to_f64() - y.to_f64()).powi(2))
+      .sum::<f64>()
+      .sqrt()
+}
+
+/// Returns the Manhattan distance between two vectors.
+pub fn manhattan_distance<T: Feature>(v1: &ArrayView1<T>, v2: &ArrayView1<T>) -> f64 {
+    v1.iter()
+      .zip(v2.iter())
+      .map(|(x,y)| (x.to_f64() - y.to_f64()).abs())
+      .sum::<f64>()
+}
+
+/// Returns the Chebyshev distance between two vectors.
+pub fn chebyshev_distance<T: Feature>(v1: &ArrayView1<T>, v2: &ArrayView1<T>) -> f64 {
+    v1.iter()
+      .zip(v2.iter())
+      .map(|(x,y)| (x.to_f64() - y.to_f64()).abs())
+      .fold(0., f64::max)
+}
+
+/// Returns the Minkowski distance of order `p` between two vectors.
+pub fn minkowski_distance<T: Feature>(v1: &ArrayView1<T>, v2: &ArrayView1<T>,
+                                      p: f64) -> f64 {
+    v1.iter()
+      .zip(v2.iter())
+      .map(|(x,y)| (x.to_f64() - y.to_f64()).abs().powf(p))
+      .sum::<f64>()
+      .powf(1. / p)
+}
+
+/// Returns the cosine distance between two vectors.
+pub fn cosine_distance<T: Feature>(v1: &ArrayView1<T>, v2: &ArrayView1<T>) -> f64 {
+    let (dot, norm1, norm2) = v1.iter()
+                                .zip(v2.iter())
+                                .map(|(x,y)| (x.to_f64(), y.to_f64()))
+                                .fold((0., 0., 0.), |(dot, norm1, norm2), (x, y)| {
+                                    (dot + x*y, norm1 + x*x, norm2 + y*y)
+                                });
+    1. - dot / (norm1.sqrt() * norm2.sqrt())
+}
+
+/// Returns the Hamming distance between two vectors.
+pub fn hamming_distance<T: Feature>(v1: &ArrayView1<T>, v2: &ArrayView1<T>) -> f64 {
+    v1.iter()
+      .zip(v2.iter())
+      .filter(|&(x,y)| x != y)
+      .count() as f64
+}
+
+/// Returns the Mahalanobis distance between two vectors.
+///
+/// # Arguments
+///
+/// * `v1`, `v2` - Vectors.
+/// * `inv_covariance` - Inverse of the covariance matrix.
+pub fn mahalanobis_distance<T: Feature>(v1: &ArrayView1<T>, v2: &ArrayView1<T>,
+                                        inv_covariance: &Array2<f64>) -> f64 {
+    let diff = v1.iter()
+                 .zip(v2.iter())
+                 .map(|(x,y)| x.to_f64() - y.to_f64())
+                 .collect::<Array1<f64>>();
+    // Rounding errors may make the quadratic form slightly negative.
+    diff.dot(&inv_covariance.dot(&diff))
+        .max(0.)
+        .sqrt()
+}
+
+/// Estimates the (sample) covariance matrix of `inputs`, and returns
+/// its inverse.
+///
+/// The covariance matrix is considered singular if a pivot is not
+/// larger than its largest absolute entry times `d` times the machine
+/// epsilon, so that the test does not depend on the scale of the
+/// features.
+///
+/// # Errors
+///
+/// - if the covariance matrix is singular (e.g., if there are fewer
+///   inputs than features, or a feature is constant).
+pub(crate) fn inverse_covariance<T: Feature>(inputs: &ArrayView2<T>) -> Result<Array2<f64>> {
+    let inputs = inputs.mapv(|x| x.to_f64());
+    let (n, d) = inputs.dim();
+    if n < 2 {
+        return Err(Error::SingularMatrix);
+    }
+
+    let mean = inputs.sum_axis(Axis(0)) / n as f64;
+    let centered = inputs - &mean;
+    let covariance = centered.t().dot(&centered) / (n - 1) as f64;
+
+    let tolerance = covariance.fold(0., |max: f64, c| max.max(c.abs()))
+                    * d as f64 * f64::EPSILON;
+
+    // Gauss-Jordan elimination with partial pivoting.
+    let mut a = covariance;
+    let mut inverse = Array2::eye(d);
+    for j in 0..d {
+        let pivot = (j..d).max_by(|&r, &s| a[[r, j]].abs()
+                                                    .partial_cmp(&a[[s, j]].abs())
+                                                    .expect("Covariance cannot be NaN"))
+                          .expect("Empty range");
+        if a[[pivot, j]].abs() <= tolerance {
+            return Err(Error::SingularMatrix);
+        }
+        for c in 0..d {
+            a.swap([j, c], [pivot, c]);
+            inverse.swap([j, c], [pivot, c]);
+        }
+
+        let p = a[[j, j]];
+        for c in 0..d {
+            a[[j, c]] /= p;
+            inverse[[j, c]] /= p;
+        }
+        for r in (0..d).filter(|&r| r != j) {
+            let factor = a[[r, j]];
+            if factor == 0. {
+                continue;
+            }
+            for c in 0..d {
+                a[[r, c]] -= factor * a[[j, c]];
+                inverse[[r, c]] -= factor * inverse[[j, c]];
+            }
+        }
+    }
+
+    Ok(inverse)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Verify the built-in distances on a small example.
+    #[test]
+    fn distances() {
+        let v1 = array![0., 3., 1.];
+        let v2 = array![4., 0., 1.];
+
+        assert_relative_eq!(euclidean_distance(&v1.view(), &v2.view()), 5.);
+        assert_relative_eq!(manhattan_distance(&v1.view(), &v2.view()), 7.);
+        assert_relative_eq!(chebyshev_distance(&v1.view(), &v2.view()), 4.);
+        assert_relative_eq!(minkowski_distance(&v1.view(), &v2.view(), 2.), 5.);
+        assert_relative_eq!(minkowski_distance(&v1.view(), &v2.view(), 1.), 7.);
+        assert_relative_eq!(cosine_distance(&v1.view(), &v2.view()), 1. - 1. / 170f64.sqrt());
+        assert_relative_eq!(hamming_distance(&v1.view(), &v2.view()), 2.);
+
+        let b1 = array![true, false, true, true];
+        let b2 = array![false, false, true, false];
+        assert_relative_eq!(hamming_distance(&b1.view(), &b2.view()), 2.);
+        assert_relative_eq!(euclidean_distance(&b1.view(), &b2.view()), 2f64.sqrt());
+
+        let i1 = array![1i32, -2];
+        let i2 = array![4i32, 2];
+        assert_relative_eq!(euclidean_distance(&i1.view(), &i2.view()), 5.);
+    }
+
+    /// Verify the inverse covariance matrix, and that the Mahalanobis
+    /// distance with respect to an identity covariance is the
+    /// Euclidean distance.
+    #[test]
+    fn mahalanobis() {
+        let inputs = array![[1., 2.],
+                            [3., 2.],
+                            [1., 6.],
+                            [3., 6.]];
+        // Covariance: [[4/3, 0], [0, 16/3]].
+        let inverse = inverse_covariance(&inputs.view()).unwrap();
+        assert_relative_eq!(inverse[[0, 0]], 0.75);
+        assert_relative_eq!(inverse[[0, 1]], 0.);
+        assert_relative_eq!(inverse[[1, 0]], 0.);
+        assert_relative_eq!(inverse[[1, 1]], 3. / 16.);
+
+        let v1 = array![0., 0.];
+        let v2 = array![2., 4.];
+        assert_relative_eq!(mahalanobis_distance(&v1.view(), &v2.view(), &inverse),
+                            (4. * 0.75 + 16. * 3. / 16f64).sqrt());
+        assert_relative_eq!(mahalanobis_distance(&v1.view(), &v2.view(), &Array2::eye(2)),
+                            euclidean_distance(&v1.view(), &v2.view()));
+
+        // Singularity does not depend on the scale of the features.
+        let inverse = inverse_covariance(&(inputs * 1e-8).view()).unwrap();
+        assert_relative_eq!(inverse[[0, 0]], 0.75e16, max_relative = 1e-12);
+        assert_relative_eq!(inverse[[1, 1]], 3. / 16. * 1e16, max_relative = 1e-12);
+
+        // Constant feature, and linearly dependent features.
+        match inverse_covariance(&array![[1., 0.], [2., 0.], [3., 0.]].view()) {
+            Err(Error::SingularMatrix) => {},
+            r => panic!("Unexpected result: {:?}", r),
+        }
+        match inverse_covariance(&array![[1e8, 3e8], [2e8, 6e8], [4e8, 12e8]].view()) {
+            Err(Error::SingularMatrix) => {},
+            r => panic!("Unexpected result: {:?}", r),
+        }
+    }
+}
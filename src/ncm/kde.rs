@@ -0,0 +1,675 @@
+//! Kernel density estimation nonconformity measure.
+use std::f64;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use ndarray::prelude::*;
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::de::Error as DeError;
+
+use error::{Error, Result, check_lengths, check_labels, check_nan};
+use ncm::{NonconformityScorer, SortedCalibration, check_dimension, inputs_y, visit_inputs};
+use ncm::distance::{Feature, euclidean_distance};
+use ncm::knn::split_inputs;
+
+
+/// Kernel of a kernel density estimate.
+///
+/// Kernels are evaluated at $u = \|x - x_i\| / h$, for bandwidth $h$,
+/// and they are not normalized.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Kernel {
+    /// $\exp(-u^2/2)$.
+    Gaussian,
+    /// $1 - u^2$ if $u < 1$, 0 otherwise.
+    Epanechnikov,
+    /// 1 if $u < 1$, 0 otherwise.
+    Tophat,
+    /// $\exp(-u)$.
+    Exponential,
+    /// $1 - u$ if $u < 1$, 0 otherwise.
+    Linear,
+    /// $\cos(\pi u / 2)$ if $u < 1$, 0 otherwise.
+    Cosine,
+}
+
+impl Kernel {
+    /// Evaluates the kernel at `u`, the distance between two vectors
+    /// divided by the bandwidth.
+    pub fn evaluate(&self, u: f64) -> f64 {
+        match *self {
+            Kernel::Gaussian => (-0.5 * u * u).exp(),
+            Kernel::Exponential => (-u).exp(),
+            _ if u >= 1. => 0.,
+            Kernel::Epanechnikov => 1. - u * u,
+            Kernel::Tophat => 1.,
+            Kernel::Linear => 1. - u,
+            Kernel::Cosine => (f64::consts::FRAC_PI_2 * u).cos(),
+        }
+    }
+}
+
+impl FromStr for Kernel {
+    type Err = Error;
+
+    /// Parses a kernel from its lowercase name (e.g., "gaussian").
+    fn from_str(s: &str) -> Result<Kernel> {
+        match s {
+            "gaussian" => Ok(Kernel::Gaussian),
+            "epanechnikov" => Ok(Kernel::Epanechnikov),
+            "tophat" => Ok(Kernel::Tophat),
+            "exponential" => Ok(Kernel::Exponential),
+            "linear" => Ok(Kernel::Linear),
+            "cosine" => Ok(Kernel::Cosine),
+            _ => Err(Error::Format(format!("unknown kernel {}", s))),
+        }
+    }
+}
+
+/// Bandwidth of a kernel density estimate.
+///
+/// Rules of thumb estimate the bandwidth from the `n` training inputs,
+/// with dimension `d`, as a multiple of $\sigma n^{-1/(d+4)}$, where
+/// $\sigma$ is the average standard deviation of the features.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Bandwidth {
+    /// A given (positive) bandwidth.
+    Fixed(f64),
+    /// Silverman's rule of thumb:
+    /// $h = (4/(d+2))^{1/(d+4)} \sigma n^{-1/(d+4)}$.
+    Silverman,
+    /// Scott's rule of thumb: $h = \sigma n^{-1/(d+4)}$.
+    Scott,
+}
+
+impl Bandwidth {
+    /// Returns the bandwidth for some training inputs.
+    ///
+    /// # Errors
+    ///
+    /// - if the bandwidth is estimated by a rule of thumb, and the
+    ///   features of the inputs have zero variance.
+    fn estimate<T: Feature>(&self, inputs: &ArrayView2<T>) -> Result<f64> {
+        let (n, d) = inputs.dim();
+        let factor = match *self {
+            Bandwidth::Fixed(h) => return Ok(h),
+            Bandwidth::Silverman => (4. / (d as f64 + 2.)).powf(1. / (d as f64 + 4.)),
+            Bandwidth::Scott => 1.,
+        };
+
+        let sigma = inputs.axis_iter(Axis(1))
+                          .map(|feature| {
+                              let values = feature.mapv(|v| v.to_f64());
+                              let mean = values.scalar_sum() / n as f64;
+                              (values.mapv(|v| (v - mean).powi(2)).scalar_sum()
+                                  / (n as f64 - 1.)).sqrt()
+                          })
+                          .sum::<f64>() / d as f64;
+
+        let h = factor * sigma * (n as f64).powf(-1. / (d as f64 + 4.));
+        if !h.is_finite() || h <= 0. {
+            return Err(Error::ZeroVariance);
+        }
+
+        Ok(h)
+    }
+}
+
+/// Returns an error if a bandwidth value is not positive.
+fn check_bandwidth(h: f64) -> Result<()> {
+    if h.is_nan() || h <= 0. {
+        return Err(Error::Format(format!("bandwidth must be positive, got {}", h)));
+    }
+
+    Ok(())
+}
+
+impl FromStr for Bandwidth {
+    type Err = Error;
+
+    /// Parses a bandwidth, either a positive number or the name of
+    /// a rule of thumb ("silverman" or "scott").
+    fn from_str(s: &str) -> Result<Bandwidth> {
+        match s {
+            "silverman" => Ok(Bandwidth::Silverman),
+            "scott" => Ok(Bandwidth::Scott),
+            _ => {
+                let h = s.parse::<f64>()?;
+                check_bandwidth(h)?;
+                Ok(Bandwidth::Fixed(h))
+            },
+        }
+    }
+}
+
+/// A kernel density estimation (KDE) nonconformity measure.
+///
+/// The nonconformity score of an example $(x, y)$ is the negative
+/// density at $x$ estimated from the training inputs with label $y$,
+/// up to a constant factor that only depends on the kernel, the
+/// bandwidth and the dimension:
+///
+/// $$-\frac{1}{n_y} \sum_{i: y_i = y} K(\|x - x_i\| / h).$$
+///
+/// For a transductive CP, the score of each training example is
+/// computed leaving it out of the estimate (and including the test
+/// example); for an ICP, the scores are computed with respect to the
+/// proper training set.
+/// The bandwidth is chosen when calling `train()`, and it is not
+/// changed by `update()`.
+///
+/// # Examples
+///
+/// ```
+/// #[macro_use(array)]
+/// extern crate ndarray;
+/// extern crate random_world;
+///
+/// # fn main() {
+/// use random_world::cp::*;
+/// use random_world::ncm::*;
+///
+/// let ncm = KDE::new(Kernel::Gaussian, Bandwidth::Silverman);
+/// let mut cp = CP::new(ncm, 2, Some(0.3));
+/// let train_inputs = array![[0., 0.],
+///                           [1., 0.],
+///                           [0., 1.],
+///                           [1., 1.],
+///                           [2., 2.],
+///                           [1., 2.]];
+/// let train_targets = array![0, 0, 0, 1, 1, 1];
+///
+/// cp.train(&train_inputs.view(), &train_targets.view())
+///   .expect("Failed to train");
+/// let preds = cp.predict(&array![[0., 0.5], [2., 1.5]].view())
+///               .expect("Failed to predict");
+/// assert!(preds == array![[true, false],
+///                         [false, true]]);
+/// # }
+/// ```
+pub struct KDE<T: Sync> {
+    kernel: Kernel,
+    bandwidth: Bandwidth,
+    // Bandwidth value, set by train().
+    h: Option<f64>,
+    n_labels: Option<usize>,
+    // train_inputs[y] contains all training inputs with label y.
+    train_inputs: Option<Vec<Array2<T>>>,
+    // Calibration examples and their scores, for an ICP.
+    calibration: Option<SortedCalibration<T>>,
+    // For transductive CP: kernel_sums[y][i] is the sum of the kernel
+    // between train_inputs[y][i] and the other inputs in train_inputs[y].
+    // Only computed on the first call to scores(), and then kept
+    // up-to-date by update().
+    kernel_sums: OnceLock<Vec<Vec<f64>>>,
+}
+
+impl<T> KDE<T> where T: Feature {
+    /// Constructs a KDE nonconformity measure.
+    ///
+    /// # Arguments
+    ///
+    /// * `kernel` - Kernel.
+    /// * `bandwidth` - Either a fixed bandwidth, or a rule of thumb
+    ///                 to estimate it from the training inputs.
+    pub fn new(kernel: Kernel, bandwidth: Bandwidth) -> KDE<T> {
+        if let Bandwidth::Fixed(h) = bandwidth {
+            assert!(h > 0., "Bandwidth must be positive");
+        }
+
+        KDE {
+            kernel: kernel,
+            bandwidth: bandwidth,
+            h: None,
+            n_labels: None,
+            train_inputs: None,
+            calibration: None,
+            kernel_sums: OnceLock::new(),
+        }
+    }
+
+    /// Returns the bandwidth, if the scorer was trained.
+    pub fn bandwidth(&self) -> Option<f64> {
+        self.h
+    }
+
+    /// Returns the kernel between two vectors, for bandwidth `h`.
+    fn kernel(&self, h: f64, v1: &ArrayView1<T>, v2: &ArrayView1<T>) -> f64 {
+        self.kernel.evaluate(euclidean_distance(v1, v2) / h)
+    }
+
+    /// Returns the kernel between `x` and each training input with
+    /// label `y`, after checking that `x` can be compared to them.
+    fn kernels(&self, x: &ArrayView1<T>, y: usize) -> Result<Vec<f64>> {
+        let train_inputs = self.train_inputs.as_ref()
+                                            .ok_or(Error::NotTrained)?;
+        let train_inputs_y = inputs_y(train_inputs, x, y)?;
+        let h = self.h.ok_or(Error::NotTrained)?;
+        // Some kernels are not NaN for NaN distances.
+        if x.iter().any(|v| v.to_f64().is_nan()) {
+            return Err(Error::NaN);
+        }
+
+        let kernels = train_inputs_y.outer_iter()
+                                    .map(|x_| self.kernel(h, &x_, x))
+                                    .collect::<Vec<_>>();
+        check_nan(&kernels)?;
+
+        Ok(kernels)
+    }
+
+    /// Recomputes the nonconformity scores of the calibration inputs,
+    /// if any, which depend on the training data.
+    fn sort_calibration(&mut self) -> Result<()> {
+        if let Some(calibration) = self.calibration.take() {
+            self.calibration = Some(calibration.rescore(|x, y| self.score(x, y))?);
+        }
+
+        Ok(())
+    }
+}
+
+/// Serialized representation of a KDE nonconformity measure.
+///
+/// Calibration scores and kernel sums are not stored, as they can be
+/// recomputed from the inputs.
+#[derive(Serialize, Deserialize)]
+struct KDEState<I> {
+    kernel: Kernel,
+    bandwidth: Bandwidth,
+    h: Option<f64>,
+    n_labels: Option<usize>,
+    train_inputs: Option<I>,
+    calibration_inputs: Option<I>,
+}
+
+impl<T> Serialize for KDE<T> where T: Sync + Serialize {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        KDEState {
+            kernel: self.kernel,
+            bandwidth: self.bandwidth,
+            h: self.h,
+            n_labels: self.n_labels,
+            train_inputs: self.train_inputs.as_ref()
+                                           .map(|train_inputs| &train_inputs[..]),
+            calibration_inputs: self.calibration.as_ref()
+                                                .map(|calibration| calibration.inputs()),
+        }.serialize(serializer)
+    }
+}
+
+impl<'de, T> Deserialize<'de> for KDE<T> where T: Feature + Deserialize<'de> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<KDE<T>, D::Error> {
+        let state = KDEState::<Vec<Array2<T>>>::deserialize(deserializer)?;
+        if let Bandwidth::Fixed(h) = state.bandwidth {
+            check_bandwidth(h).map_err(D::Error::custom)?;
+        }
+        match state.h {
+            Some(h) => check_bandwidth(h).map_err(D::Error::custom)?,
+            None if state.train_inputs.is_some() => {
+                return Err(D::Error::custom("missing bandwidth of a trained KDE"));
+            },
+            None => {},
+        }
+
+        let mut kde = KDE::new(state.kernel, state.bandwidth);
+        kde.h = state.h;
+        kde.n_labels = state.n_labels;
+        kde.train_inputs = state.train_inputs;
+        if let Some(calibration_inputs) = state.calibration_inputs {
+            kde.calibration = Some(SortedCalibration::new(calibration_inputs,
+                                                          |x, y| kde.score(x, y))
+                                                     .map_err(D::Error::custom)?);
+        }
+
+        Ok(kde)
+    }
+}
+
+impl<T: Sync> NonconformityScorer<T> for KDE<T>
+        where T: Feature {
+    /// Trains a KDE nonconformity scorer, and chooses its bandwidth.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<T>) with values of type T of training
+    ///              vectors.
+    /// * `targets` - Vector (Array1<T>) of labels corresponding to the
+    ///               training vectors.
+    /// * `n_labels` - Number of unique labels in the classification problem.
+    fn train(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView1<usize>,
+             n_labels: usize) -> Result<()> {
+        if self.train_inputs.is_some() {
+            return Err(Error::AlreadyTrained);
+        }
+        let train_inputs = split_inputs(inputs, targets, n_labels)?;
+        self.h = Some(self.bandwidth.estimate(inputs)?);
+        self.train_inputs = Some(train_inputs);
+        self.n_labels = Some(n_labels);
+
+        Ok(())
+    }
+
+    /// Calibrates a KDE nonconformity scorer for an ICP.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<T>) with values of type T of calibration
+    ///              vectors.
+    /// * `targets` - Vector (Array1<T>) of labels corresponding to the
+    ///               calibration vectors.
+    fn calibrate(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView1<usize>)
+             -> Result<()> {
+        let n_labels = self.n_labels.ok_or(Error::NotTrained)?;
+        check_dimension(&self.train_inputs, inputs.cols())?;
+
+        let calibration_inputs = split_inputs(inputs, targets, n_labels)?;
+        self.calibration = Some(SortedCalibration::new(calibration_inputs,
+                                                       |x, y| self.score(x, y))?);
+
+        Ok(())
+    }
+
+    /// Updates a KDE nonconformity scorer with more training data.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<T>) with values of type T of training
+    ///              vectors.
+    /// * `targets` - Vector (Array1<T>) of labels corresponding to the
+    ///               training vectors.
+    fn update(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView1<usize>)
+        -> Result<()> {
+
+        let n_labels = self.n_labels.ok_or(Error::NotTrained)?;
+        check_dimension(&self.train_inputs, inputs.cols())?;
+        check_lengths(inputs.rows(), targets.len())?;
+        check_labels(targets, n_labels)?;
+
+        let (kernel, h) = (self.kernel, self.h.ok_or(Error::NotTrained)?);
+        let train_inputs = self.train_inputs.as_mut()
+                                            .ok_or(Error::NotTrained)?;
+
+        for (x, y) in inputs.outer_iter().zip(targets) {
+            // Update the kernel sums of TCP, if computed.
+            if let Some(kernel_sums) = self.kernel_sums.get_mut() {
+                let mut sum = 0.;
+                for (x_, sum_) in train_inputs[*y].outer_iter()
+                                                  .zip(kernel_sums[*y].iter_mut()) {
+                    let k = kernel.evaluate(euclidean_distance(&x_, &x) / h);
+                    *sum_ += k;
+                    sum += k;
+                }
+                kernel_sums[*y].push(sum);
+            }
+            train_inputs[*y] = stack![Axis(0), train_inputs[*y],
+                                      x.into_shape((1, x.len()))
+                                       .expect("Unexpected reshaping error")];
+        }
+
+        self.sort_calibration()
+    }
+
+    /// Updates a KDE nonconformity scorer with more calibration data.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<T>) with values of type T of calibration
+    ///              vectors.
+    /// * `targets` - Vector (Array1<T>) of labels corresponding to the
+    ///               calibration vectors.
+    fn update_calibration(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView1<usize>)
+        -> Result<()> {
+
+        check_dimension(&self.train_inputs, inputs.cols())?;
+        let mut calibration = self.calibration.take()
+                                              .ok_or(Error::NotCalibrated)?;
+        let extended = calibration.extend(inputs, targets, |x, y| self.score(x, y));
+        self.calibration = Some(calibration);
+
+        extended
+    }
+
+    /// Computes the nonconformity scores of training inputs and of a new
+    /// test example.
+    ///
+    /// The scores (a_1, ..., a_{n-1}) of the training examples are
+    /// computed leaving each of them out of the density estimate, and
+    /// including the new example (x, y). They are returned, with the
+    /// score a_n of the new example, in the following order:
+    ///     (a_n, a_1, a_2, ..., a_{n-1}).
+    ///
+    /// # Arguments
+    ///
+    /// * `x` - Test object.
+    /// * `y` - (Candidate) label for the test object.
+    fn scores(&self, x: &ArrayView1<T>, y: usize) -> Result<Vec<f64>> {
+        let kernels = self.kernels(x, y)?;
+
+        // ICP: calibration scores were computed when calibrating.
+        if let Some(calibration) = self.calibration.as_ref() {
+            let n = kernels.len() as f64;
+            let score = if n > 0. { -kernels.iter().sum::<f64>() / n } else { 0. };
+
+            return calibration.with_score(score, y);
+        }
+
+        // TCP. Each example is scored with respect to the n other ones.
+        let train_inputs = self.train_inputs.as_ref()
+                                            .ok_or(Error::NotTrained)?;
+        let h = self.h.ok_or(Error::NotTrained)?;
+        let kernel_sums = self.kernel_sums.get_or_init(|| {
+            train_inputs.iter()
+                        .map(|inputs_y| {
+                            inputs_y.outer_iter()
+                                    .enumerate()
+                                    .map(|(i, x_)| {
+                                        inputs_y.outer_iter()
+                                                .enumerate()
+                                                .filter(|&(j, _)| j != i)
+                                                .map(|(_, x)| self.kernel(h, &x, &x_))
+                                                .sum::<f64>()
+                                    })
+                                    .collect()
+                        })
+                        .collect()
+        });
+        let n = kernels.len() as f64;
+
+        let mut scores = Vec::with_capacity(kernels.len() + 1);
+        scores.push(if n > 0. { -kernels.iter().sum::<f64>() / n } else { 0. });
+        scores.extend(kernels.iter()
+                             .zip(&kernel_sums[y])
+                             .map(|(k, sum)| -(sum + k) / n));
+
+        Ok(scores)
+    }
+
+    /// Computes the nonconformity score of a new example with respect
+    /// to the (proper) training set.
+    ///
+    /// # Arguments
+    ///
+    /// * `x` - Test object.
+    /// * `y` - (Candidate) label for the test object.
+    fn score(&self, x: &ArrayView1<T>, y: usize) -> Result<f64> {
+        let kernels = self.kernels(x, y)?;
+
+        if kernels.is_empty() {
+            return Ok(0.);
+        }
+
+        Ok(-kernels.iter().sum::<f64>() / kernels.len() as f64)
+    }
+
+    /// Returns the nonconformity scores of the calibration examples
+    /// with label `y`, sorted in ascending order.
+    ///
+    /// Returns None if the scorer was not calibrated.
+    ///
+    /// # Arguments
+    ///
+    /// * `y` - (Candidate) label for the test object.
+    fn calibration_scores(&self, y: usize) -> Option<&[f64]> {
+        self.calibration.as_ref()
+                        .and_then(|calibration| calibration.scores(y))
+    }
+
+    /// Visits the examples whose nonconformity scores are returned
+    /// by `scores()`.
+    ///
+    /// These are the calibration examples with label `y` for an ICP,
+    /// and the training examples with label `y` otherwise.
+    ///
+    /// # Arguments
+    ///
+    /// * `y` - (Candidate) label for the test object.
+    /// * `visit` - Function called on each example.
+    fn visit_examples(&self, y: usize, visit: &mut dyn FnMut(&ArrayView1<T>, usize))
+                      -> Result<()> {
+        let inputs = match self.calibration {
+            Some(ref calibration) => calibration.inputs(),
+            None => self.train_inputs.as_ref()
+                                     .ok_or(Error::NotTrained)?,
+        };
+
+        visit_inputs(inputs, y, visit)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Verify the kernels at a few points.
+    #[test]
+    fn kernels() {
+        let kernels = [Kernel::Gaussian, Kernel::Epanechnikov, Kernel::Tophat,
+                       Kernel::Exponential, Kernel::Linear, Kernel::Cosine];
+        let expected_0 = [1., 1., 1., 1., 1., 1.];
+        let expected_half = [(-0.125f64).exp(), 0.75, 1., (-0.5f64).exp(), 0.5,
+                             0.5f64.sqrt()];
+
+        for ((kernel, e0), e_half) in kernels.iter().zip(&expected_0).zip(&expected_half) {
+            assert_relative_eq!(kernel.evaluate(0.), *e0);
+            assert_relative_eq!(kernel.evaluate(0.5), *e_half);
+            if *kernel != Kernel::Gaussian && *kernel != Kernel::Exponential {
+                assert!(kernel.evaluate(1.) == 0.);
+                assert!(kernel.evaluate(2.) == 0.);
+            }
+        }
+
+        assert!("tophat".parse::<Kernel>().unwrap() == Kernel::Tophat);
+        assert!("scott".parse::<Bandwidth>().unwrap() == Bandwidth::Scott);
+        assert!("0.5".parse::<Bandwidth>().unwrap() == Bandwidth::Fixed(0.5));
+        assert!("foo".parse::<Kernel>().is_err());
+        assert!("-1".parse::<Bandwidth>().is_err());
+    }
+
+    /// Verify the bandwidth rules of thumb.
+    #[test]
+    fn bandwidth() {
+        // d = 1, standard deviation 1: h = sigma * 3^(-1/5) for Scott,
+        // and (4/3)^(1/5) times that for Silverman.
+        let inputs = array![[0.], [1.], [2.]];
+
+        assert_relative_eq!(Bandwidth::Scott.estimate(&inputs.view()).unwrap(),
+                            3f64.powf(-0.2));
+        assert_relative_eq!(Bandwidth::Silverman.estimate(&inputs.view()).unwrap(),
+                            (4. / 9f64).powf(0.2));
+        assert!(Bandwidth::Fixed(0.3).estimate(&inputs.view()).unwrap() == 0.3);
+
+        // d = 3, standard deviations 1, 2 and 3: h = 2 * 3^(-1/7) for
+        // Scott, and (4/5)^(1/7) times that for Silverman.
+        let inputs = array![[0., 0., 0.],
+                            [1., 2., 3.],
+                            [2., 4., 6.]];
+
+        assert_relative_eq!(Bandwidth::Scott.estimate(&inputs.view()).unwrap(),
+                            2. * 3f64.powf(-1. / 7.));
+        assert_relative_eq!(Bandwidth::Silverman.estimate(&inputs.view()).unwrap(),
+                            2. * (4. / 15f64).powf(1. / 7.));
+
+        match Bandwidth::Scott.estimate(&array![[1., 1.], [1., 1.]].view()) {
+            Err(Error::ZeroVariance) => {},
+            r => panic!("Unexpected result: {:?}", r),
+        }
+    }
+
+    /// Verify that TCP scores computed from the cached kernel sums,
+    /// also after update(), are the leave-one-out scores, and that
+    /// ICP scores are computed with respect to the training set.
+    #[test]
+    fn scores() {
+        let n_labels = 2;
+        let inputs = Array::from_shape_fn((30, 2), |(i, j)| ((i * (j + 2)) as f64).sin());
+        let targets = Array::from_shape_fn(30, |i| i % n_labels);
+        let test_input = array![0.5, -0.5];
+
+        let mut kde = KDE::new(Kernel::Epanechnikov, Bandwidth::Fixed(0.8));
+        kde.train(&inputs.slice(s![..10, ..]), &targets.slice(s![..10]), n_labels)
+           .expect("Failed to train model");
+        // Compute kernel sums before updating.
+        kde.scores(&test_input.view(), 0)
+           .expect("Failed to compute scores");
+        kde.update(&inputs.slice(s![10.., ..]), &targets.slice(s![10..]))
+           .expect("Failed to update model");
+
+        for y in 0..n_labels {
+            // Brute-force leave-one-out scores.
+            let train_inputs_y = &kde.train_inputs.as_ref().unwrap()[y];
+            let all_inputs = stack![Axis(0), test_input.clone().into_shape((1, 2)).unwrap(),
+                                    *train_inputs_y];
+            let n = train_inputs_y.rows() as f64;
+            let expected_scores = (0..all_inputs.rows()).map(|i| {
+                -all_inputs.outer_iter()
+                           .enumerate()
+                           .filter(|&(j, _)| j != i)
+                           .map(|(_, x)| {
+                               let u = euclidean_distance(&x, &all_inputs.row(i)) / 0.8;
+                               (1. - u * u).max(0.)
+                           })
+                           .sum::<f64>() / n
+            }).collect::<Vec<_>>();
+
+            let scores = kde.scores(&test_input.view(), y).unwrap();
+            for (s, e) in scores.iter().zip(&expected_scores) {
+                assert_relative_eq!(*s, *e, epsilon = 1e-12);
+            }
+            assert_relative_eq!(kde.score(&test_input.view(), y).unwrap(),
+                                expected_scores[0]);
+        }
+
+        // ICP.
+        let mut kde = KDE::new(Kernel::Tophat, Bandwidth::Fixed(1.));
+        kde.train(&array![[0.], [0.5], [3.]].view(), &array![0, 0, 0].view(), 1)
+           .expect("Failed to train model");
+        kde.calibrate(&array![[2.], [0.2]].view(), &array![0, 0].view())
+           .expect("Failed to calibrate model");
+        assert!(kde.scores(&array![1.].view(), 0).unwrap() == vec![-1./3., -2./3., 0.]);
+    }
+
+    /// Verify that a stored KDE is loaded, and that invalid bandwidths
+    /// are rejected.
+    #[test]
+    fn serialization() {
+        let mut kde = KDE::new(Kernel::Tophat, Bandwidth::Fixed(1.));
+        kde.train(&array![[0.], [0.5], [3.]].view(), &array![0, 0, 0].view(), 1)
+           .expect("Failed to train model");
+        let loaded: KDE<f64> = serde_json::from_str(&serde_json::to_string(&kde).unwrap())
+                                          .unwrap();
+        assert!(loaded.score(&array![1.].view(), 0).unwrap() == -1./3.);
+
+        let train_inputs = r#""n_labels":1,"train_inputs":[{"v":1,"dim":[1,1],"data":[0.0]}],
+                              "calibration_inputs":null"#;
+        let invalid = [format!(r#"{{"kernel":"Gaussian","bandwidth":{{"Fixed":-1.0}},"h":1.0,{}}}"#,
+                               train_inputs),
+                       format!(r#"{{"kernel":"Gaussian","bandwidth":"Scott","h":0.0,{}}}"#,
+                               train_inputs),
+                       format!(r#"{{"kernel":"Gaussian","bandwidth":"Scott","h":null,{}}}"#,
+                               train_inputs)];
+        for state in &invalid {
+            assert!(serde_json::from_str::<KDE<f64>>(state).is_err());
+        }
+    }
+}
@@ -0,0 +1,236 @@
+//! KD-tree index for k-NN queries.
+//!
+//! The tree stores row indices of a matrix of points, which is passed
+//! to every method; rows may be appended to the matrix and then
+//! inserted into the tree with `insert()`.
+//! Queries are exact: they return the same distances as a brute-force
+//! search, provided that the distance is a Minkowski distance
+//! (for which the distance along one coordinate is a lower bound of
+//! the distance between two points).
+use ndarray::prelude::*;
+
+use ncm::distance::Feature;
+use ncm::knn::insert_distance;
+
+
+/// Relative tolerance for pruning a subtree, so that rounding errors
+/// in the computation of distances cannot exclude a nearest neighbor.
+const PRUNE_TOLERANCE: f64 = 1e-9;
+
+/// A node of the tree, splitting its subtrees according to the value
+/// of its point on coordinate `axis`.
+struct Node {
+    index: usize,
+    axis: usize,
+    left: Option<usize>,
+    right: Option<usize>,
+}
+
+/// A k-NN query, with the arguments of `KDTree::nearest()`.
+struct Query<'a, 'b: 'a, T: 'b> {
+    points: &'a Array2<T>,
+    x: &'a ArrayView1<'b, T>,
+    k: usize,
+    distance: &'a dyn Fn(&ArrayView1<T>, &ArrayView1<T>) -> f64,
+    exclude: Option<usize>,
+}
+
+/// A KD-tree over the rows of a matrix.
+pub(crate) struct KDTree {
+    nodes: Vec<Node>,
+    root: Option<usize>,
+}
+
+impl KDTree {
+    /// Builds a balanced KD-tree over all the rows of `points`.
+    ///
+    /// # Arguments
+    ///
+    /// * `points` - Matrix (Array2<T>) whose rows are the points,
+    ///              with at least one column.
+    pub fn build<T: Feature>(points: &Array2<T>) -> KDTree {
+        assert!(points.cols() > 0);
+
+        let mut tree = KDTree {
+            nodes: Vec::with_capacity(points.rows()),
+            root: None,
+        };
+        let mut indices = (0..points.rows()).collect::<Vec<_>>();
+        tree.root = tree.build_subtree(points, &mut indices, 0);
+
+        tree
+    }
+
+    /// Builds the subtree of `indices` by splitting them on their
+    /// median, and returns its root.
+    fn build_subtree<T: Feature>(&mut self, points: &Array2<T>, indices: &mut [usize],
+                                 depth: usize) -> Option<usize> {
+        if indices.is_empty() {
+            return None;
+        }
+
+        let axis = depth % points.cols();
+        indices.sort_by(|&i, &j| points[[i, axis]].to_f64()
+                                                  .partial_cmp(&points[[j, axis]].to_f64())
+                                                  .expect("Inputs cannot be NaN"));
+        let mid = indices.len() / 2;
+
+        let node = self.nodes.len();
+        self.nodes.push(Node {
+            index: indices[mid],
+            axis: axis,
+            left: None,
+            right: None,
+        });
+        let (left, right) = indices.split_at_mut(mid);
+        self.nodes[node].left = self.build_subtree(points, left, depth + 1);
+        self.nodes[node].right = self.build_subtree(points, &mut right[1..], depth + 1);
+
+        Some(node)
+    }
+
+    /// Inserts the `index`-th row of `points` into the tree.
+    ///
+    /// # Arguments
+    ///
+    /// * `points` - Matrix (Array2<T>) whose rows are the points.
+    /// * `index` - Row of `points` to insert.
+    pub fn insert<T: Feature>(&mut self, points: &Array2<T>, index: usize) {
+        let new_node = self.nodes.len();
+
+        let mut node = match self.root {
+            Some(root) => root,
+            None => {
+                self.nodes.push(Node { index: index, axis: 0, left: None, right: None });
+                self.root = Some(new_node);
+                return;
+            },
+        };
+
+        loop {
+            let (axis, split) = (self.nodes[node].axis, self.nodes[node].index);
+            let child = if points[[index, axis]].to_f64() < points[[split, axis]].to_f64() {
+                &mut self.nodes[node].left
+            } else {
+                &mut self.nodes[node].right
+            };
+            match *child {
+                Some(c) => node = c,
+                None => {
+                    *child = Some(new_node);
+                    break;
+                },
+            }
+        }
+
+        let axis = (self.nodes[node].axis + 1) % points.cols();
+        self.nodes.push(Node { index: index, axis: axis, left: None, right: None });
+    }
+
+    /// Returns the distances of `x` from its `k` nearest points,
+    /// sorted in ascending order.
+    ///
+    /// # Arguments
+    ///
+    /// * `points` - Matrix (Array2<T>) whose rows are the points.
+    /// * `x` - Query point.
+    /// * `k` - Number of nearest neighbors.
+    /// * `distance` - Minkowski distance, called as `distance(point, x)`.
+    /// * `exclude` - Row of `points` to ignore, if any (e.g., `x` itself).
+    pub fn nearest<T: Feature>(&self, points: &Array2<T>, x: &ArrayView1<T>, k: usize,
+                               distance: &dyn Fn(&ArrayView1<T>, &ArrayView1<T>) -> f64,
+                               exclude: Option<usize>) -> Vec<f64> {
+        let query = Query {
+            points: points,
+            x: x,
+            k: k,
+            distance: distance,
+            exclude: exclude,
+        };
+        let mut neighbors = Vec::with_capacity(k + 1);
+        if let (Some(root), true) = (self.root, k > 0) {
+            self.search(root, &query, &mut neighbors);
+        }
+
+        neighbors
+    }
+
+    /// Visits the subtree of `node`, keeping in `neighbors` the `k`
+    /// smallest distances found so far.
+    fn search<T: Feature>(&self, node: usize, query: &Query<T>, neighbors: &mut Vec<f64>) {
+        let node = &self.nodes[node];
+        let point = query.points.row(node.index);
+        let k = query.k;
+
+        if query.exclude != Some(node.index) {
+            insert_distance(neighbors, (query.distance)(&point, query.x), k);
+        }
+
+        let diff = query.x[node.axis].to_f64() - point[node.axis].to_f64();
+        let (near, far) = if diff < 0. {
+            (node.left, node.right)
+        } else {
+            (node.right, node.left)
+        };
+
+        if let Some(near) = near {
+            self.search(near, query, neighbors);
+        }
+        // The points in the far subtree are at least |diff| away from x.
+        if let Some(far) = far {
+            if neighbors.len() < k || diff.abs() <= neighbors[k-1] * (1. + PRUNE_TOLERANCE) {
+                self.search(far, query, neighbors);
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lazysort::SortedPartial;
+    use ncm::distance::*;
+
+    /// Verify that the nearest neighbors found by a KD-tree, built
+    /// in one go or by insertion, are those found by brute force.
+    #[test]
+    fn nearest() {
+        // Rounded coordinates, so that there are ties.
+        let points = Array::from_shape_fn((60, 3), |(i, j)| {
+            (((i * (j + 3)) as f64).sin() * 10.).round() / 10.
+        });
+        let queries = Array::from_shape_fn((10, 3), |(i, j)| ((i * 7 + j) as f64).cos());
+        let distances: Vec<Distance<f64>> = vec![Distance::Euclidean, Distance::Manhattan,
+                                                 Distance::Chebyshev, Distance::Minkowski(3.)];
+
+        let built = KDTree::build(&points);
+        let mut inserted = KDTree::build(&points.slice(s![..1, ..]).to_owned());
+        for i in 1..points.rows() {
+            inserted.insert(&points, i);
+        }
+
+        for distance in distances {
+            let dist = |v1: &ArrayView1<f64>, v2: &ArrayView1<f64>| distance.compute(v1, v2, None);
+            for k in 0..5 {
+                for x in queries.outer_iter().chain(points.outer_iter().take(5)) {
+                    let expected = points.outer_iter()
+                                         .map(|p| dist(&p, &x))
+                                         .sorted_partial_last()
+                                         .take(k)
+                                         .collect::<Vec<_>>();
+                    assert!(built.nearest(&points, &x, k, &dist, None) == expected);
+                    assert!(inserted.nearest(&points, &x, k, &dist, None) == expected);
+                }
+                // Excluding a point.
+                let expected = points.outer_iter()
+                                     .skip(1)
+                                     .map(|p| dist(&p, &points.row(0)))
+                                     .sorted_partial_last()
+                                     .take(k)
+                                     .collect::<Vec<_>>();
+                assert!(built.nearest(&points, &points.row(0), k, &dist, Some(0)) == expected);
+            }
+        }
+    }
+}
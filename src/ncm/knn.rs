@@ -1,29 +1,28 @@
 //! k-NN nonconformity measure.
 use std::f64;
 use std::cmp::min;
+use std::str::FromStr;
+use std::sync::OnceLock;
 use lazysort::SortedPartial;
 use ndarray::prelude::*;
-use rusty_machine::learning::LearningResult;
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::de::Error as DeError;
 
-use ncm::NonconformityScorer;
-
-
-/// Returns the Euclidean distance between two vectors of f64 values.
-fn euclidean_distance(v1: &ArrayView1<f64>, v2: &ArrayView1<f64>) -> f64 {
-    v1.iter()
-      .zip(v2.iter())
-      .map(|(x,y)| (x - y).powi(2))
-      .sum::<f64>()
-      .sqrt()
-}
+use error::{Error, Result, check_lengths, check_labels, check_nan};
+use ncm::{NonconformityScorer, SortedCalibration, check_dimension, inputs_y, visit_inputs};
+use ncm::distance::{Distance, Feature, inverse_covariance};
+use ncm::kdtree::KDTree;
 
 /// Splits inputs according to their labels.
 ///
 /// Returns as output a `train_inputs: Vec<Array2<T>>`, such that for each
 /// unique label `y`, `train_inputs[y]` contains a matrix with the inputs with
 /// label `y`.
-fn split_inputs<T>(inputs: &ArrayView2<T>, targets: &ArrayView1<usize>,
-         n_labels: usize) -> Vec<Array2<T>> where T: Clone + Sync + Copy {
+pub(crate) fn split_inputs<T>(inputs: &ArrayView2<T>, targets: &ArrayView1<usize>,
+         n_labels: usize) -> Result<Vec<Array2<T>>> where T: Clone + Sync + Copy {
+    check_lengths(inputs.rows(), targets.len())?;
+    check_labels(targets, n_labels)?;
+
     // Split examples w.r.t. their labels. For each unique label y,
     // train_inputs[y] will contain a matrix with the inputs with
     // label y.
@@ -33,7 +32,6 @@ fn split_inputs<T>(inputs: &ArrayView2<T>, targets: &ArrayView1<usize>,
     let mut train_inputs_vec = vec![vec![]; n_labels];
 
     for (x, y) in inputs.outer_iter().zip(targets) {
-        // Implicitly asserts that 0 <= y < self.n_labels.
         train_inputs_vec[*y].extend(x.iter());
     }
 
@@ -43,29 +41,135 @@ fn split_inputs<T>(inputs: &ArrayView2<T>, targets: &ArrayView1<usize>,
     let mut train_inputs = vec![];
     for inputs_y in train_inputs_vec {
         let n = inputs_y.len() / d;
-        train_inputs.push(Array::from_shape_vec((n, d), inputs_y)
-                                .expect("Unexpected error in reshaping"));
+        train_inputs.push(Array::from_shape_vec((n, d), inputs_y)?);
+    }
+
+    Ok(train_inputs)
+}
+
+/// Returns, for each row of `inputs`, the distances from its `k` nearest
+/// neighbors among the other rows, sorted in ascending order.
+///
+/// If a KD-tree `index` of the rows is given, it is used to find the
+/// neighbors.
+fn neighbor_distances<T: Feature>(inputs: &Array2<T>, k: usize,
+                                  distance: &dyn Fn(&ArrayView1<T>, &ArrayView1<T>) -> f64,
+                                  index: Option<&KDTree>) -> Vec<Vec<f64>> {
+    if let Some(index) = index {
+        return inputs.outer_iter()
+                     .enumerate()
+                     .map(|(i, input)| index.nearest(inputs, &input, k, distance, Some(i)))
+                     .collect();
     }
 
-    train_inputs
+    inputs.outer_iter()
+          .enumerate()
+          .map(|(i, input)| inputs.outer_iter()
+                                  .enumerate()
+                                  .filter(|&(j, _)| j != i)
+                                  .map(|(_, x)| distance(&x, &input))
+                                  .sorted_partial_last()
+                                  .take(k)
+                                  .collect())
+          .collect()
+}
+
+/// Returns true if any of `values` is NaN.
+fn has_nan<'a, T, I>(values: I) -> bool
+        where T: Feature + 'a, I: IntoIterator<Item=&'a T> {
+    values.into_iter().any(|v| v.to_f64().is_nan())
+}
+
+/// Inserts distance `d` into a sorted list of neighbor distances,
+/// keeping at most `k` of them.
+pub(crate) fn insert_distance(neighbors: &mut Vec<f64>, d: f64, k: usize) {
+    let pos = neighbors.partition_point(|v| *v <= d);
+    if pos < k {
+        neighbors.insert(pos, d);
+        neighbors.truncate(k);
+    }
+}
+
+/// How a k-NN nonconformity measure combines the distances of an
+/// example $(x, y)$ from its nearest neighbors.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum KNNMode {
+    /// Sum of the distances from the $k$ nearest neighbors with
+    /// label $y$ (default).
+    #[default]
+    Sum,
+    /// Sum of the distances from the $k$ nearest neighbors with
+    /// label $y$, divided by the sum of the distances from the $k$
+    /// nearest neighbors with any other label.
+    Ratio,
+    /// As `Ratio`, but the distance from the $i$-th nearest neighbor
+    /// is weighted by $1/i$ in both sums, so that closer neighbors
+    /// matter more.
+    WeightedRatio,
+}
+
+impl FromStr for KNNMode {
+    type Err = Error;
+
+    /// Parses a mode from its lowercase name ("sum", "ratio" or
+    /// "weighted-ratio").
+    fn from_str(s: &str) -> Result<KNNMode> {
+        match s {
+            "sum" => Ok(KNNMode::Sum),
+            "ratio" => Ok(KNNMode::Ratio),
+            "weighted-ratio" => Ok(KNNMode::WeightedRatio),
+            _ => Err(Error::Format(format!("unknown k-NN mode {}", s))),
+        }
+    }
 }
 
 /// A k-NN nonconformity measure.
 ///
 /// The score is defined for some distance metric and number of
-/// neighbors.
+/// neighbors, and it is computed according to a `KNNMode`.
+/// In `Ratio` modes, if there are no examples with other labels
+/// the score is the (weighted) sum of same-label distances; if
+/// the sum of other-label distances is 0, the score is infinite,
+/// or 1 if the same-label sum is also 0.
+/// Inputs can have any element type implementing `Feature` (e.g.,
+/// `f32`, integers or `bool`).
+///
+/// For Minkowski distances (e.g., Euclidean), the nearest neighbors
+/// of an object are found via a KD-tree index of the training inputs,
+/// which is built by `train()` and extended by `update()`.
+/// The scores are the same as those obtained by brute-force search,
+/// which can be forced with `set_index(false)`.
 pub struct KNN<T: Sync> {
     k: usize,
-    distance: fn(&ArrayView1<T>, &ArrayView1<T>) -> f64,
+    distance: Distance<T>,
+    mode: KNNMode,
+    // Inverse covariance matrix of the training inputs, only
+    // estimated for the Mahalanobis distance.
+    inv_covariance: Option<Array2<f64>>,
+    use_index: bool,
+    // If use_index is set and the distance is a Minkowski distance,
+    // index[y] is a KD-tree of train_inputs[y].
+    index: Option<Vec<KDTree>>,
     n_labels: Option<usize>,
     // Training inputs are stored in a train_inputs, indexed
     // by a label y, where train_inputs[y] contains all training
     // inputs with label y.
     train_inputs: Option<Vec<Array2<T>>>,
-    // Calibration inputs are optional. If set, then the
-    // NCM is trained on train_inputs, and the scores are
-    // computed on calibration_inputs.
-    calibration_inputs: Option<Vec<Array2<T>>>,
+    // Calibration examples are optional. If set, then the
+    // NCM is trained on train_inputs, and the scores of the
+    // calibration examples are computed once when calibrating.
+    calibration: Option<SortedCalibration<T>>,
+    // For transductive CP: neighbor_distances[y][i] contains the
+    // (ascending) distances of train_inputs[y][i] from its k nearest
+    // neighbors in train_inputs[y]. Only computed if needed (i.e., on
+    // the first call to scores() for a TCP), and then kept up-to-date
+    // by update().
+    neighbor_distances: OnceLock<Vec<Vec<Vec<f64>>>>,
+    // For transductive CP in Ratio modes: other_distances[y][i]
+    // contains the (ascending) distances of train_inputs[y][i] from
+    // its k nearest neighbors with labels other than y. Computed and
+    // kept up-to-date as neighbor_distances.
+    other_distances: OnceLock<Vec<Vec<Vec<f64>>>>,
 }
 
 impl KNN<f64> {
@@ -84,18 +188,301 @@ impl KNN<f64> {
     /// let ncm = KNN::new(k);
     /// ```
     pub fn new(k: usize) -> KNN<f64> {
+        KNN::with_distance(k, Distance::Euclidean)
+    }
+}
+
+impl<T> KNN<T> where T: Feature {
+    /// Constructs a k-NN nonconformity measure with a given distance.
+    ///
+    /// For the Mahalanobis distance, the covariance matrix is estimated
+    /// from the inputs passed to `train()`, and it is not updated by
+    /// `update()`.
+    ///
+    /// # Arguments
+    ///
+    /// `k` - Number of nearest neighbors.
+    /// `distance` - Distance between input vectors, either a built-in
+    ///              one or `Distance::Custom()`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// #[macro_use(array)]
+    /// extern crate ndarray;
+    /// extern crate random_world;
+    ///
+    /// # fn main() {
+    /// use ndarray::prelude::*;
+    /// use random_world::cp::*;
+    /// use random_world::ncm::*;
+    ///
+    /// // k-NN CP on binary vectors with Hamming distance.
+    /// let ncm = KNN::with_distance(1, Distance::Hamming);
+    /// let mut cp = CP::new(ncm, 2, Some(0.3));
+    /// let train_inputs = array![[true, true, false],
+    ///                           [true, false, false],
+    ///                           [false, true, true],
+    ///                           [false, false, true]];
+    /// let train_targets = array![0, 0, 1, 1];
+    ///
+    /// cp.train(&train_inputs.view(), &train_targets.view())
+    ///   .expect("Failed to train");
+    /// let pvalues = cp.predict_confidence(&array![[true, true, true]].view())
+    ///                 .expect("Failed to predict");
+    /// assert!(pvalues == array![[1., 1.]]);
+    ///
+    /// // Custom distance on integer vectors.
+    /// fn max_abs(v1: &ArrayView1<i32>, v2: &ArrayView1<i32>) -> f64 {
+    ///     v1.iter().zip(v2).map(|(x, y)| (x - y).abs()).max().unwrap() as f64
+    /// }
+    /// let ncm = KNN::with_distance(2, Distance::Custom(max_abs));
+    /// # let _ = ncm;
+    /// # }
+    /// ```
+    pub fn with_distance(k: usize, distance: Distance<T>) -> KNN<T> {
+        if let Distance::Minkowski(p) = distance {
+            assert!(p >= 1., "Minkowski distance requires p >= 1");
+        }
+
         KNN {
             k: k,
-            distance: euclidean_distance,
+            distance: distance,
+            mode: KNNMode::Sum,
+            inv_covariance: None,
+            use_index: true,
+            index: None,
             train_inputs: None,
-            calibration_inputs: None,
+            calibration: None,
+            neighbor_distances: OnceLock::new(),
+            other_distances: OnceLock::new(),
             n_labels: None,
         }
     }
+
+    /// Sets whether nearest neighbors are searched via a KD-tree index
+    /// (default) or by brute force.
+    ///
+    /// The index is only used for Minkowski distances (Euclidean,
+    /// Manhattan, Chebyshev and Minkowski-p); brute force may be
+    /// faster for high-dimensional inputs.
+    ///
+    /// # Arguments
+    ///
+    /// * `use_index` - Whether to use a KD-tree index.
+    pub fn set_index(&mut self, use_index: bool) {
+        self.use_index = use_index;
+        self.build_index();
+    }
+
+    /// Sets how the distances from the nearest neighbors are combined
+    /// into a score (default: `KNNMode::Sum`).
+    ///
+    /// If the scorer was already calibrated, calibration scores are
+    /// recomputed.
+    ///
+    /// # Arguments
+    ///
+    /// * `mode` - k-NN mode.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// #[macro_use(array)]
+    /// extern crate ndarray;
+    /// extern crate random_world;
+    ///
+    /// # fn main() {
+    /// use random_world::ncm::*;
+    ///
+    /// let mut ncm = KNN::new(1);
+    /// ncm.set_mode(KNNMode::Ratio)
+    ///    .expect("Failed to set mode");
+    /// let train_inputs = array![[0.], [1.], [4.], [6.]];
+    /// let train_targets = array![0, 0, 1, 1];
+    /// ncm.train(&train_inputs.view(), &train_targets.view(), 2)
+    ///    .expect("Failed to train");
+    /// // Nearest same-label distance 1, nearest other-label distance 2.
+    /// assert!(ncm.score(&array![2.].view(), 0).unwrap() == 0.5);
+    /// # }
+    /// ```
+    pub fn set_mode(&mut self, mode: KNNMode) -> Result<()> {
+        self.mode = mode;
+        self.sort_calibration()
+    }
+
+    /// Builds the KD-tree index of the training inputs, if it should
+    /// be used, or drops it otherwise.
+    ///
+    /// Training inputs containing NaN values are not indexed, so that
+    /// predictions fail as they do with brute-force search.
+    fn build_index(&mut self) {
+        self.index = match self.train_inputs {
+            Some(ref train_inputs) if self.use_index && self.distance.is_minkowski() &&
+                                      train_inputs.iter().all(|x| x.cols() > 0) &&
+                                      !train_inputs.iter().any(|x| has_nan(x)) => {
+                Some(train_inputs.iter()
+                                 .map(KDTree::build)
+                                 .collect())
+            },
+            _ => None,
+        };
+    }
+
+    /// Returns the distance between two vectors.
+    fn distance(&self, v1: &ArrayView1<T>, v2: &ArrayView1<T>) -> f64 {
+        self.distance.compute(v1, v2, self.inv_covariance.as_ref())
+    }
+
+    /// Combines ascending neighbor distances (at most `k`) into a sum,
+    /// weighted according to the mode.
+    fn aggregate<I: IntoIterator<Item=f64>>(&self, distances: I) -> f64 {
+        match self.mode {
+            KNNMode::WeightedRatio => distances.into_iter()
+                                               .enumerate()
+                                               .map(|(i, d)| d / (i + 1) as f64)
+                                               .sum(),
+            _ => distances.into_iter().sum(),
+        }
+    }
+
+    /// Returns the score given the aggregated distances `same` from
+    /// the same-label neighbors, and the distances `other` from the
+    /// nearest neighbors with other labels (ignored in `Sum` mode).
+    fn combine(&self, same: f64, other: &[f64]) -> f64 {
+        if self.mode == KNNMode::Sum || other.is_empty() {
+            return same;
+        }
+        let other = self.aggregate(other.iter().cloned());
+        if other > 0. {
+            same / other
+        } else if same > 0. {
+            f64::INFINITY
+        } else {
+            1.
+        }
+    }
+
+    /// Returns the (ascending) distances of `x` from its `k` nearest
+    /// training inputs with labels other than `y`.
+    fn other_neighbors(&self, train_inputs: &[Array2<T>], x: &ArrayView1<T>, y: usize)
+            -> Vec<f64> {
+        let mut neighbors = Vec::with_capacity(self.k + 1);
+        for (y_, inputs_y_) in train_inputs.iter().enumerate().filter(|&(y_, _)| y_ != y) {
+            match self.index {
+                Some(ref index) => {
+                    let distances = index[y_].nearest(inputs_y_, x, self.k,
+                                                      &|v1, v2| self.distance(v1, v2), None);
+                    for d in distances {
+                        insert_distance(&mut neighbors, d, self.k);
+                    }
+                },
+                None => for x_ in inputs_y_.outer_iter() {
+                    insert_distance(&mut neighbors, self.distance(&x_, x), self.k);
+                },
+            }
+        }
+
+        neighbors
+    }
+
+    /// Returns the training inputs with label `y`, after checking that
+    /// the test object `x` can be compared to them.
+    fn train_inputs_y(&self, x: &ArrayView1<T>, y: usize) -> Result<&Array2<T>> {
+        let train_inputs = self.train_inputs.as_ref()
+                                            .ok_or(Error::NotTrained)?;
+        inputs_y(train_inputs, x, y)
+    }
+
+    /// Recomputes the nonconformity scores of the calibration inputs,
+    /// if any, which depend on the training data and on the mode.
+    fn sort_calibration(&mut self) -> Result<()> {
+        if let Some(calibration) = self.calibration.take() {
+            self.calibration = Some(calibration.rescore(|x, y| self.score(x, y))?);
+        }
+
+        Ok(())
+    }
+}
+
+/// Serialized representation of a k-NN nonconformity measure.
+///
+/// Calibration scores and neighbor distances are not stored, as they
+/// can be recomputed from the inputs.
+/// Models stored before distances (modes) could be chosen have no
+/// `distance` (`mode`) field, and use the Euclidean distance (`Sum`).
+/// A custom distance cannot be serialized.
+#[derive(Serialize, Deserialize)]
+struct KNNState<I, D> {
+    k: usize,
+    #[serde(default)]
+    distance: D,
+    #[serde(default)]
+    mode: KNNMode,
+    #[serde(default)]
+    inv_covariance: Option<Array2<f64>>,
+    n_labels: Option<usize>,
+    train_inputs: Option<I>,
+    calibration_inputs: Option<I>,
+}
+
+impl<T> Serialize for KNN<T> where T: Sync + Serialize {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        KNNState {
+            k: self.k,
+            distance: &self.distance,
+            mode: self.mode,
+            inv_covariance: self.inv_covariance.clone(),
+            n_labels: self.n_labels,
+            train_inputs: self.train_inputs.as_ref()
+                                           .map(|train_inputs| &train_inputs[..]),
+            calibration_inputs: self.calibration.as_ref()
+                                                .map(|calibration| calibration.inputs()),
+        }.serialize(serializer)
+    }
+}
+
+impl<'de, T> Deserialize<'de> for KNN<T> where T: Feature + Deserialize<'de> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<KNN<T>, D::Error> {
+        let state = KNNState::<Vec<Array2<T>>, Distance<T>>::deserialize(deserializer)?;
+        if let Distance::Minkowski(p) = state.distance {
+            if p.is_nan() || p < 1. {
+                return Err(D::Error::custom(format!("Minkowski distance requires p >= 1, got {}",
+                                                    p)));
+            }
+        }
+        // A trained Mahalanobis k-NN needs the inverse covariance matrix.
+        if let (&Distance::Mahalanobis, Some(train_inputs)) = (&state.distance,
+                                                               state.train_inputs.as_ref()) {
+            let d = train_inputs.first().map_or(0, |inputs| inputs.cols());
+            match state.inv_covariance {
+                Some(ref inv_covariance) if inv_covariance.dim() == (d, d) => {},
+                Some(ref inv_covariance) => {
+                    return Err(D::Error::custom(Error::DimensionMismatch {
+                        expected: d, found: inv_covariance.rows() }));
+                },
+                None => return Err(D::Error::custom("missing inverse covariance matrix")),
+            }
+        }
+
+        let mut knn = KNN::with_distance(state.k, state.distance);
+        knn.mode = state.mode;
+        knn.inv_covariance = state.inv_covariance;
+        knn.n_labels = state.n_labels;
+        knn.train_inputs = state.train_inputs;
+        knn.build_index();
+        if let Some(calibration_inputs) = state.calibration_inputs {
+            knn.calibration = Some(SortedCalibration::new(calibration_inputs,
+                                                          |x, y| knn.score(x, y))
+                                                     .map_err(D::Error::custom)?);
+        }
+
+        Ok(knn)
+    }
 }
 
 impl<T: Sync> NonconformityScorer<T> for KNN<T>
-        where T: Clone + Sync + Copy {
+        where T: Feature {
     /// Trains a k-NN nonconformity scorer.
     ///
     /// Note: `train()` should be only called once. To update the training
@@ -110,12 +497,16 @@ impl<T: Sync> NonconformityScorer<T> for KNN<T>
     ///               training vectors.
     /// * `n_labels` - Number of unique labels in the classification problem.
     fn train(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView1<usize>,
-             n_labels: usize) -> LearningResult<()> {
+             n_labels: usize) -> Result<()> {
         if self.train_inputs.is_some() {
-            panic!("Can only train once");
+            return Err(Error::AlreadyTrained);
         }
+        if let Distance::Mahalanobis = self.distance {
+            self.inv_covariance = Some(inverse_covariance(inputs)?);
+        }
+        self.train_inputs = Some(split_inputs(inputs, targets, n_labels)?);
         self.n_labels = Some(n_labels);
-        self.train_inputs = Some(split_inputs(inputs, targets, n_labels));
+        self.build_index();
 
         Ok(())
     }
@@ -129,12 +520,14 @@ impl<T: Sync> NonconformityScorer<T> for KNN<T>
     ///               training vectors.
     /// * `n_labels` - Number of unique labels in the classification problem.
     fn calibrate(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView1<usize>)
-             -> LearningResult<()> {
-        if self.train_inputs.is_none() {
-            panic!("Need to train before calibrate()-ing");
-        }
-        self.calibration_inputs = Some(split_inputs(inputs, targets,
-                                                    self.n_labels.unwrap()));
+             -> Result<()> {
+        let n_labels = self.n_labels.ok_or(Error::NotTrained)?;
+        check_dimension(&self.train_inputs, inputs.cols())?;
+        let calibration_inputs = split_inputs(inputs, targets, n_labels)?;
+
+        // Compute calibration scores once.
+        self.calibration = Some(SortedCalibration::new(calibration_inputs,
+                                                       |x, y| self.score(x, y))?);
 
         Ok(())
     }
@@ -152,24 +545,87 @@ impl<T: Sync> NonconformityScorer<T> for KNN<T>
     /// * `targets` - Vector (Array1<T>) of labels corresponding to the
     ///               training vectors.
     fn update(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView1<usize>)
-        -> LearningResult<()> {
+        -> Result<()> {
 
-        let train_inputs = match self.train_inputs {
-            Some(ref mut train_inputs) => train_inputs,
-            None => panic!("Call train() once before update()"),
-        };
+        let n_labels = self.n_labels.ok_or(Error::NotTrained)?;
+        check_dimension(&self.train_inputs, inputs.cols())?;
+        check_lengths(inputs.rows(), targets.len())?;
+        check_labels(targets, n_labels)?;
 
         // NOTE: when ndarray will have cheap concatenation, we
         // should iterate once through (inputs, targets) and just
         // append each (x, y) to the appropriate self.train_inputs[y].
         // The current method is less efficient than that.
         for (x, y) in inputs.outer_iter().zip(targets) {
+            // Update the other-label neighbor distances of TCP, if
+            // computed.
+            if self.other_distances.get().is_some() {
+                let train_inputs = self.train_inputs.as_ref()
+                                                    .ok_or(Error::NotTrained)?;
+                let neighbors = self.other_neighbors(train_inputs, &x, *y);
+                let other_distances = self.other_distances.get_mut().unwrap();
+                for (y_, inputs_y_) in train_inputs.iter().enumerate() {
+                    if y_ == *y {
+                        continue;
+                    }
+                    for (x_, neighbors_) in inputs_y_.outer_iter()
+                                                     .zip(other_distances[y_].iter_mut()) {
+                        insert_distance(neighbors_,
+                                        self.distance.compute(&x, &x_,
+                                                              self.inv_covariance.as_ref()),
+                                        self.k);
+                    }
+                }
+                other_distances[*y].push(neighbors);
+            }
+
+            let train_inputs = self.train_inputs.as_mut()
+                                                .ok_or(Error::NotTrained)?;
+            let (distance, inv_covariance) = (&self.distance, self.inv_covariance.as_ref());
+            // Update the neighbor distances of TCP, if computed.
+            if let Some(neighbor_distances) = self.neighbor_distances.get_mut() {
+                let mut neighbors = vec![];
+                for (x_, neighbors_) in train_inputs[*y].outer_iter()
+                                                        .zip(neighbor_distances[*y].iter_mut()) {
+                    insert_distance(neighbors_, distance.compute(&x, &x_, inv_covariance),
+                                    self.k);
+                    insert_distance(&mut neighbors, distance.compute(&x_, &x, inv_covariance),
+                                    self.k);
+                }
+                neighbor_distances[*y].push(neighbors);
+            }
             train_inputs[*y] = stack![Axis(0), train_inputs[*y],
-                                      x.clone().into_shape((1, x.len()))
+                                      x.into_shape((1, x.len()))
                                                .expect("Unexpected reshaping error")];
+            if has_nan(x) {
+                self.index = None;
+            }
+            if let Some(index) = self.index.as_mut() {
+                index[*y].insert(&train_inputs[*y], train_inputs[*y].rows() - 1);
+            }
         }
 
-        Ok(())
+        self.sort_calibration()
+    }
+
+    /// Updates a k-NN nonconformity scorer with more calibration data.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<T>) with values of type T of calibration
+    ///              vectors.
+    /// * `targets` - Vector (Array1<T>) of labels corresponding to the
+    ///               calibration vectors.
+    fn update_calibration(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView1<usize>)
+        -> Result<()> {
+
+        check_dimension(&self.train_inputs, inputs.cols())?;
+        let mut calibration = self.calibration.take()
+                                              .ok_or(Error::NotCalibrated)?;
+        let extended = calibration.extend(inputs, targets, |x, y| self.score(x, y));
+        self.calibration = Some(calibration);
+
+        extended
     }
     /// Computes the nonconformity scores of training inputs and of a new
     /// test example.
@@ -184,52 +640,162 @@ impl<T: Sync> NonconformityScorer<T> for KNN<T>
     ///
     /// * `x` - Test object.
     /// * `y` - (Candidate) label for the test object.
-    fn scores(&self, x: &ArrayView1<T>, y: usize) -> Vec<f64> {
-        let train_inputs = self.train_inputs.as_ref()
-                                            .expect("You should train the model first");
-        let train_inputs_y = &train_inputs[y];
+    fn scores(&self, x: &ArrayView1<T>, y: usize) -> Result<Vec<f64>> {
+        let train_inputs_y = self.train_inputs_y(x, y)?;
         let mut scores;
 
-        // ICP.
-        if let Some(calibration_inputs) = self.calibration_inputs.as_ref() {
-            //// TODO: update to new version of ndarray and rejoyce.
-            let test_inputs = stack![Axis(0), x.into_shape((1, train_inputs_y.cols()))
-                                               .expect("Unexpected error in reshaping"),
-                                     calibration_inputs[y].clone()];
-            scores = Vec::with_capacity(test_inputs.len());
-            let k = min(self.k, train_inputs_y.rows());
-            for input in test_inputs.outer_iter() {
-                let score = train_inputs_y.outer_iter()
-                                          .map(|x| (self.distance)(&x, &input))
-                                          .sorted_partial_last()
-                                          .take(k)
-                                          .sum::<f64>();
-                scores.push(score);
-            }
+        // ICP: calibration scores were computed when calibrating.
+        if let Some(calibration) = self.calibration.as_ref() {
+            scores = calibration.with_score(self.score(x, y)?, y)?;
         }
         // TCP.
+        // The score of a training example is computed from the distances
+        // of its k nearest neighbors, which are computed once and cached,
+        // and from its distance from the test object. This assumes the
+        // distance to be symmetric.
         else {
-            // XXX: once ndarray supports appending a row, we should
-            // append to the matrix rather than creating a new one.
-            let test_inputs = stack![Axis(0), x.into_shape((1, train_inputs_y.cols()))
-                                               .expect("Unexpected error in reshaping"),
-                                     train_inputs_y.clone()];
-            scores = Vec::with_capacity(test_inputs.len());
-            let k = min(self.k, test_inputs.rows()-1);
-            for i in 0..test_inputs.rows() {
-                let input = test_inputs.row(i);
-                let score = test_inputs.outer_iter()
-                                       .enumerate()
-                                       .filter(|&(j, _)| j != i)
-                                       .map(|(_, x)| (self.distance)(&x, &input))
-                                       .sorted_partial_last()
-                                       .take(k)
-                                       .sum::<f64>();
-                scores.push(score);
+            let train_inputs = self.train_inputs.as_ref()
+                                                .ok_or(Error::NotTrained)?;
+            let neighbor_distances = self.neighbor_distances.get_or_init(|| {
+                train_inputs.iter()
+                            .enumerate()
+                            .map(|(y, inputs_y)| {
+                                let index = self.index.as_ref()
+                                                      .map(|index| &index[y]);
+                                neighbor_distances(inputs_y, self.k,
+                                                   &|v1, v2| self.distance(v1, v2), index)
+                            })
+                            .collect()
+            });
+            // The other-label neighbors of training examples with label
+            // y do not depend on the test example.
+            let other_distances = match self.mode {
+                KNNMode::Sum => None,
+                _ => Some(self.other_distances.get_or_init(|| {
+                    train_inputs.iter()
+                                .enumerate()
+                                .map(|(y, inputs_y)| {
+                                    inputs_y.outer_iter()
+                                            .map(|x_| self.other_neighbors(train_inputs,
+                                                                           &x_, y))
+                                            .collect()
+                                })
+                                .collect()
+                })),
+            };
+            let k = min(self.k, train_inputs_y.rows());
+
+            // Distances of the test object from the training examples.
+            let distances = train_inputs_y.outer_iter()
+                                          .map(|x_| self.distance(&x_, x))
+                                          .collect::<Vec<_>>();
+            check_nan(&distances)?;
+            let other = match self.mode {
+                KNNMode::Sum => vec![],
+                _ => self.other_neighbors(train_inputs, x, y),
+            };
+            check_nan(&other)?;
+
+            scores = Vec::with_capacity(distances.len() + 1);
+            let same = self.aggregate(distances.iter()
+                                               .cloned()
+                                               .sorted_partial_last()
+                                               .take(k));
+            scores.push(self.combine(same, &other));
+
+            for (i, (d, neighbors)) in distances.into_iter()
+                                                .zip(&neighbor_distances[y])
+                                                .enumerate() {
+                // Combine the k smallest distances in ascending order.
+                let pos = neighbors.partition_point(|v| *v <= d);
+                let same = self.aggregate(neighbors[..pos].iter()
+                                                          .chain(Some(&d))
+                                                          .chain(neighbors[pos..].iter())
+                                                          .cloned()
+                                                          .take(k));
+                let other = other_distances.map_or(&[][..], |o| &o[y][i][..]);
+                scores.push(self.combine(same, other));
             }
         }
 
-        scores
+        Ok(scores)
+    }
+
+    /// Computes the nonconformity score of a new example with respect
+    /// to the (proper) training set.
+    ///
+    /// In `Sum` mode, the score is the sum of the distances of `x`
+    /// from its `k` nearest neighbors with label `y` in the training
+    /// set; see `KNNMode` for the other modes.
+    ///
+    /// # Arguments
+    ///
+    /// * `x` - Test object.
+    /// * `y` - (Candidate) label for the test object.
+    fn score(&self, x: &ArrayView1<T>, y: usize) -> Result<f64> {
+        let train_inputs_y = self.train_inputs_y(x, y)?;
+        let k = min(self.k, train_inputs_y.rows());
+
+        let same = if let Some(index) = self.index.as_ref() {
+            let distances = index[y].nearest(train_inputs_y, x, k,
+                                             &|v1, v2| self.distance(v1, v2), None);
+            check_nan(&distances)?;
+
+            self.aggregate(distances)
+        } else {
+            let distances = train_inputs_y.outer_iter()
+                                          .map(|x_| self.distance(&x_, x))
+                                          .collect::<Vec<_>>();
+            check_nan(&distances)?;
+
+            self.aggregate(distances.into_iter()
+                                    .sorted_partial_last()
+                                    .take(k))
+        };
+
+        if self.mode == KNNMode::Sum {
+            return Ok(same);
+        }
+        let train_inputs = self.train_inputs.as_ref()
+                                            .ok_or(Error::NotTrained)?;
+        let other = self.other_neighbors(train_inputs, x, y);
+        check_nan(&other)?;
+
+        Ok(self.combine(same, &other))
+    }
+
+    /// Returns the nonconformity scores of the calibration examples
+    /// with label `y`, sorted in ascending order.
+    ///
+    /// Returns None if the scorer was not calibrated.
+    ///
+    /// # Arguments
+    ///
+    /// * `y` - (Candidate) label for the test object.
+    fn calibration_scores(&self, y: usize) -> Option<&[f64]> {
+        self.calibration.as_ref()
+                        .and_then(|calibration| calibration.scores(y))
+    }
+
+    /// Visits the examples whose nonconformity scores are returned
+    /// by `scores()`.
+    ///
+    /// These are the calibration examples with label `y` for an ICP,
+    /// and the training examples with label `y` otherwise.
+    ///
+    /// # Arguments
+    ///
+    /// * `y` - (Candidate) label for the test object.
+    /// * `visit` - Function called on each example.
+    fn visit_examples(&self, y: usize, visit: &mut dyn FnMut(&ArrayView1<T>, usize))
+                      -> Result<()> {
+        let inputs = match self.calibration {
+            Some(ref calibration) => calibration.inputs(),
+            None => self.train_inputs.as_ref()
+                                     .ok_or(Error::NotTrained)?,
+        };
+
+        visit_inputs(inputs, y, visit)
     }
 }
 
@@ -237,6 +803,7 @@ impl<T: Sync> NonconformityScorer<T> for KNN<T>
 #[cfg(test)]
 mod tests {
     use super::*;
+    use ncm::distance::euclidean_distance;
 
     /// Verify that KNN training succeeds properly (i.e., it
     /// correctly splits training inputs per label).
@@ -327,8 +894,229 @@ mod tests {
 
         knn.train(&train_inputs.view(), &train_targets.view(), n_labels)
            .expect("Failed to train k-NN ncm");
-        let scores = knn.scores(&test_input.view(), test_target);
+        let scores = knn.scores(&test_input.view(), test_target)
+                        .expect("Failed to compute scores");
 
         assert!(scores == expected_scores);
     }
+
+    /// Verify that TCP scores computed from the cached neighbor
+    /// distances, also after update(), are identical to those computed
+    /// from all the pairwise distances.
+    #[test]
+    fn incremental_tcp() {
+        let k = 3;
+        let n_labels = 2;
+        let inputs = Array::from_shape_fn((40, 2), |(i, j)| ((i * (j + 2)) as f64).sin());
+        let targets = Array::from_shape_fn(40, |i| i % n_labels);
+        let test_input = array![0.5, -0.5];
+
+        let mut knn = KNN::new(k);
+        knn.train(&inputs.slice(s![..20, ..]), &targets.slice(s![..20]), n_labels)
+           .expect("Failed to train model");
+        // Compute neighbor distances before updating.
+        knn.scores(&test_input.view(), 0)
+           .expect("Failed to compute scores");
+        knn.update(&inputs.slice(s![20.., ..]), &targets.slice(s![20..]))
+           .expect("Failed to update model");
+
+        for y in 0..n_labels {
+            // Brute-force scores.
+            let train_inputs_y = &knn.train_inputs.as_ref().unwrap()[y];
+            let all_inputs = stack![Axis(0), test_input.clone().into_shape((1, 2)).unwrap(),
+                                    *train_inputs_y];
+            let expected_scores = (0..all_inputs.rows()).map(|i| {
+                all_inputs.outer_iter()
+                          .enumerate()
+                          .filter(|&(j, _)| j != i)
+                          .map(|(_, x)| euclidean_distance(&x, &all_inputs.row(i)))
+                          .sorted_partial_last()
+                          .take(k)
+                          .sum::<f64>()
+            }).collect::<Vec<_>>();
+
+            assert!(knn.scores(&test_input.view(), y).unwrap() == expected_scores);
+        }
+    }
+
+    /// Verify that scores computed via the KD-tree index, also after
+    /// update(), are identical to those computed by brute force.
+    #[test]
+    fn index() {
+        let k = 3;
+        let n_labels = 2;
+        let inputs = Array::from_shape_fn((60, 3), |(i, j)| ((i * (j + 2)) as f64).sin());
+        let targets = Array::from_shape_fn(60, |i| i % n_labels);
+        let test_inputs = Array::from_shape_fn((5, 3), |(i, j)| ((i + j) as f64).cos());
+
+        for distance in [Distance::Euclidean, Distance::Manhattan, Distance::Chebyshev] {
+            let mut knn = KNN::with_distance(k, distance.clone());
+            let mut brute = KNN::with_distance(k, distance);
+            brute.set_index(false);
+            for ncm in [&mut knn, &mut brute] {
+                ncm.train(&inputs.slice(s![..20, ..]), &targets.slice(s![..20]), n_labels)
+                   .expect("Failed to train model");
+                ncm.update(&inputs.slice(s![20.., ..]), &targets.slice(s![20..]))
+                   .expect("Failed to update model");
+            }
+            assert!(knn.index.is_some() && brute.index.is_none());
+
+            for (x, y) in test_inputs.outer_iter().zip(0..) {
+                let y = y % n_labels;
+                assert!(knn.score(&x, y).unwrap() == brute.score(&x, y).unwrap());
+                assert!(knn.scores(&x, y).unwrap() == brute.scores(&x, y).unwrap());
+            }
+        }
+    }
+
+    /// Verify the scores of Ratio modes, and that TCP scores computed
+    /// from the cached neighbor distances, also after update(), are
+    /// the scores of each example w.r.t. all the other ones.
+    #[test]
+    fn modes() {
+        let mut knn = KNN::new(2);
+        knn.set_mode(KNNMode::WeightedRatio).unwrap();
+        knn.train(&array![[0.], [1.], [3.], [5.], [6.]].view(),
+                  &array![0, 0, 0, 1, 1].view(), 2).unwrap();
+        // Same-label distances (1, 1), other-label distances (3, 4).
+        assert_relative_eq!(knn.score(&array![2.].view(), 0).unwrap(), 1.5 / 5.);
+        assert!("weighted-ratio".parse::<KNNMode>().unwrap() == KNNMode::WeightedRatio);
+        assert!("median".parse::<KNNMode>().is_err());
+
+        let k = 3;
+        let n_labels = 3;
+        let inputs = Array::from_shape_fn((45, 2), |(i, j)| ((i * (j + 2)) as f64).sin());
+        let targets = Array::from_shape_fn(45, |i| (i / 2) % n_labels);
+        let test_input = array![0.5, -0.5];
+
+        for &mode in &[KNNMode::Ratio, KNNMode::WeightedRatio] {
+            for &use_index in &[true, false] {
+                let mut knn = KNN::new(k);
+                knn.set_mode(mode).unwrap();
+                knn.set_index(use_index);
+                knn.train(&inputs.slice(s![..20, ..]), &targets.slice(s![..20]), n_labels)
+                   .expect("Failed to train model");
+                // Compute neighbor distances before updating.
+                knn.scores(&test_input.view(), 0)
+                   .expect("Failed to compute scores");
+                knn.update(&inputs.slice(s![20.., ..]), &targets.slice(s![20..]))
+                   .expect("Failed to update model");
+
+                for y in 0..n_labels {
+                    let mut expected = vec![knn.score(&test_input.view(), y).unwrap()];
+                    let mut examples = vec![];
+                    knn.visit_examples(y, &mut |x, _| examples.push(x.to_owned())).unwrap();
+                    for x in &examples {
+                        // Leave x out, and add the test example.
+                        let mut loo = KNN::new(k);
+                        loo.set_mode(mode).unwrap();
+                        let (mut loo_inputs, mut loo_targets) = (vec![], vec![]);
+                        loo_inputs.extend(test_input.iter());
+                        loo_targets.push(y);
+                        for (x_, y_) in inputs.outer_iter().zip(targets.iter()) {
+                            if x_ != x.view() {
+                                loo_inputs.extend(x_.iter());
+                                loo_targets.push(*y_);
+                            }
+                        }
+                        let loo_inputs = Array::from_shape_vec((loo_targets.len(), 2),
+                                                               loo_inputs).unwrap();
+                        loo.train(&loo_inputs.view(), &Array::from_vec(loo_targets).view(),
+                                  n_labels).unwrap();
+                        expected.push(loo.score(&x.view(), y).unwrap());
+                    }
+
+                    let scores = knn.scores(&test_input.view(), y).unwrap();
+                    assert!(scores.len() == expected.len());
+                    for (score, expected) in scores.iter().zip(expected) {
+                        assert_relative_eq!(*score, expected, epsilon = 1e-12);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Verify that KNN scores use the chosen distance, also on
+    /// non-f64 inputs.
+    #[test]
+    fn distances() {
+        let train_inputs = array![[0, 0],
+                                  [3, 4],
+                                  [1, 0]];
+        let train_targets = array![0, 0, 0];
+        let test_input = array![0, 1];
+
+        let mut knn = KNN::with_distance(1, Distance::Manhattan);
+        knn.train(&train_inputs.view(), &train_targets.view(), 1).unwrap();
+        assert!(knn.scores(&test_input.view(), 0).unwrap() == vec![1., 1., 6., 1.]);
+
+        let mut knn = KNN::with_distance(1, Distance::Chebyshev);
+        knn.train(&train_inputs.view(), &train_targets.view(), 1).unwrap();
+        assert!(knn.scores(&test_input.view(), 0).unwrap() == vec![1., 1., 3., 1.]);
+
+        let train_inputs = array![[true, false], [true, true]];
+        let mut knn = KNN::with_distance(1, Distance::Hamming);
+        knn.train(&train_inputs.view(), &array![0, 0].view(), 1).unwrap();
+        assert!(knn.score(&array![false, false].view(), 0).unwrap() == 1.);
+
+        fn constant(_: &ArrayView1<f32>, _: &ArrayView1<f32>) -> f64 { 2. }
+        let mut knn = KNN::with_distance(2, Distance::Custom(constant));
+        knn.train(&array![[0f32], [1.], [2.]].view(), &array![0, 0, 0].view(), 1).unwrap();
+        assert!(knn.scores(&array![5f32].view(), 0).unwrap() == vec![4., 4., 4., 4.]);
+    }
+
+    /// Verify that the covariance of the Mahalanobis distance is
+    /// estimated at train time, and that it is kept when storing and
+    /// loading the scorer.
+    #[test]
+    fn mahalanobis() {
+        let train_inputs = array![[1., 2.],
+                                  [3., 2.],
+                                  [1., 6.],
+                                  [3., 6.]];
+        let train_targets = array![0, 0, 0, 0];
+        let test_input = array![1., 4.];
+
+        let mut knn = KNN::with_distance(1, Distance::Mahalanobis);
+        knn.train(&train_inputs.view(), &train_targets.view(), 1).unwrap();
+        // Inverse covariance: diag(3/4, 3/16).
+        assert_relative_eq!(knn.score(&test_input.view(), 0).unwrap(), 0.75f64.sqrt());
+        knn.update(&array![[10., 10.]].view(), &array![0].view()).unwrap();
+        assert_relative_eq!(knn.score(&test_input.view(), 0).unwrap(), 0.75f64.sqrt());
+
+        let stored = serde_json::to_string(&knn).unwrap();
+        let loaded: KNN<f64> = serde_json::from_str(&stored).unwrap();
+        assert_relative_eq!(loaded.score(&test_input.view(), 0).unwrap(), 0.75f64.sqrt());
+
+        // Custom distances cannot be stored.
+        let knn = KNN::with_distance(1, Distance::Custom(euclidean_distance::<f64>));
+        assert!(serde_json::to_string(&knn).is_err());
+
+        // Models without a distance use the Euclidean one.
+        let loaded: KNN<f64> = serde_json::from_str(r#"{"k":1,"n_labels":1,
+            "train_inputs":[{"v":1,"dim":[2,1],"data":[0.0,2.0]}],
+            "calibration_inputs":null}"#).unwrap();
+        assert!(loaded.score(&array![3.].view(), 0).unwrap() == 1.);
+
+        // Invalid models are rejected.
+        let train_inputs = r#""train_inputs":[{"v":1,"dim":[2,2],"data":[0.0,2.0,1.0,1.0]}]"#;
+        let invalid = [format!(r#"{{"k":1,"distance":{{"Minkowski":0.5}},"n_labels":1,{},
+                                    "calibration_inputs":null}}"#, train_inputs),
+                       format!(r#"{{"k":1,"distance":"Mahalanobis","n_labels":1,{},
+                                    "calibration_inputs":null}}"#, train_inputs),
+                       format!(r#"{{"k":1,"distance":"Mahalanobis","n_labels":1,{},
+                                    "inv_covariance":{{"v":1,"dim":[1,1],"data":[1.0]}},
+                                    "calibration_inputs":null}}"#, train_inputs)];
+        for state in &invalid {
+            assert!(serde_json::from_str::<KNN<f64>>(state).is_err());
+        }
+
+        // The mode is stored.
+        let mut knn = KNN::new(1);
+        knn.set_mode(KNNMode::Ratio).unwrap();
+        knn.train(&array![[0.], [4.]].view(), &array![0, 1].view(), 2).unwrap();
+        let loaded: KNN<f64> = serde_json::from_str(&serde_json::to_string(&knn).unwrap())
+                                          .unwrap();
+        assert!(loaded.score(&array![1.].view(), 0).unwrap() == 1. / 3.);
+    }
 }
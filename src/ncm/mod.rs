@@ -4,12 +4,215 @@
 //! A `NonconformityScorer<T>` implements a nonconformity measure `score()`,
 //! which determines how "strange" a new input vector looks like with
 //! respect to previously observed ones.
+//! A `RegressionScorer<T>` is its counterpart for real-valued targets.
+pub mod aps;
+pub mod classifier;
+pub mod distance;
+mod kdtree;
+pub mod kde;
 pub mod knn;
+pub mod precomputed;
+pub mod residual;
 
 use ndarray::prelude::*;
-use rusty_machine::learning::LearningResult;
+use error::{Error, Result, check_lengths, check_labels, check_nan};
 
-pub use self::knn::KNN;
+pub use self::aps::RAPS;
+pub use self::classifier::{ClassifierScorer, ScoringClassifier};
+pub use self::distance::{Distance, Feature};
+pub use self::kde::{KDE, Kernel, Bandwidth};
+pub use self::knn::{KNN, KNNMode};
+pub use self::precomputed::PrecomputedScores;
+pub use self::residual::{AbsoluteResidual, SignedResidual};
+
+/// Returns the inputs with label `y` among inputs split by label
+/// (see `knn::split_inputs()`), after checking that the object `x`
+/// can be compared to them.
+pub(crate) fn inputs_y<'a, T>(inputs: &'a [Array2<T>], x: &ArrayView1<T>, y: usize)
+        -> Result<&'a Array2<T>> {
+    let inputs_y = inputs.get(y)
+                         .ok_or(Error::InvalidLabel { label: y, n_labels: inputs.len() })?;
+    if x.len() != inputs_y.cols() {
+        return Err(Error::DimensionMismatch { expected: inputs_y.cols(), found: x.len() });
+    }
+
+    Ok(inputs_y)
+}
+
+/// Returns an error if vectors of dimension `d` cannot be compared
+/// to the training inputs split by label, or if there are no training
+/// inputs (i.e., the scorer was not trained).
+pub(crate) fn check_dimension<T>(train_inputs: &Option<Vec<Array2<T>>>, d: usize) -> Result<()> {
+    let train_inputs = train_inputs.as_ref()
+                                   .ok_or(Error::NotTrained)?;
+
+    match train_inputs.first() {
+        Some(inputs_0) if inputs_0.cols() != d => {
+            Err(Error::DimensionMismatch { expected: inputs_0.cols(), found: d })
+        },
+        _ => Ok(()),
+    }
+}
+
+/// Calls `visit(x, y)` for each input `x` with label `y`, among inputs
+/// split by label.
+pub(crate) fn visit_inputs<T>(inputs: &[Array2<T>], y: usize,
+                              visit: &mut dyn FnMut(&ArrayView1<T>, usize)) -> Result<()> {
+    let inputs_y = inputs.get(y)
+                         .ok_or(Error::InvalidLabel { label: y, n_labels: inputs.len() })?;
+
+    for x in inputs_y.outer_iter() {
+        visit(&x, y);
+    }
+
+    Ok(())
+}
+
+/// Calibration examples of an inductive nonconformity scorer, together
+/// with their nonconformity scores.
+///
+/// Examples are split by label: `inputs[y]` contains the calibration
+/// inputs with label `y`, and `scores[y]` their scores. Both are sorted
+/// by score in ascending order, so that p-values can be computed by
+/// binary search.
+pub(crate) struct SortedCalibration<T> {
+    inputs: Vec<Array2<T>>,
+    scores: Vec<Vec<f64>>,
+}
+
+impl<T: Copy> SortedCalibration<T> {
+    /// Computes the scores of calibration inputs split by label, and
+    /// sorts both inputs and scores by score.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Calibration inputs split by label.
+    /// * `score` - Function returning the score of an example `(x, y)`.
+    ///
+    /// # Errors
+    ///
+    /// - if `score` fails, or it returns NaN.
+    pub fn new<F>(inputs: Vec<Array2<T>>, mut score: F) -> Result<SortedCalibration<T>>
+            where F: FnMut(&ArrayView1<T>, usize) -> Result<f64> {
+        let mut scores = Vec::with_capacity(inputs.len());
+        let mut sorted_inputs = Vec::with_capacity(inputs.len());
+
+        for (y, inputs_y) in inputs.into_iter().enumerate() {
+            let scores_y = inputs_y.outer_iter()
+                                   .map(|x| score(&x, y))
+                                   .collect::<Result<Vec<_>>>()?;
+            if scores_y.iter().any(|s| s.is_nan()) {
+                return Err(Error::NaN);
+            }
+            let mut indices = (0..scores_y.len()).collect::<Vec<_>>();
+            indices.sort_by(|&i, &j| scores_y[i].partial_cmp(&scores_y[j])
+                                                .expect("Nonconformity scores cannot be NaN"));
+
+            scores.push(indices.iter()
+                               .map(|&i| scores_y[i])
+                               .collect());
+            sorted_inputs.push(inputs_y.select(Axis(0), &indices));
+        }
+
+        Ok(SortedCalibration {
+            inputs: sorted_inputs,
+            scores: scores,
+        })
+    }
+
+    /// Recomputes the scores of the calibration inputs (e.g., after
+    /// the training data changed), and sorts them again.
+    ///
+    /// # Arguments
+    ///
+    /// * `score` - Function returning the score of an example `(x, y)`.
+    pub fn rescore<F>(self, score: F) -> Result<SortedCalibration<T>>
+            where F: FnMut(&ArrayView1<T>, usize) -> Result<f64> {
+        SortedCalibration::new(self.inputs, score)
+    }
+
+    /// Adds a calibration example `(x, y)` with score `score`, keeping
+    /// inputs and scores sorted.
+    ///
+    /// The label and the dimension of `x` should have been checked.
+    pub fn insert(&mut self, x: &ArrayView1<T>, y: usize, score: f64) {
+        let scores_y = &mut self.scores[y];
+        let pos = scores_y.partition_point(|s| *s <= score);
+        scores_y.insert(pos, score);
+
+        let inputs_y = &self.inputs[y];
+        let pos = pos as isize;
+        self.inputs[y] = stack![Axis(0),
+                                inputs_y.slice(s![..pos, ..]),
+                                x.into_shape((1, x.len()))
+                                 .expect("Unexpected reshaping error"),
+                                inputs_y.slice(s![pos.., ..])];
+    }
+
+    /// Adds calibration examples `(inputs[i], targets[i])`, keeping
+    /// inputs and scores sorted.
+    ///
+    /// Either all the examples are added, or none of them.
+    /// The dimension of the inputs should have been checked.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Calibration inputs.
+    /// * `targets` - Labels corresponding to the calibration inputs.
+    /// * `score` - Function returning the score of an example `(x, y)`.
+    ///
+    /// # Errors
+    ///
+    /// - if the number of inputs and labels differ, or if a label
+    ///   is not valid.
+    /// - if `score` fails, or it returns NaN.
+    pub fn extend<F>(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView1<usize>,
+                     mut score: F) -> Result<()>
+            where F: FnMut(&ArrayView1<T>, usize) -> Result<f64> {
+        check_lengths(inputs.rows(), targets.len())?;
+        check_labels(targets, self.scores.len())?;
+
+        let scores = inputs.outer_iter()
+                           .zip(targets)
+                           .map(|(x, y)| score(&x, *y))
+                           .collect::<Result<Vec<_>>>()?;
+        check_nan(&scores)?;
+
+        for ((x, y), score) in inputs.outer_iter().zip(targets).zip(scores) {
+            self.insert(&x, *y, score);
+        }
+
+        Ok(())
+    }
+}
+
+impl<T> SortedCalibration<T> {
+    /// Returns the calibration inputs, split by label.
+    pub fn inputs(&self) -> &[Array2<T>] {
+        &self.inputs
+    }
+
+    /// Returns the (sorted) scores of the calibration examples with
+    /// label `y`, or None if `y` is not a valid label.
+    pub fn scores(&self, y: usize) -> Option<&[f64]> {
+        self.scores.get(y)
+                   .map(|scores_y| &scores_y[..])
+    }
+
+    /// Returns the score `score` of a test example followed by the
+    /// scores of the calibration examples with label `y`, in the order
+    /// of `NonconformityScorer::scores()`.
+    pub fn with_score(&self, score: f64, y: usize) -> Result<Vec<f64>> {
+        let scores_y = self.scores(y)
+                           .ok_or(Error::InvalidLabel { label: y, n_labels: self.scores.len() })?;
+
+        let mut scores = Vec::with_capacity(scores_y.len() + 1);
+        scores.push(score);
+        scores.extend(scores_y.iter());
+
+        Ok(scores)
+    }
+}
 
 /// A NonconformityScorer can be used to associate a
 /// nonconformity score to a new example.
@@ -29,7 +232,7 @@ pub trait NonconformityScorer<T: Sync> {
     /// * `targets` - Vector (Array1<T>) of labels corresponding to the
     ///               training vectors.
     fn train(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView1<usize>,
-             n_labels: usize) -> LearningResult<()>;
+             n_labels: usize) -> Result<()>;
     /// Calibrates a `NonconformityScorer` for an ICP.
     ///
     /// # Arguments
@@ -39,7 +242,7 @@ pub trait NonconformityScorer<T: Sync> {
     /// * `targets` - Vector (Array1<T>) of labels corresponding to the
     ///               training vectors.
     fn calibrate(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView1<usize>)
-             -> LearningResult<()>;
+             -> Result<()>;
     /// Updates a `NonconformityScorer` with more training data.
     ///
     /// After calling `train()` once, `update()` allows to add
@@ -52,7 +255,22 @@ pub trait NonconformityScorer<T: Sync> {
     ///              vectors.
     /// * `targets` - Vector (Array1<T>) of labels corresponding to the
     ///               training vectors.
-    fn update(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView1<usize>) -> LearningResult<()>;
+    fn update(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView1<usize>) -> Result<()>;
+    /// Updates a calibrated `NonconformityScorer` with more calibration
+    /// data.
+    ///
+    /// After calling `calibrate()` once, `update_calibration()` allows
+    /// to add inputs to the scorer's calibration data, leaving the
+    /// (proper) training data untouched.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<T>) with values of type T of calibration
+    ///              vectors.
+    /// * `targets` - Vector (Array1<T>) of labels corresponding to the
+    ///               calibration vectors.
+    fn update_calibration(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView1<usize>)
+             -> Result<()>;
     /// Computes the nonconformity scores of training inputs and of a new
     /// test example.
     ///
@@ -66,5 +284,128 @@ pub trait NonconformityScorer<T: Sync> {
     ///
     /// * `x` - Test object.
     /// * `y` - (Candidate) label for the test object.
-    fn scores(&self, input: &ArrayView1<T>, targets: usize) -> Vec<f64>;
-}
\ No newline at end of file
+    fn scores(&self, input: &ArrayView1<T>, targets: usize) -> Result<Vec<f64>>;
+    /// Computes the nonconformity score of a new example with respect
+    /// to the (proper) training set.
+    ///
+    /// Unlike `scores()`, it only returns the score of the new
+    /// example $(x, y)$.
+    ///
+    /// # Arguments
+    ///
+    /// * `x` - Test object.
+    /// * `y` - (Candidate) label for the test object.
+    fn score(&self, input: &ArrayView1<T>, target: usize) -> Result<f64>;
+    /// Returns the nonconformity scores of the calibration examples
+    /// with label `y`, sorted in ascending order, if the scorer
+    /// precomputed them when calibrating.
+    ///
+    /// An ICP can then compute a p-value with a single call to `score()`
+    /// and a binary search, instead of calling `scores()`.
+    /// The default implementation returns None.
+    ///
+    /// # Arguments
+    ///
+    /// * `y` - (Candidate) label for the test object.
+    fn calibration_scores(&self, _y: usize) -> Option<&[f64]> {
+        None
+    }
+    /// Draws the random number of a test object, if scores are
+    /// randomized (e.g., for `RAPS`).
+    ///
+    /// A CP draws one number for each test object, in the order of the
+    /// test objects, and passes it to `score_with_draw()` (or
+    /// `scores_with_draw()`) for all its candidate labels.
+    /// The default implementation returns None.
+    fn test_draw(&self) -> Option<f64> {
+        None
+    }
+    /// Computes the nonconformity score of a new example, as `score()`,
+    /// given the random number `u` drawn for `x` by `test_draw()`.
+    ///
+    /// The default implementation ignores `u`, and calls `score()`.
+    ///
+    /// # Arguments
+    ///
+    /// * `x` - Test object.
+    /// * `y` - (Candidate) label for the test object.
+    /// * `u` - Random number returned by `test_draw()`.
+    fn score_with_draw(&self, x: &ArrayView1<T>, y: usize, _u: Option<f64>) -> Result<f64> {
+        self.score(x, y)
+    }
+    /// Computes the nonconformity scores of training inputs and of a new
+    /// test example, as `scores()`, given the random number `u` drawn
+    /// for `x` by `test_draw()`.
+    ///
+    /// The default implementation ignores `u`, and calls `scores()`.
+    ///
+    /// # Arguments
+    ///
+    /// * `x` - Test object.
+    /// * `y` - (Candidate) label for the test object.
+    /// * `u` - Random number returned by `test_draw()`.
+    fn scores_with_draw(&self, x: &ArrayView1<T>, y: usize, _u: Option<f64>)
+                        -> Result<Vec<f64>> {
+        self.scores(x, y)
+    }
+    /// Visits the examples whose nonconformity scores are returned
+    /// by `scores()`.
+    ///
+    /// Calls `visit(x_i, y_i)` for each training (or calibration, for
+    /// an ICP) example $(x_i, y_i)$ whose score is returned by
+    /// `scores(x, y)`, in the same order; that is, the examples
+    /// corresponding to (a_1, a_2, ..., a_{n-1}).
+    ///
+    /// # Arguments
+    ///
+    /// * `y` - (Candidate) label for the test object.
+    /// * `visit` - Function called on each example.
+    fn visit_examples(&self, y: usize, visit: &mut dyn FnMut(&ArrayView1<T>, usize))
+                      -> Result<()>;
+}
+/// A RegressionScorer can be used to associate a nonconformity
+/// score to a new example with a real-valued target.
+///
+/// This is the regression counterpart of `NonconformityScorer`.
+/// This trait is parametrized over `T`, the element type.
+pub trait RegressionScorer<T: Sync> {
+    /// Trains a `RegressionScorer` on its proper training set.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<T>) with values of type T of training
+    ///              vectors.
+    /// * `targets` - Vector (Array1<f64>) of real-valued targets
+    ///               corresponding to the training vectors.
+    fn train(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView1<f64>)
+             -> Result<()>;
+    /// Returns the point predictions of the underlying regressor.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<T>) with values of type T of
+    ///              test vectors.
+    fn predict(&self, inputs: &ArrayView2<T>) -> Result<Array1<f64>>;
+    /// Computes the nonconformity scores of examples `(inputs[i], targets[i])`.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<T>) with values of type T of vectors.
+    /// * `targets` - Vector (Array1<f64>) of (candidate) targets for
+    ///               the vectors.
+    fn scores(&self, inputs: &ArrayView2<T>, targets: &ArrayView1<f64>)
+              -> Result<Vec<f64>>;
+    /// Returns, for each input vector `x`, the interval of targets `y`
+    /// such that the nonconformity score of `(x, y)` is at most
+    /// `threshold`.
+    ///
+    /// The return value is a matrix with shape `(n_inputs, 2)`,
+    /// where the `i`-th row is `[lower, upper]`.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<T>) with values of type T of vectors.
+    /// * `threshold` - Largest nonconformity score admitted in the interval.
+    fn intervals(&self, inputs: &ArrayView2<T>, threshold: f64)
+                 -> Result<Array2<f64>>;
+}
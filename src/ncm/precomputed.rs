@@ -0,0 +1,292 @@
+//! Nonconformity measure from precomputed scores.
+use ndarray::prelude::*;
+
+use error::{Error, Result, check_lengths, check_labels, check_nan};
+use ncm::{NonconformityScorer, SortedCalibration, visit_inputs};
+use ncm::knn::split_inputs;
+
+
+/// A nonconformity measure whose scores were computed externally
+/// (e.g., by a model trained with a different library).
+///
+/// Objects are not feature vectors, but rows of nonconformity scores:
+/// an object with one score per label is scored, for label `y`, by its
+/// `y`-th score; an object with a single score is scored by it for any
+/// label (e.g., the score of a calibration example w.r.t. its true
+/// label).
+///
+/// Since the scores do not depend on other examples, this measure can
+/// only be used by an ICP: `train()` only sets the number of labels
+/// (it is typically called with no examples), and the scores of the
+/// calibration examples are those passed to `calibrate()`.
+///
+/// # Examples
+///
+/// ```
+/// #[macro_use(array)]
+/// extern crate ndarray;
+/// extern crate random_world;
+///
+/// # fn main() {
+/// use ndarray::prelude::*;
+/// use random_world::cp::*;
+/// use random_world::ncm::*;
+///
+/// let mut cp = CP::new_inductive(PrecomputedScores::new(), 2, None);
+/// // Scores of the calibration examples w.r.t. their true labels.
+/// let calibration_scores = array![[0.1], [0.4], [0.3], [0.2]];
+/// let calibration_targets = array![0, 0, 1, 1];
+/// // Scores of the test examples w.r.t. each label.
+/// let test_scores = array![[0.2, 0.5]];
+///
+/// cp.train(&Array2::zeros((0, 1)).view(), &Array1::zeros(0).view())
+///   .expect("Failed to train");
+/// cp.calibrate(&calibration_scores.view(), &calibration_targets.view())
+///   .expect("Failed to calibrate");
+/// let pvalues = cp.predict_confidence(&test_scores.view())
+///                 .expect("Failed to predict");
+/// assert!(pvalues == array![[2. / 3., 1. / 3.]]);
+/// # }
+/// ```
+#[derive(Default)]
+pub struct PrecomputedScores {
+    n_labels: Option<usize>,
+    // Rows of scores of the calibration examples, and their scores.
+    calibration: Option<SortedCalibration<f64>>,
+}
+
+impl PrecomputedScores {
+    /// Constructs a nonconformity measure from precomputed scores.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use random_world::ncm::*;
+    ///
+    /// let ncm = PrecomputedScores::new();
+    /// ```
+    pub fn new() -> PrecomputedScores {
+        PrecomputedScores {
+            n_labels: None,
+            calibration: None,
+        }
+    }
+
+    /// Returns an error if rows of scores have length other than 1
+    /// or the number of labels.
+    fn check_dimension(&self, len: usize) -> Result<()> {
+        let n_labels = self.n_labels.ok_or(Error::NotTrained)?;
+        if len != 1 && len != n_labels {
+            return Err(Error::DimensionMismatch { expected: n_labels, found: len });
+        }
+
+        Ok(())
+    }
+}
+
+impl NonconformityScorer<f64> for PrecomputedScores {
+    /// Sets the number of labels.
+    ///
+    /// Training examples are not used, but they should be rows of
+    /// scores, as any other input.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<f64>) of rows of scores.
+    /// * `targets` - Vector (Array1<usize>) of labels corresponding to the
+    ///               rows of scores.
+    /// * `n_labels` - Number of unique labels in the classification problem.
+    fn train(&mut self, inputs: &ArrayView2<f64>, targets: &ArrayView1<usize>,
+             n_labels: usize) -> Result<()> {
+        if self.n_labels.is_some() {
+            return Err(Error::AlreadyTrained);
+        }
+        check_lengths(inputs.rows(), targets.len())?;
+        check_labels(targets, n_labels)?;
+        self.n_labels = Some(n_labels);
+        self.check_dimension(inputs.cols())
+    }
+
+    /// Stores the scores of the calibration examples.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<f64>) of rows of scores of the
+    ///              calibration examples.
+    /// * `targets` - Vector (Array1<usize>) of labels corresponding to the
+    ///               rows of scores.
+    fn calibrate(&mut self, inputs: &ArrayView2<f64>, targets: &ArrayView1<usize>)
+             -> Result<()> {
+        let n_labels = self.n_labels.ok_or(Error::NotTrained)?;
+        self.check_dimension(inputs.cols())?;
+        let calibration_inputs = split_inputs(inputs, targets, n_labels)?;
+        self.calibration = Some(SortedCalibration::new(calibration_inputs,
+                                                       |x, y| self.score(x, y))?);
+
+        Ok(())
+    }
+
+    /// Only checks the rows of scores: scores computed externally
+    /// cannot be updated with more training examples.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<f64>) of rows of scores.
+    /// * `targets` - Vector (Array1<usize>) of labels corresponding to the
+    ///               rows of scores.
+    fn update(&mut self, inputs: &ArrayView2<f64>, targets: &ArrayView1<usize>)
+        -> Result<()> {
+
+        let n_labels = self.n_labels.ok_or(Error::NotTrained)?;
+        self.check_dimension(inputs.cols())?;
+        check_lengths(inputs.rows(), targets.len())?;
+        check_labels(targets, n_labels)
+    }
+
+    /// Adds the scores of calibration examples.
+    ///
+    /// Rows of scores should have the same length as those passed
+    /// to `calibrate()`.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<f64>) of rows of scores of the
+    ///              calibration examples.
+    /// * `targets` - Vector (Array1<usize>) of labels corresponding to the
+    ///               rows of scores.
+    fn update_calibration(&mut self, inputs: &ArrayView2<f64>, targets: &ArrayView1<usize>)
+        -> Result<()> {
+
+        let d = match self.calibration {
+            Some(ref calibration) => calibration.inputs()
+                                                .first()
+                                                .map_or(1, |inputs| inputs.cols()),
+            None => return Err(Error::NotCalibrated),
+        };
+        // Calibration rows are stored together, so they have the same length.
+        if inputs.cols() != d {
+            return Err(Error::DimensionMismatch { expected: d, found: inputs.cols() });
+        }
+        let mut calibration = self.calibration.take()
+                                              .ok_or(Error::NotCalibrated)?;
+        let extended = calibration.extend(inputs, targets, |x, y| self.score(x, y));
+        self.calibration = Some(calibration);
+
+        extended
+    }
+
+    /// Returns the score of a test example, followed by the scores of
+    /// the calibration examples with label `y`.
+    ///
+    /// # Arguments
+    ///
+    /// * `x` - Row of scores of the test example.
+    /// * `y` - (Candidate) label for the test object.
+    ///
+    /// # Errors
+    ///
+    /// - if the scorer was not calibrated.
+    fn scores(&self, x: &ArrayView1<f64>, y: usize) -> Result<Vec<f64>> {
+        let calibration = self.calibration.as_ref()
+                                          .ok_or(Error::NotCalibrated)?;
+
+        calibration.with_score(self.score(x, y)?, y)
+    }
+
+    /// Returns the score of an example, i.e., its `y`-th score or,
+    /// if it has a single score, that score.
+    ///
+    /// # Arguments
+    ///
+    /// * `x` - Row of scores.
+    /// * `y` - (Candidate) label for the object.
+    fn score(&self, x: &ArrayView1<f64>, y: usize) -> Result<f64> {
+        let n_labels = self.n_labels.ok_or(Error::NotTrained)?;
+        if y >= n_labels {
+            return Err(Error::InvalidLabel { label: y, n_labels: n_labels });
+        }
+        self.check_dimension(x.len())?;
+
+        let score = if x.len() == 1 { x[0] } else { x[y] };
+        check_nan(&[score])?;
+
+        Ok(score)
+    }
+
+    /// Returns the scores of the calibration examples with label `y`,
+    /// sorted in ascending order.
+    ///
+    /// Returns None if the scorer was not calibrated.
+    ///
+    /// # Arguments
+    ///
+    /// * `y` - (Candidate) label for the test object.
+    fn calibration_scores(&self, y: usize) -> Option<&[f64]> {
+        self.calibration.as_ref()
+                        .and_then(|calibration| calibration.scores(y))
+    }
+
+    /// Visits the calibration examples with label `y`, i.e., their
+    /// rows of scores.
+    ///
+    /// # Arguments
+    ///
+    /// * `y` - (Candidate) label for the test object.
+    /// * `visit` - Function called on each example.
+    fn visit_examples(&self, y: usize, visit: &mut dyn FnMut(&ArrayView1<f64>, usize))
+                      -> Result<()> {
+        let calibration = self.calibration.as_ref()
+                                          .ok_or(Error::NotCalibrated)?;
+
+        visit_inputs(calibration.inputs(), y, visit)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Verify that scores are read from the rows of scores, and that
+    /// calibration scores are sorted, also after update_calibration().
+    #[test]
+    fn scores() {
+        let mut ncm = PrecomputedScores::new();
+        ncm.train(&Array2::zeros((0, 3)).view(), &Array1::zeros(0).view(), 3).unwrap();
+
+        // Scores of the true labels, or per-label scores.
+        ncm.calibrate(&array![[0.5], [0.1], [0.3]].view(), &array![1, 1, 0].view()).unwrap();
+        ncm.update_calibration(&array![[0.2]].view(), &array![1].view()).unwrap();
+        assert!(ncm.calibration_scores(0).unwrap() == &[0.3][..]);
+        assert!(ncm.calibration_scores(1).unwrap() == &[0.1, 0.2, 0.5][..]);
+        assert!(ncm.calibration_scores(2).unwrap().is_empty());
+
+        let x = array![0.4, 0.7, 0.];
+        assert!(ncm.scores(&x.view(), 1).unwrap() == vec![0.7, 0.1, 0.2, 0.5]);
+        assert!(ncm.score(&x.view(), 2).unwrap() == 0.);
+        let mut visited = vec![];
+        ncm.visit_examples(1, &mut |x, _| visited.push(x.to_vec())).unwrap();
+        assert!(visited == vec![vec![0.1], vec![0.2], vec![0.5]]);
+
+        match ncm.update_calibration(&array![[9., 0.2, 9.]].view(), &array![1].view()) {
+            Err(Error::DimensionMismatch { expected: 1, found: 3 }) => {},
+            r => panic!("Unexpected result: {:?}", r),
+        }
+        match ncm.score(&array![0.4, 0.7].view(), 0) {
+            Err(Error::DimensionMismatch { expected: 3, found: 2 }) => {},
+            r => panic!("Unexpected result: {:?}", r),
+        }
+        match ncm.score(&array![0.4, 0.7, 0.].view(), 3) {
+            Err(Error::InvalidLabel { label: 3, n_labels: 3 }) => {},
+            r => panic!("Unexpected result: {:?}", r),
+        }
+
+        // Scores cannot be computed before calibrating.
+        let mut ncm = PrecomputedScores::new();
+        ncm.train(&Array2::zeros((0, 1)).view(), &Array1::zeros(0).view(), 2).unwrap();
+        match ncm.scores(&array![0.1].view(), 0) {
+            Err(Error::NotCalibrated) => {},
+            r => panic!("Unexpected result: {:?}", r),
+        }
+    }
+}
@@ -0,0 +1,263 @@
+//! Residual nonconformity measures for regression.
+use std::f64;
+use ndarray::prelude::*;
+use rusty_machine::learning::SupModel;
+use rusty_machine::linalg::{Matrix, Vector};
+
+use error::{Result, check_lengths};
+use ncm::RegressionScorer;
+
+
+/// Converts a matrix view of f64 values into a rusty-machine `Matrix`.
+pub(crate) fn to_matrix(inputs: &ArrayView2<f64>) -> Matrix<f64> {
+    Matrix::new(inputs.rows(), inputs.cols(),
+                inputs.iter().cloned().collect::<Vec<_>>())
+}
+
+/// Trains a rusty-machine regressor on training vectors and their
+/// real-valued targets.
+fn train_model<M>(model: &mut M, inputs: &ArrayView2<f64>, targets: &ArrayView1<f64>)
+        -> Result<()> where M: SupModel<Matrix<f64>, Vector<f64>> {
+    check_lengths(inputs.rows(), targets.len())?;
+
+    let targets = Vector::new(targets.iter().cloned().collect::<Vec<_>>());
+    model.train(&to_matrix(inputs), &targets)?;
+
+    Ok(())
+}
+
+/// Returns the point predictions of a rusty-machine regressor.
+fn predict_model<M>(model: &M, inputs: &ArrayView2<f64>) -> Result<Array1<f64>>
+        where M: SupModel<Matrix<f64>, Vector<f64>> {
+    let preds = model.predict(&to_matrix(inputs))?;
+
+    Ok(Array::from_vec(preds.into_vec()))
+}
+
+/// Absolute residual nonconformity measure.
+///
+/// Given an underlying regressor `M` with point prediction $\hat{y}(x)$,
+/// the nonconformity score of an example $(x, y)$ is
+/// $|y - \hat{y}(x)|$.
+/// The regressor can be any rusty-machine supervised model
+/// implementing `SupModel<Matrix<f64>, Vector<f64>>`
+/// (e.g., `LinRegressor`, `GaussianProcess`).
+pub struct AbsoluteResidual<M: SupModel<Matrix<f64>, Vector<f64>>> {
+    model: M,
+}
+
+impl<M: SupModel<Matrix<f64>, Vector<f64>>> AbsoluteResidual<M> {
+    /// Constructs an absolute residual nonconformity measure.
+    ///
+    /// # Arguments
+    ///
+    /// * `model` - Underlying (untrained) regressor.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// extern crate rusty_machine;
+    /// extern crate random_world;
+    ///
+    /// # fn main() {
+    /// use rusty_machine::learning::lin_reg::LinRegressor;
+    /// use random_world::ncm::*;
+    ///
+    /// let ncm = AbsoluteResidual::new(LinRegressor::default());
+    /// # }
+    /// ```
+    pub fn new(model: M) -> AbsoluteResidual<M> {
+        AbsoluteResidual {
+            model: model,
+        }
+    }
+}
+
+impl<M> RegressionScorer<f64> for AbsoluteResidual<M>
+        where M: SupModel<Matrix<f64>, Vector<f64>> {
+    /// Trains the underlying regressor.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<f64>) of training vectors.
+    /// * `targets` - Vector (Array1<f64>) of real-valued targets
+    ///               corresponding to the training vectors.
+    fn train(&mut self, inputs: &ArrayView2<f64>, targets: &ArrayView1<f64>)
+             -> Result<()> {
+        train_model(&mut self.model, inputs, targets)
+    }
+
+    /// Returns the point predictions of the underlying regressor.
+    fn predict(&self, inputs: &ArrayView2<f64>) -> Result<Array1<f64>> {
+        predict_model(&self.model, inputs)
+    }
+
+    /// Computes the absolute residuals $|y_i - \hat{y}(x_i)|$.
+    fn scores(&self, inputs: &ArrayView2<f64>, targets: &ArrayView1<f64>)
+              -> Result<Vec<f64>> {
+        check_lengths(inputs.rows(), targets.len())?;
+
+        let preds = self.predict(inputs)?;
+
+        Ok(preds.iter()
+                .zip(targets.iter())
+                .map(|(y_hat, y)| (y - y_hat).abs())
+                .collect())
+    }
+
+    /// Returns the intervals $[\hat{y}(x) - t, \hat{y}(x) + t]$,
+    /// where $t$ is `threshold`.
+    fn intervals(&self, inputs: &ArrayView2<f64>, threshold: f64)
+                 -> Result<Array2<f64>> {
+        let preds = self.predict(inputs)?;
+
+        let mut intervals = Array2::<f64>::from_elem((inputs.rows(), 2), f64::NAN);
+        for (mut interval, y_hat) in intervals.outer_iter_mut().zip(preds.iter()) {
+            interval[0] = y_hat - threshold;
+            interval[1] = y_hat + threshold;
+        }
+
+        Ok(intervals)
+    }
+}
+
+/// Signed residual nonconformity measure.
+///
+/// Given an underlying regressor `M` with point prediction $\hat{y}(x)$,
+/// the nonconformity score of an example $(x, y)$ is
+/// $y - \hat{y}(x)$.
+/// Since the score increases with `y`, it can be used by a Conformal
+/// Predictive System (see `CPS`); used by an ICP, it gives one-sided
+/// prediction intervals.
+pub struct SignedResidual<M: SupModel<Matrix<f64>, Vector<f64>>> {
+    model: M,
+}
+
+impl<M: SupModel<Matrix<f64>, Vector<f64>>> SignedResidual<M> {
+    /// Constructs a signed residual nonconformity measure.
+    ///
+    /// # Arguments
+    ///
+    /// * `model` - Underlying (untrained) regressor.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// extern crate rusty_machine;
+    /// extern crate random_world;
+    ///
+    /// # fn main() {
+    /// use rusty_machine::learning::lin_reg::LinRegressor;
+    /// use random_world::ncm::*;
+    ///
+    /// let ncm = SignedResidual::new(LinRegressor::default());
+    /// # }
+    /// ```
+    pub fn new(model: M) -> SignedResidual<M> {
+        SignedResidual {
+            model: model,
+        }
+    }
+}
+
+impl<M> RegressionScorer<f64> for SignedResidual<M>
+        where M: SupModel<Matrix<f64>, Vector<f64>> {
+    /// Trains the underlying regressor.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<f64>) of training vectors.
+    /// * `targets` - Vector (Array1<f64>) of real-valued targets
+    ///               corresponding to the training vectors.
+    fn train(&mut self, inputs: &ArrayView2<f64>, targets: &ArrayView1<f64>)
+             -> Result<()> {
+        train_model(&mut self.model, inputs, targets)
+    }
+
+    /// Returns the point predictions of the underlying regressor.
+    fn predict(&self, inputs: &ArrayView2<f64>) -> Result<Array1<f64>> {
+        predict_model(&self.model, inputs)
+    }
+
+    /// Computes the signed residuals $y_i - \hat{y}(x_i)$.
+    fn scores(&self, inputs: &ArrayView2<f64>, targets: &ArrayView1<f64>)
+              -> Result<Vec<f64>> {
+        check_lengths(inputs.rows(), targets.len())?;
+
+        let preds = self.predict(inputs)?;
+
+        Ok(preds.iter()
+                .zip(targets.iter())
+                .map(|(y_hat, y)| y - y_hat)
+                .collect())
+    }
+
+    /// Returns the intervals $[-\infty, \hat{y}(x) + t]$, where $t$ is
+    /// `threshold`.
+    fn intervals(&self, inputs: &ArrayView2<f64>, threshold: f64)
+                 -> Result<Array2<f64>> {
+        let preds = self.predict(inputs)?;
+
+        let mut intervals = Array2::<f64>::from_elem((inputs.rows(), 2), f64::NAN);
+        for (mut interval, y_hat) in intervals.outer_iter_mut().zip(preds.iter()) {
+            interval[0] = f64::NEG_INFINITY;
+            interval[1] = y_hat + threshold;
+        }
+
+        Ok(intervals)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusty_machine::learning::lin_reg::LinRegressor;
+
+    /// Verify that absolute residuals and intervals are computed
+    /// w.r.t. the underlying regressor's predictions.
+    #[test]
+    fn absolute_residual() {
+        let mut ncm = AbsoluteResidual::new(LinRegressor::default());
+
+        let train_inputs = array![[0.], [1.], [2.], [3.]];
+        let train_targets = array![1., 3., 5., 7.];
+        let inputs = array![[4.], [5.]];
+        let targets = array![10., 10.];
+
+        ncm.train(&train_inputs.view(), &train_targets.view()).unwrap();
+
+        let scores = ncm.scores(&inputs.view(), &targets.view()).unwrap();
+        assert_relative_eq!(scores[0], 1., epsilon = 1e-9);
+        assert_relative_eq!(scores[1], 1., epsilon = 1e-9);
+
+        let intervals = ncm.intervals(&inputs.view(), 0.5).unwrap();
+        assert_relative_eq!(intervals[[0,0]], 8.5, epsilon = 1e-9);
+        assert_relative_eq!(intervals[[0,1]], 9.5, epsilon = 1e-9);
+        assert_relative_eq!(intervals[[1,0]], 10.5, epsilon = 1e-9);
+        assert_relative_eq!(intervals[[1,1]], 11.5, epsilon = 1e-9);
+    }
+
+    /// Verify that signed residuals keep their sign, and that intervals
+    /// are one-sided.
+    #[test]
+    fn signed_residual() {
+        let mut ncm = SignedResidual::new(LinRegressor::default());
+
+        let train_inputs = array![[0.], [1.], [2.], [3.]];
+        let train_targets = array![1., 3., 5., 7.];
+        let inputs = array![[4.], [5.]];
+        let targets = array![10., 10.];
+
+        ncm.train(&train_inputs.view(), &train_targets.view()).unwrap();
+
+        let scores = ncm.scores(&inputs.view(), &targets.view()).unwrap();
+        assert_relative_eq!(scores[0], 1., epsilon = 1e-9);
+        assert_relative_eq!(scores[1], -1., epsilon = 1e-9);
+
+        let intervals = ncm.intervals(&inputs.view(), 0.5).unwrap();
+        assert!(intervals[[0,0]] == f64::NEG_INFINITY);
+        assert_relative_eq!(intervals[[0,1]], 9.5, epsilon = 1e-9);
+        assert_relative_eq!(intervals[[1,1]], 11.5, epsilon = 1e-9);
+    }
+}
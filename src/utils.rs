@@ -2,16 +2,42 @@
 //! Utility routines for loading and storing data into files.
 use ndarray::prelude::*;
 use std::fmt::Display;
-use std::error::Error;
 use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Read};
 use csv::{ReaderBuilder, WriterBuilder};
+use npyz::{NpyFile, Order};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+
+use cp::ForcedPrediction;
+use error::{Error, Result};
+
+/// Name identifying files stored by `save_model()`.
+const MODEL_FORMAT: &str = "random-world";
+/// Version of the format of files stored by `save_model()`.
+///
+/// It should be increased every time the serialized representation
+/// of a model changes.
+pub const MODEL_FORMAT_VERSION: u32 = 1;
+
+/// Envelope of a model stored into a file.
+#[derive(Serialize, Deserialize)]
+struct ModelFile<M> {
+    format: String,
+    version: u32,
+    model: M,
+}
 
 /// Loads a CSV data file.
 ///
 /// The file format should be, for each row:
 ///     label, x1, x2, ...
 /// where x1, x2, ... are features forming a feature vector.
-pub fn load_data(fname: &str) -> Result<(Array2<f64>, Array1<usize>), Box<Error>> {
+///
+/// Returns an error if the file cannot be parsed, if its rows have
+/// different lengths, or if features are NaN.
+pub fn load_data(fname: &str) -> Result<(Array2<f64>, Array1<usize>)> {
     let mut reader = ReaderBuilder::new()
                                    .has_headers(false)
                                    .from_path(fname)?;
@@ -21,19 +47,26 @@ pub fn load_data(fname: &str) -> Result<(Array2<f64>, Array1<usize>), Box<Error>
 
     let mut d: Option<usize> = None;
 
-    for result in reader.records() {
+    for (i, result) in reader.records().enumerate() {
         let record = result?;
 
-        inputs.extend(record.iter()
-                            .skip(1)  // First one is the label.
-                            .map(|x| x.trim()
-                                      .parse::<f64>().ok()
-                                                     .expect("Failed to parse")));
-        targets.push(record[0].parse::<usize>()?);
+        for x in record.iter().skip(1) {  // First one is the label.
+            let x = x.trim()
+                     .parse::<f64>()
+                     .map_err(|e| Error::Format(format!("line {}: {}", i + 1, e)))?;
+            if x.is_nan() {
+                return Err(Error::NaN);
+            }
+            inputs.push(x);
+        }
+        targets.push(record[0].trim()
+                              .parse::<usize>()
+                              .map_err(|e| Error::Format(format!("line {}: {}", i + 1, e)))?);
 
         if let Some(x) = d {
             if x != record.len() - 1 {
-                panic!("File has wrong format");
+                return Err(Error::Format(format!("line {}: expected {} features, found {}",
+                                                 i + 1, x, record.len() - 1)));
             }
         } else {
             d = Some(record.len() - 1);
@@ -41,16 +74,149 @@ pub fn load_data(fname: &str) -> Result<(Array2<f64>, Array1<usize>), Box<Error>
     }
 
     let inputs_a = if let Some(d) = d {
-        let n = inputs.len() / d;
+        let n = targets.len();
         Array::from_vec(inputs)
               .into_shape((n, d))?
     } else {
-        panic!("File has wrong format");
+        return Err(Error::Format("empty file".to_string()));
     };
 
     Ok((inputs_a, Array::from_vec(targets)))
 }
 
+/// Loads a CSV file of unlabeled data.
+///
+/// The file format should be, for each row:
+///     x1, x2, ...
+/// where x1, x2, ... are features forming a feature vector.
+///
+/// Returns an error if the file cannot be parsed, if its rows have
+/// different lengths, or if features are NaN.
+pub fn load_inputs(fname: &str) -> Result<Array2<f64>> {
+    let mut reader = ReaderBuilder::new()
+                                   .has_headers(false)
+                                   .from_path(fname)?;
+
+    let mut inputs: Vec<f64> = Vec::new();
+    let mut n = 0;
+
+    let mut d: Option<usize> = None;
+
+    for (i, result) in reader.records().enumerate() {
+        let record = result?;
+
+        for x in record.iter() {
+            let x = x.trim()
+                     .parse::<f64>()
+                     .map_err(|e| Error::Format(format!("line {}: {}", i + 1, e)))?;
+            if x.is_nan() {
+                return Err(Error::NaN);
+            }
+            inputs.push(x);
+        }
+        n += 1;
+
+        if let Some(x) = d {
+            if x != record.len() {
+                return Err(Error::Format(format!("line {}: expected {} features, found {}",
+                                                 i + 1, x, record.len())));
+            }
+        } else {
+            d = Some(record.len());
+        }
+    }
+
+    if let Some(d) = d {
+        Ok(Array::from_vec(inputs)
+                 .into_shape((n, d))?)
+    } else {
+        Err(Error::Format("empty file".to_string()))
+    }
+}
+
+/// Reads the values of a `.npy` file as `f64`.
+fn npy_values<R: Read>(npy: NpyFile<R>) -> Result<Vec<f64>> {
+    let npy = match npy.try_data::<f64>() {
+        Ok(data) => return Ok(data.collect::<io::Result<_>>()?),
+        Err(npy) => npy,
+    };
+    let npy = match npy.try_data::<f32>() {
+        Ok(data) => return Ok(data.map(|x| x.map(f64::from))
+                                  .collect::<io::Result<_>>()?),
+        Err(npy) => npy,
+    };
+    let npy = match npy.try_data::<i64>() {
+        Ok(data) => return Ok(data.map(|x| x.map(|x| x as f64))
+                                  .collect::<io::Result<_>>()?),
+        Err(npy) => npy,
+    };
+    match npy.try_data::<i32>() {
+        Ok(data) => Ok(data.map(|x| x.map(f64::from))
+                           .collect::<io::Result<_>>()?),
+        Err(npy) => Err(Error::Format(format!("unsupported dtype {}", npy.dtype().descr()))),
+    }
+}
+
+/// Loads a NumPy `.npy` file of unlabeled data.
+///
+/// The file should contain a 2-dimensional array of floating point
+/// numbers (`f8` or `f4`) or integers (`i8` or `i4`), whose rows are
+/// feature vectors; a 1-dimensional array is loaded as a single column.
+///
+/// Returns an error if the file cannot be parsed, if it contains an
+/// array of other type or dimension, or if features are NaN.
+pub fn load_npy(fname: &str) -> Result<Array2<f64>> {
+    let npy = NpyFile::new(BufReader::new(File::open(fname)?))?;
+
+    let (n, d) = match *npy.shape() {
+        [n] => (n as usize, 1),
+        [n, d] => (n as usize, d as usize),
+        ref shape => return Err(Error::Format(format!("expected a 1- or 2-dimensional \
+                                                       array, found shape {:?}", shape))),
+    };
+    let order = npy.order();
+    let values = npy_values(npy)?;
+    if values.iter().any(|x| x.is_nan()) {
+        return Err(Error::NaN);
+    }
+
+    Ok(match order {
+        Order::C => Array::from_shape_vec((n, d), values)?,
+        Order::Fortran => Array::from_shape_vec((d, n), values)?.reversed_axes()
+                                                                .to_owned(),
+    })
+}
+
+/// Loads a NumPy `.npy` data file.
+///
+/// The file should contain a 2-dimensional array (see `load_npy()`)
+/// whose rows have format:
+///     label, x1, x2, ...
+/// where x1, x2, ... are features forming a feature vector.
+///
+/// Returns an error if the file cannot be parsed, if labels are not
+/// non-negative integers, or if features are NaN.
+pub fn load_npy_data(fname: &str) -> Result<(Array2<f64>, Array1<usize>)> {
+    let data = load_npy(fname)?;
+    if data.cols() == 0 {
+        return Err(Error::Format("expected a label column".to_string()));
+    }
+
+    let targets = data.column(0)
+                      .iter()
+                      .enumerate()
+                      .map(|(i, &y)| {
+                          if y >= 0. && y.fract() == 0. {
+                              Ok(y as usize)
+                          } else {
+                              Err(Error::Format(format!("row {}: invalid label {}", i + 1, y)))
+                          }
+                      })
+                      .collect::<Result<Vec<_>>>()?;
+
+    Ok((data.slice(s![.., 1..]).to_owned(), Array::from_vec(targets)))
+}
+
 /// Stores predictions into a CSV file.
 ///
 /// It stores either predictions (bool values) or p-values (f64)
@@ -59,11 +225,10 @@ pub fn load_data(fname: &str) -> Result<(Array2<f64>, Array1<usize>), Box<Error>
 ///     x1, x2, ...
 /// where each value corresponds to a label.
 pub fn store_predictions<T>(predictions: ArrayView2<T>, fname: &str,
-        append: bool) -> Result<(), Box<Error>> where T: Display {
+        append: bool) -> Result<()> where T: Display {
     if !append {
         // Empty the file.
-        let _ = File::create(fname)
-                     .expect("Failed to create file");
+        let _ = File::create(fname)?;
     }
     let mut writer = WriterBuilder::new().from_writer(OpenOptions::new()
                                              .append(append)
@@ -79,6 +244,31 @@ pub fn store_predictions<T>(predictions: ArrayView2<T>, fname: &str,
     Ok(())
 }
 
+/// Stores forced predictions into a CSV file.
+///
+/// Each line contains the forced prediction for one test object:
+///     label, confidence, credibility
+pub fn store_forced_predictions(predictions: &[ForcedPrediction], fname: &str,
+        append: bool) -> Result<()> {
+    if !append {
+        // Empty the file.
+        let _ = File::create(fname)?;
+    }
+    let mut writer = WriterBuilder::new().from_writer(OpenOptions::new()
+                                             .append(append)
+                                             .write(true)
+                                             .open(fname)?);
+
+    for forced in predictions {
+        writer.write_record(&[format!("{}", forced.label),
+                              format!("{}", forced.confidence),
+                              format!("{}", forced.credibility)])?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
 /// Loads predictions into a CSV file.
 ///
 /// It stores either predictions (bool values) or p-values (f64)
@@ -86,7 +276,7 @@ pub fn store_predictions<T>(predictions: ArrayView2<T>, fname: &str,
 /// for one test object:
 ///     x1, x2, ...
 /// where each value corresponds to a label.
-pub fn load_pvalues(fname: &str) -> Result<Array2<f64>, Box<Error>> {
+pub fn load_pvalues(fname: &str) -> Result<Array2<f64>> {
     let mut reader = ReaderBuilder::new()
                                    .has_headers(false)
                                    .from_path(fname)?;
@@ -94,18 +284,19 @@ pub fn load_pvalues(fname: &str) -> Result<Array2<f64>, Box<Error>> {
     let mut pvalues = vec![];
     let mut d: Option<usize> = None;
 
-    for result in reader.records() {
+    for (i, result) in reader.records().enumerate() {
         let record = result?;
 
-        pvalues.extend(record.iter()
-                             .map(|x| x.trim()
-                                       .parse::<f64>()
-                                       .ok()
-                                       .expect("Failed to parse")));
+        for x in record.iter() {
+            pvalues.push(x.trim()
+                          .parse::<f64>()
+                          .map_err(|e| Error::Format(format!("line {}: {}", i + 1, e)))?);
+        }
         // Update length of a row.
         if let Some(d) = d {
             if d != record.len() {
-                panic!("File has wrong format");
+                return Err(Error::Format(format!("line {}: expected {} p-values, found {}",
+                                                 i + 1, d, record.len())));
             }
         } else {
             d = Some(record.len());
@@ -117,8 +308,94 @@ pub fn load_pvalues(fname: &str) -> Result<Array2<f64>, Box<Error>> {
         Array::from_vec(pvalues)
               .into_shape((n, d))?
     } else {
-        panic!("File has wrong format");
+        return Err(Error::Format("empty file".to_string()));
     };
 
     Ok(pvalues_a)
-}
\ No newline at end of file
+}
+
+/// Stores a trained model (e.g., a `CP`, a `KNN` nonconformity measure,
+/// or a `Martingale`) into a file.
+///
+/// The file is a JSON object with the following fields:
+///
+/// - `format`: always `"random-world"`;
+/// - `version`: version of the format (`MODEL_FORMAT_VERSION`);
+/// - `model`: the serialized model.
+///
+/// A `CP` is serialized with its nonconformity measure (`ncm`),
+/// `epsilon`, `smooth`, `n_labels`, `calibrated` (null for a transductive
+/// CP) and the state of its PRNG (`rng`: the `seed` and the number of
+/// `draws`, null for a deterministic CP).
+/// A `KNN` is serialized with `k`, its `distance` (and the
+/// `inv_covariance` of the Mahalanobis distance), `n_labels`, and its
+/// `train_inputs` and `calibration_inputs`, split per label.
+/// A `KDE` is serialized with its `kernel`, `bandwidth` (and its value
+/// `h`), `n_labels`, `train_inputs` and `calibration_inputs`.
+/// A `Martingale` is serialized with its `kind` (and parameters),
+/// `current` value, `threshold` and previous `pvalues`.
+///
+/// # Examples
+///
+/// ```
+/// #[macro_use(array)]
+/// extern crate ndarray;
+/// extern crate random_world;
+///
+/// # fn main() {
+/// use random_world::cp::*;
+/// use random_world::ncm::*;
+/// use random_world::utils::{save_model, load_model};
+///
+/// let mut cp = CP::new(KNN::new(2), 2, None);
+/// let train_inputs = array![[0., 0.],
+///                           [1., 0.],
+///                           [1., 1.],
+///                           [2., 2.]];
+/// let train_targets = array![0, 0, 1, 1];
+/// cp.train(&train_inputs.view(), &train_targets.view())
+///   .expect("Failed to train the model");
+///
+/// # let fname = std::env::temp_dir().join("random-world-doctest-cp.json");
+/// # let fname = fname.to_str().unwrap();
+/// save_model(&cp, fname).expect("Failed to store the model");
+/// let mut cp_loaded: CP<f64, KNN<f64>> = load_model(fname)
+///                                         .expect("Failed to load the model");
+///
+/// let test_inputs = array![[0., 1.]];
+/// assert!(cp.predict_confidence(&test_inputs.view()).unwrap() ==
+///         cp_loaded.predict_confidence(&test_inputs.view()).unwrap());
+/// # }
+/// ```
+pub fn save_model<M: Serialize>(model: &M, fname: &str) -> Result<()> {
+    let file = File::create(fname)?;
+    let model_file = ModelFile {
+        format: MODEL_FORMAT.to_string(),
+        version: MODEL_FORMAT_VERSION,
+        model: model,
+    };
+    serde_json::to_writer(file, &model_file)?;
+
+    Ok(())
+}
+
+/// Loads a trained model from a file stored by `save_model()`.
+///
+/// Returns an error if the file was stored with a different
+/// version of the format.
+pub fn load_model<M: DeserializeOwned>(fname: &str) -> Result<M> {
+    let file = File::open(fname)?;
+    // Check the version before deserializing the model itself.
+    let model_file: ModelFile<serde_json::Value> = serde_json::from_reader(file)?;
+
+    if model_file.format != MODEL_FORMAT {
+        return Err(Error::Format(format!("not a {} model file", MODEL_FORMAT)));
+    }
+    if model_file.version != MODEL_FORMAT_VERSION {
+        return Err(Error::Format(format!("unsupported model format version {} \
+                                          (expected {})",
+                                         model_file.version, MODEL_FORMAT_VERSION)));
+    }
+
+    Ok(serde_json::from_value(model_file.model)?)
+}
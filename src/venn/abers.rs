@@ -0,0 +1,287 @@
+//! Inductive Venn-ABERS Predictors.
+use std::f64;
+use std::marker::PhantomData;
+use ndarray::prelude::*;
+
+use error::{Error, Result, check_lengths, check_labels};
+use ncm::NonconformityScorer;
+use venn::MultiprobabilityPredictor;
+
+
+/// Fits isotonic regression on calibration scores and labels, extended
+/// with a test example with score `score` and label `label`, and returns
+/// the fitted value at `score`.
+///
+/// Isotonic regression is fitted with the Pool Adjacent Violators
+/// algorithm.
+///
+/// # Arguments
+///
+/// * `calibration` - Pairs `(score, label)`, sorted by score.
+/// * `score` - Score of the test example.
+/// * `label` - Label (either 0 or 1) assumed for the test example.
+fn isotonic_value(calibration: &[(f64, usize)], score: f64, label: usize) -> f64 {
+    // Position of the test example among the sorted calibration scores.
+    let pos = calibration.iter()
+                         .take_while(|&&(s, _)| s < score)
+                         .count();
+    let examples = calibration[..pos].iter()
+                                     .cloned()
+                                     .chain(Some((score, label)))
+                                     .chain(calibration[pos..].iter().cloned());
+
+    // Blocks (sum of labels, number of examples, contains test example).
+    // Examples with identical scores start in the same block.
+    let mut blocks: Vec<(f64, f64, bool)> = vec![];
+    let mut last_score = None;
+    for (i, (s, y)) in examples.enumerate() {
+        let is_test = i == pos;
+        if last_score == Some(s) {
+            let block = blocks.last_mut().unwrap();
+            block.0 += y as f64;
+            block.1 += 1.;
+            block.2 |= is_test;
+        } else {
+            blocks.push((y as f64, 1., is_test));
+        }
+        last_score = Some(s);
+
+        // Pool adjacent violators.
+        while blocks.len() > 1 {
+            let (sum, count, test) = blocks[blocks.len()-1];
+            let (sum_prev, count_prev, test_prev) = blocks[blocks.len()-2];
+            if sum_prev / count_prev <= sum / count {
+                break;
+            }
+            blocks.pop();
+            *blocks.last_mut().unwrap() = (sum_prev + sum, count_prev + count,
+                                           test_prev || test);
+        }
+    }
+
+    let (sum, count, _) = blocks.into_iter()
+                                .find(|&(_, _, test)| test)
+                                .expect("Unexpected error in isotonic regression");
+    sum / count
+}
+
+/// An Inductive Venn-ABERS Predictor (IVAP), for some nonconformity
+/// scorer N and matrix element type T.
+///
+/// IVAP only supports binary classification (labels 0 and 1).
+/// The underlying scoring function of an object `x` is
+/// $s(x) = A(x, 0) - A(x, 1)$, where $A$ is the nonconformity scorer
+/// trained on the proper training set (i.e., the larger the score,
+/// the more `x` conforms to label 1).
+/// For a test object `x`, isotonic regression is fitted on the
+/// calibration scores extended with `(s(x), 0)` and with
+/// `(s(x), 1)`, which gives two probabilities `p0 <= p1`
+/// for label 1. The probability of label 1 is in `[p0, p1]`, and
+/// the one of label 0 is in `[1-p1, 1-p0]`.
+pub struct VennAbers<T: Sync, N: NonconformityScorer<T>> {
+    ncm: N,
+    calibration_inputs: Option<Array2<T>>,
+    calibration_targets: Option<Array1<usize>>,
+    // Pairs (score, label) of calibration examples, sorted by score.
+    calibration: Option<Vec<(f64, usize)>>,
+    marker: PhantomData<T>,
+}
+
+impl<T, N> VennAbers<T, N>
+        where T: Clone + Sync + Copy, N: NonconformityScorer<T> {
+    /// Constructs a new Inductive Venn-ABERS Predictor
+    /// `VennAbers<T,N>` from a nonconformity scorer.
+    ///
+    /// # Arguments
+    ///
+    /// * `ncm` - An object implementing NonconformityScorer.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use random_world::ncm::*;
+    /// use random_world::venn::*;
+    ///
+    /// let ncm = KNN::new(2);
+    /// let mut ivap = VennAbers::new(ncm);
+    /// ```
+    pub fn new(ncm: N) -> VennAbers<T, N> {
+        VennAbers {
+            ncm: ncm,
+            calibration_inputs: None,
+            calibration_targets: None,
+            calibration: None,
+            marker: PhantomData,
+        }
+    }
+
+    /// Scoring function, computed from the nonconformity scorer.
+    fn scoring(&self, x: &ArrayView1<T>) -> Result<f64> {
+        let score = self.ncm.score(x, 0)? - self.ncm.score(x, 1)?;
+        if score.is_nan() {
+            return Err(Error::NaN);
+        }
+
+        Ok(score)
+    }
+
+    /// Computes the scores of the calibration examples.
+    fn compute_calibration(&mut self) -> Result<()> {
+        let calibration = match (self.calibration_inputs.as_ref(),
+                                 self.calibration_targets.as_ref()) {
+            (Some(inputs), Some(targets)) => {
+                let mut calibration = inputs.outer_iter()
+                                            .zip(targets.iter())
+                                            .map(|(x, y)| Ok((self.scoring(&x)?, *y)))
+                                            .collect::<Result<Vec<_>>>()?;
+                calibration.sort_by(|a, b| a.partial_cmp(b)
+                                            .expect("Scores cannot be NaN"));
+                Some(calibration)
+            },
+            _ => None,
+        };
+        self.calibration = calibration;
+
+        Ok(())
+    }
+}
+
+impl<T, N> MultiprobabilityPredictor<T> for VennAbers<T, N>
+        where T: Clone + Sync + Copy, N: NonconformityScorer<T> {
+    /// Trains the nonconformity scorer on the proper training set.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<T>) with values of type T of training
+    ///              vectors.
+    /// * `targets` - Vector (Array1<T>) of labels (0 or 1) corresponding
+    ///               to the training vectors.
+    ///
+    /// # Errors
+    ///
+    /// - if the number of training examples is not consistent
+    ///   with the number of respective labels.
+    /// - if labels are not binary.
+    fn train(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView1<usize>)
+             -> Result<()> {
+        check_lengths(inputs.rows(), targets.len())?;
+        check_labels(targets, 2)?;
+
+        self.ncm.train(inputs, targets, 2)?;
+        self.compute_calibration()
+    }
+
+    /// Updates the nonconformity scorer with more proper training data.
+    ///
+    /// If the predictor was already calibrated, the scores of the
+    /// calibration examples are recomputed.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<T>) with values of type T of training
+    ///              vectors.
+    /// * `targets` - Vector (Array1<T>) of labels (0 or 1) corresponding
+    ///               to the training vectors.
+    fn update(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView1<usize>)
+              -> Result<()> {
+        check_lengths(inputs.rows(), targets.len())?;
+        check_labels(targets, 2)?;
+
+        self.ncm.update(inputs, targets)?;
+        self.compute_calibration()
+    }
+
+    /// Calibrates the Inductive Venn-ABERS Predictor.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<T>) with values of type T of calibration
+    ///              vectors.
+    /// * `targets` - Vector (Array1<T>) of labels (0 or 1) corresponding
+    ///               to the calibration vectors.
+    fn calibrate(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView1<usize>)
+                 -> Result<()> {
+        check_lengths(inputs.rows(), targets.len())?;
+        // Venn-ABERS requires binary labels.
+        check_labels(targets, 2)?;
+
+        self.calibration_inputs = Some(inputs.to_owned());
+        self.calibration_targets = Some(targets.to_owned());
+        self.compute_calibration()
+    }
+
+    /// Returns lower and upper probabilities for test vectors.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// #[macro_use(array)]
+    /// extern crate ndarray;
+    /// extern crate random_world;
+    ///
+    /// # fn main() {
+    /// use random_world::ncm::*;
+    /// use random_world::venn::*;
+    ///
+    /// let ncm = KNN::new(1);
+    /// let mut ivap = VennAbers::new(ncm);
+    /// let train_inputs = array![[0.], [1.], [3.], [4.]];
+    /// let train_targets = array![0, 0, 1, 1];
+    /// let calibration_inputs = array![[0.5], [2.], [2.5], [3.5]];
+    /// let calibration_targets = array![0, 1, 0, 1];
+    /// let test_inputs = array![[3.]];
+    ///
+    /// ivap.train(&train_inputs.view(), &train_targets.view())
+    ///     .expect("Failed to train");
+    /// ivap.calibrate(&calibration_inputs.view(), &calibration_targets.view())
+    ///     .expect("Failed to calibrate");
+    /// let (lower, upper) = ivap.predict_probabilities(&test_inputs.view())
+    ///                          .expect("Failed to predict");
+    /// assert!(lower == array![[0., 0.5]]);
+    /// assert!(upper == array![[0.5, 1.]]);
+    /// # }
+    /// ```
+    fn predict_probabilities(&mut self, inputs: &ArrayView2<T>)
+                             -> Result<(Array2<f64>, Array2<f64>)> {
+        let calibration = self.calibration.as_ref()
+                              .ok_or(Error::NotCalibrated)?;
+
+        // Init with NaN to ease future debugging.
+        let mut lower = Array2::<f64>::from_elem((inputs.rows(), 2), f64::NAN);
+        let mut upper = Array2::<f64>::from_elem((inputs.rows(), 2), f64::NAN);
+
+        for (i, x) in inputs.outer_iter().enumerate() {
+            let score = self.scoring(&x)?;
+            let p0 = isotonic_value(calibration, score, 0);
+            let p1 = isotonic_value(calibration, score, 1);
+
+            lower[[i,0]] = 1. - p1;
+            upper[[i,0]] = 1. - p0;
+            lower[[i,1]] = p0;
+            upper[[i,1]] = p1;
+        }
+
+        Ok((lower, upper))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Verify isotonic regression (PAVA) on a sequence with violators.
+    #[test]
+    fn isotonic() {
+        let calibration = vec![(0., 0), (1., 1), (2., 0), (3., 1), (4., 1)];
+
+        // Pools: {0}, {1, 2, 2.5}, {3}, {4}.
+        assert_relative_eq!(isotonic_value(&calibration, 2.5, 0), 1./3.);
+        // Pools: {0}, {1, 2}, {2.5}, {3}, {4}.
+        assert_relative_eq!(isotonic_value(&calibration, 2.5, 1), 1.);
+        // Ties with calibration scores.
+        // Pools: {0, 0}, {1, 2}, {3}, {4}.
+        assert_relative_eq!(isotonic_value(&calibration, 0., 1), 0.5);
+        assert_relative_eq!(isotonic_value(&calibration, 0., 0), 0.);
+    }
+}
@@ -0,0 +1,57 @@
+//! Module defining Venn Predictors.
+//!
+//! A `MultiprobabilityPredictor<T>` implements all methods to provide
+//! a multiprobability prediction for new input vectors; that is,
+//! for each label, a lower and an upper bound on the probability
+//! of the label.
+//! Examples of multiprobability predictors are Venn Predictors
+//! and Inductive Venn-ABERS Predictors.
+pub mod predictor;
+pub mod abers;
+
+use ndarray::prelude::*;
+
+use error::Result;
+
+pub use self::predictor::{Venn, VennTaxonomy, KNNTaxonomy};
+pub use self::abers::VennAbers;
+
+
+/// A Multiprobability Predictor (e.g., Venn or Venn-ABERS Predictor).
+///
+/// This trait is parametrized over `T`, the element type.
+/// It provides all the methods for making a multiprobability prediction.
+pub trait MultiprobabilityPredictor<T> {
+    /// Trains a Multiprobability Predictor on a training set.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<T>) with values of type T of training
+    ///              vectors.
+    /// * `targets` - Vector (Array1<T>) of labels corresponding to the
+    ///               training vectors.
+    fn train(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView1<usize>) -> Result<()>;
+    /// Updates a Multiprobability Predictor with more training data.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<T>) with values of type T of training
+    ///              vectors.
+    /// * `targets` - Vector (Array1<T>) of labels corresponding to the
+    ///               training vectors.
+    fn update(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView1<usize>) -> Result<()>;
+    /// Calibrates an inductive Multiprobability Predictor.
+    fn calibrate(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView1<usize>) -> Result<()>;
+    /// Returns lower and upper probabilities for test vectors.
+    ///
+    /// The return value is a pair `(lower, upper)` of matrices of `f64`
+    /// (`Array2<f64>`) with shape `(n_inputs, n_labels)`, where
+    /// `n_inputs = inputs.rows()` and `n_labels` is the number of
+    /// possible labels;
+    /// in such matrices, each column `y` corresponds to a label,
+    /// each row `i` to an input object, and the values at `[i,y]`
+    /// are respectively the lower and upper probability of label `y`
+    /// for the `i`-th input object.
+    fn predict_probabilities(&mut self, inputs: &ArrayView2<T>)
+                             -> Result<(Array2<f64>, Array2<f64>)>;
+}
@@ -0,0 +1,317 @@
+//! Venn Predictors.
+use std::f64;
+use std::marker::PhantomData;
+use lazysort::SortedPartial;
+use ndarray::prelude::*;
+
+use error::{Error, Result, check_lengths, check_labels};
+use ncm::distance::euclidean_distance;
+use venn::MultiprobabilityPredictor;
+
+
+/// A Venn taxonomy.
+///
+/// A Venn taxonomy splits a bag of examples into categories; the
+/// category of an example can depend on all the other examples of
+/// the bag.
+pub trait VennTaxonomy<T> {
+    /// Returns the category of each example of a bag.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<T>) with values of type T of the
+    ///              vectors in the bag.
+    /// * `targets` - Vector (Array1<T>) of labels corresponding to the
+    ///               vectors.
+    fn categories(&self, inputs: &ArrayView2<T>, targets: &ArrayView1<usize>) -> Vec<usize>;
+}
+
+/// A k-NN Venn taxonomy.
+///
+/// The category of an example is the most frequent label among its
+/// `k` nearest neighbors (w.r.t. the Euclidean distance) in the bag;
+/// ties are broken in favor of the smallest label.
+pub struct KNNTaxonomy {
+    k: usize,
+}
+
+impl KNNTaxonomy {
+    /// Constructs a k-NN Venn taxonomy.
+    ///
+    /// # Arguments
+    ///
+    /// `k` - Number of nearest neighbors.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use random_world::venn::*;
+    ///
+    /// let k = 1;
+    /// let taxonomy = KNNTaxonomy::new(k);
+    /// ```
+    pub fn new(k: usize) -> KNNTaxonomy {
+        assert!(k > 0);
+
+        KNNTaxonomy {
+            k: k,
+        }
+    }
+}
+
+impl VennTaxonomy<f64> for KNNTaxonomy {
+    fn categories(&self, inputs: &ArrayView2<f64>, targets: &ArrayView1<usize>) -> Vec<usize> {
+        let n_labels = targets.iter().max().map_or(0, |y| y + 1);
+
+        inputs.outer_iter()
+              .enumerate()
+              .map(|(i, x)| {
+                  let mut votes = vec![0; n_labels];
+                  let neighbors = inputs.outer_iter()
+                                        .zip(targets.iter())
+                                        .enumerate()
+                                        .filter(|&(j, _)| j != i)
+                                        .map(|(_, (x_, y_))| (euclidean_distance(&x_, &x), *y_))
+                                        .sorted_partial_last()
+                                        .take(self.k);
+                  for (_, y) in neighbors {
+                      votes[y] += 1;
+                  }
+                  // Most voted label, smallest label in case of ties.
+                  votes.iter()
+                       .enumerate()
+                       .fold((0, 0), |best, (y, v)| if *v > best.1 { (y, *v) } else { best })
+                       .0
+              })
+              .collect()
+    }
+}
+
+/// A Venn Predictor, for some Venn taxonomy V and matrix element type T.
+///
+/// For each test object `x` and each candidate label `y`, the example
+/// `(x, y)` is added to the training set, and the empirical distribution
+/// of the labels in the category of `(x, y)` is computed.
+/// The lower (upper) probability of a label is the smallest (largest)
+/// probability it receives across all the candidate labels `y`.
+/// Venn Predictors are valid (i.e., well-calibrated) under the
+/// exchangeability assumption.
+pub struct Venn<T, V: VennTaxonomy<T>> {
+    taxonomy: V,
+    n_labels: usize,
+    train_inputs: Option<Array2<T>>,
+    train_targets: Option<Array1<usize>>,
+    marker: PhantomData<T>,
+}
+
+impl<T, V: VennTaxonomy<T>> Venn<T, V> {
+    /// Constructs a new Venn Predictor `Venn<T,V>` from a Venn taxonomy.
+    ///
+    /// # Arguments
+    ///
+    /// * `taxonomy` - An object implementing VennTaxonomy.
+    /// * `n_labels` - The number of labels.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use random_world::venn::*;
+    ///
+    /// let taxonomy = KNNTaxonomy::new(1);
+    /// let n_labels = 2;
+    /// let mut venn = Venn::new(taxonomy, n_labels);
+    /// ```
+    pub fn new(taxonomy: V, n_labels: usize) -> Venn<T, V> {
+        assert!(n_labels > 0);
+
+        Venn {
+            taxonomy: taxonomy,
+            n_labels: n_labels,
+            train_inputs: None,
+            train_targets: None,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T, V> MultiprobabilityPredictor<T> for Venn<T, V>
+        where T: Clone + Copy, V: VennTaxonomy<T> {
+    /// Trains a Venn Predictor on a training set.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<T>) with values of type T of training
+    ///              vectors.
+    /// * `targets` - Vector (Array1<T>) of labels corresponding to the
+    ///               training vectors.
+    ///
+    /// # Errors
+    ///
+    /// - if the number of training examples is not consistent
+    ///   with the number of respective labels.
+    /// - if a label is not smaller than `n_labels`.
+    fn train(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView1<usize>)
+             -> Result<()> {
+        check_lengths(inputs.rows(), targets.len())?;
+        check_labels(targets, self.n_labels)?;
+
+        self.train_inputs = Some(inputs.to_owned());
+        self.train_targets = Some(targets.to_owned());
+
+        Ok(())
+    }
+
+    /// Updates a Venn Predictor with more training data.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - Matrix (Array2<T>) with values of type T of training
+    ///              vectors.
+    /// * `targets` - Vector (Array1<T>) of labels corresponding to the
+    ///               training vectors.
+    ///
+    /// # Errors
+    ///
+    /// - if the number of training examples is not consistent
+    ///   with the number of respective labels.
+    /// - if a label is not smaller than `n_labels`.
+    /// - if `train()` hasn't been called once before.
+    fn update(&mut self, inputs: &ArrayView2<T>, targets: &ArrayView1<usize>)
+              -> Result<()> {
+        check_lengths(inputs.rows(), targets.len())?;
+        check_labels(targets, self.n_labels)?;
+
+        let train_inputs = match self.train_inputs {
+            Some(ref train_inputs) => {
+                if inputs.cols() != train_inputs.cols() {
+                    return Err(Error::DimensionMismatch { expected: train_inputs.cols(),
+                                                          found: inputs.cols() });
+                }
+                stack![Axis(0), *train_inputs, *inputs]
+            },
+            None => return Err(Error::NotTrained),
+        };
+        let train_targets = match self.train_targets {
+            Some(ref train_targets) => stack![Axis(0), *train_targets, *targets],
+            None => return Err(Error::NotTrained),
+        };
+        self.train_inputs = Some(train_inputs);
+        self.train_targets = Some(train_targets);
+
+        Ok(())
+    }
+
+    /// Venn Predictors are transductive: calling this method returns
+    /// an error.
+    fn calibrate(&mut self, _inputs: &ArrayView2<T>, _targets: &ArrayView1<usize>)
+                 -> Result<()> {
+        Err(Error::NotInductive)
+    }
+
+    /// Returns lower and upper probabilities for test vectors.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// #[macro_use(array)]
+    /// extern crate ndarray;
+    /// extern crate random_world;
+    ///
+    /// # fn main() {
+    /// use random_world::venn::*;
+    ///
+    /// let taxonomy = KNNTaxonomy::new(1);
+    /// let n_labels = 2;
+    /// let mut venn = Venn::new(taxonomy, n_labels);
+    /// let train_inputs = array![[0., 0.],
+    ///                           [1., 0.],
+    ///                           [0., 1.],
+    ///                           [3., 3.],
+    ///                           [4., 3.]];
+    /// let train_targets = array![0, 0, 1, 1, 1];
+    /// let test_inputs = array![[0.5, 0.]];
+    ///
+    /// venn.train(&train_inputs.view(), &train_targets.view())
+    ///     .expect("Failed to train");
+    /// let (lower, upper) = venn.predict_probabilities(&test_inputs.view())
+    ///                          .expect("Failed to predict");
+    /// assert!(lower == array![[0., 0.25]]);
+    /// assert!(upper == array![[0.75, 1.]]);
+    /// # }
+    /// ```
+    fn predict_probabilities(&mut self, inputs: &ArrayView2<T>)
+                             -> Result<(Array2<f64>, Array2<f64>)> {
+        let train_inputs = self.train_inputs.as_ref()
+                               .ok_or(Error::NotTrained)?;
+        let train_targets = self.train_targets.as_ref()
+                                .ok_or(Error::NotTrained)?;
+        let n = train_inputs.rows();
+        if inputs.cols() != train_inputs.cols() {
+            return Err(Error::DimensionMismatch { expected: train_inputs.cols(),
+                                                  found: inputs.cols() });
+        }
+
+        // Init with NaN to ease future debugging.
+        let mut lower = Array2::<f64>::from_elem((inputs.rows(), self.n_labels), f64::NAN);
+        let mut upper = Array2::<f64>::from_elem((inputs.rows(), self.n_labels), f64::NAN);
+
+        for (i, x) in inputs.outer_iter().enumerate() {
+            let bag_inputs = stack![Axis(0), *train_inputs,
+                                    x.into_shape((1, x.len()))
+                                     .expect("Unexpected error in reshaping")];
+            let mut bag_targets = stack![Axis(0), *train_targets, array![0]];
+
+            for y in 0..self.n_labels {
+                bag_targets[n] = y;
+                let categories = self.taxonomy.categories(&bag_inputs.view(),
+                                                          &bag_targets.view());
+
+                // Empirical distribution of the labels in the
+                // category of the test example.
+                let mut counts = vec![0.; self.n_labels];
+                for (c, y_) in categories.iter().zip(bag_targets.iter()) {
+                    if *c == categories[n] {
+                        counts[*y_] += 1.;
+                    }
+                }
+                let total = counts.iter().sum::<f64>();
+
+                for (label, count) in counts.into_iter().enumerate() {
+                    let p = count / total;
+                    if y == 0 || p < lower[[i,label]] {
+                        lower[[i,label]] = p;
+                    }
+                    if y == 0 || p > upper[[i,label]] {
+                        upper[[i,label]] = p;
+                    }
+                }
+            }
+        }
+
+        Ok((lower, upper))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Verify that the k-NN taxonomy assigns each example the
+    /// most frequent label among its nearest neighbors.
+    #[test]
+    fn knn_taxonomy() {
+        let taxonomy = KNNTaxonomy::new(1);
+
+        let inputs = array![[0., 0.],
+                            [1., 0.],
+                            [0., 1.],
+                            [3., 3.],
+                            [4., 3.]];
+        let targets = array![0, 0, 1, 1, 1];
+
+        let categories = taxonomy.categories(&inputs.view(), &targets.view());
+
+        assert!(categories == vec![0, 0, 0, 1, 1]);
+    }
+}
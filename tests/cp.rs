@@ -76,6 +76,59 @@ mod tests {
         assert!(pvalues == expected_pvalues);
     }
 
+    /// Tests Mondrian CP, with a taxonomy depending on the inputs.
+    #[test]
+    fn mondrian_cp() {
+        let k = 2;
+        let ncm = KNN::new(k);
+        let n_labels = 2;
+        let mut cp = CP::new(ncm, n_labels, None);
+        cp.set_taxonomy(Box::new(|x, _| if x[0] >= 1. { 1 } else { 0 }));
+
+        let train_inputs = array![[0., 0.],
+                                  [1., 0.],
+                                  [0., 1.],
+                                  [1., 1.],
+                                  [2., 2.],
+                                  [1., 2.]];
+        let train_targets = array![0, 0, 0, 1, 1, 1];
+        let test_inputs = array![[2., 1.],
+                                 [0.5, 0.]];
+        let expected_pvalues = array![[0.5, 1.],
+                                      [1., 1.]];
+
+        cp.train(&train_inputs.view(), &train_targets.view()).unwrap();
+        let pvalues = cp.predict_confidence(&test_inputs.view()).unwrap();
+        println!("Expected p-values: {:?}", expected_pvalues);
+        println!("P-values: {:?}", pvalues);
+        assert!(pvalues == expected_pvalues);
+    }
+
+    /// Tests weighted CP, with a likelihood ratio depending on the inputs.
+    #[test]
+    fn weighted_cp() {
+        let ncm = KNN::new(1);
+        let n_labels = 2;
+        let mut cp = CP::new(ncm, n_labels, None);
+        cp.set_likelihood_ratio(Box::new(|x| x[0] + 1.));
+
+        let train_inputs = array![[0.], [1.], [4.], [10.], [11.]];
+        let train_targets = array![0, 0, 0, 1, 1];
+        let test_inputs = array![[6.]];
+        // Label 0: scores 1, 1, 2 with weights 1, 2, 5; the test example
+        // has score 2 and weight 7.
+        // Label 1: scores 1, 1 with weights 11, 12; the test example
+        // has score 4 and weight 7.
+        let expected_pvalues = array![[(5. + 7.) / (1. + 2. + 5. + 7.),
+                                       7. / (11. + 12. + 7.)]];
+
+        cp.train(&train_inputs.view(), &train_targets.view()).unwrap();
+        let pvalues = cp.predict_confidence(&test_inputs.view()).unwrap();
+        println!("Expected p-values: {:?}", expected_pvalues);
+        println!("P-values: {:?}", pvalues);
+        assert!(pvalues == expected_pvalues);
+    }
+
    /// Tests CP in batch mode (i.e., train on training set, predict
    /// test set. For simplicity of the test, training and test sets are
    /// identical.
@@ -138,4 +191,115 @@ mod tests {
               .expect("Failed to update CP");
         }
     }
+
+    /// Verify forced predictions against the expected p-values
+    /// of `cp_batch_iris`.
+    #[test]
+    fn forced_iris() {
+        let mut cp = CP::new(KNN::new(3), 3, None);
+
+        let (train_inputs, train_targets) = load_data("tests/data/iris.csv")
+                                            .expect("Failed to load data");
+        let expected_pvalues = load_pvalues("tests/data/iris-batch-expected.csv")
+                                    .expect("Failed to load p-values");
+
+        cp.train(&train_inputs.view(), &train_targets.view())
+          .expect("Failed to train the model");
+
+        let forced = cp.predict_forced(&train_inputs.view())
+                       .expect("Failed to predict");
+
+        assert!(forced.len() == expected_pvalues.rows());
+        for (f, p) in forced.iter().zip(expected_pvalues.outer_iter()) {
+            let mut sorted = p.to_vec();
+            sorted.sort_by(|a, b| b.partial_cmp(a).unwrap());
+
+            assert!(p[f.label] == sorted[0]);
+            assert!(f.credibility == sorted[0]);
+            assert!(f.confidence == 1. - sorted[1]);
+        }
+    }
+
+    /// Verify that ACI keeps the on-line error rate close to epsilon,
+    /// within the bound of Gibbs and Candès (2021).
+    #[test]
+    fn aci_online_iris() {
+        let epsilon = 0.1;
+        let gamma = 0.05;
+        let cp = CP::new(KNN::new(3), 3, None);
+        let mut aci = ACI::new(cp, epsilon, gamma);
+
+        let (inputs, targets) = load_data("tests/data/iris.csv")
+                                .expect("Failed to load data");
+
+        aci.train(&inputs.slice(s![0..1, ..]), &targets.slice(s![0..1]))
+           .expect("Failed to train CP");
+
+        for (x, y) in inputs.outer_iter().zip(targets.iter()).skip(1) {
+            let x_ = x.into_shape((1, x.len())).unwrap();
+            aci.predict(&x_).expect("Failed to predict");
+            aci.update(&x_, &array![*y].view())
+               .expect("Failed to update CP");
+        }
+
+        let n = aci.epsilon_trajectory().len() as f64;
+        assert!(n == (inputs.rows() - 1) as f64);
+        assert!((aci.error_rate() - epsilon).abs() <= (1. - epsilon + gamma) / (gamma * n));
+    }
+
+    /// Verify that parallel prediction gives the same p-values as
+    /// serial prediction, also for smooth CP.
+    #[test]
+    fn parallel_smooth_iris() {
+        let n_labels = 3;
+        let seed = [0, 0];
+        let mut cp = CP::new_smooth(KNN::new(3), n_labels, None, Some(seed));
+        let mut cp_parallel = CP::new_smooth(KNN::new(3), n_labels, None, Some(seed));
+        cp_parallel.set_threads(4).unwrap();
+
+        let (inputs, targets) = load_data("tests/data/iris.csv")
+                                .expect("Failed to load data");
+        let n_train = 100;
+
+        cp.train(&inputs.slice(s![..n_train, ..]), &targets.slice(s![..n_train]))
+          .expect("Failed to train CP");
+        cp_parallel.train(&inputs.slice(s![..n_train, ..]), &targets.slice(s![..n_train]))
+                   .expect("Failed to train CP");
+
+        let pvalues = cp.predict_confidence(&inputs.slice(s![n_train.., ..]))
+                        .expect("Failed to predict");
+        let pvalues_parallel = cp_parallel.predict_confidence(&inputs.slice(s![n_train.., ..]))
+                                          .expect("Failed to predict");
+        assert!(pvalues == pvalues_parallel);
+    }
+    /// Verify that a stored and loaded smooth CP predicts as the
+    /// original one, continuing from the same PRNG state.
+    #[test]
+    fn save_load_smooth_iris() {
+        let n_labels = 3;
+        let seed = [0, 0];
+        let mut cp = CP::new_smooth(KNN::new(3), n_labels, None, Some(seed));
+
+        let (inputs, targets) = load_data("tests/data/iris.csv")
+                                .expect("Failed to load data");
+        let n_train = 100;
+
+        cp.train(&inputs.slice(s![..n_train, ..]), &targets.slice(s![..n_train]))
+          .expect("Failed to train CP");
+        // Draw some random numbers before storing the model.
+        cp.predict_confidence(&inputs.slice(s![n_train..n_train+5, ..]))
+          .expect("Failed to predict");
+
+        let model_file = std::env::temp_dir().join("random-world-test-cp.json");
+        let model_file = model_file.to_str().unwrap();
+        save_model(&cp, model_file).expect("Failed to store the model");
+        let mut cp_loaded: CP<f64, KNN<f64>> = load_model(model_file)
+                                               .expect("Failed to load the model");
+
+        let pvalues = cp.predict_confidence(&inputs.slice(s![n_train.., ..]))
+                        .expect("Failed to predict");
+        let pvalues_loaded = cp_loaded.predict_confidence(&inputs.slice(s![n_train.., ..]))
+                                      .expect("Failed to predict");
+        assert!(pvalues == pvalues_loaded);
+    }
 }
@@ -7,6 +7,7 @@ extern crate random_world;
 mod tests {
     use random_world::cp::*;
     use random_world::ncm::*;
+    use random_world::error::Error;
     use ndarray::*;
     
     #[test]
@@ -53,4 +54,303 @@ mod tests {
         cp.set_epsilon(epsilon_2);
         assert!(cp.predict(&test_inputs.view()).unwrap() == expected_preds_2);
     }
-}
\ No newline at end of file
+
+    /// Tests Mondrian ICP, with a taxonomy depending on the inputs.
+    #[test]
+    fn mondrian_icp() {
+        let k = 2;
+        let ncm = KNN::new(k);
+        let n_labels = 2;
+        let mut cp = CP::new_inductive(ncm, n_labels, None);
+        cp.set_taxonomy(Box::new(|x, _| if x[0] >= 2. { 1 } else { 0 }));
+
+        let train_inputs = array![[0., 0.],
+                                  [1., 0.],
+                                  [1., 1.],
+                                  [0., 1.],
+                                  [2., 2.],
+                                  [1., 2.]];
+        let n_train = 3;            // Size of proper training set.
+        let train_targets = array![0, 0, 1, 0, 1, 1];
+        let test_inputs = array![[2., 1.],
+                                 [1., 1.5]];
+        let expected_pvalues = array![[1., 1.],
+                                      [0.5, 1.]];
+
+        cp.train(&train_inputs.slice(s![..n_train, ..]),
+                 &train_targets.slice(s![..n_train]))
+          .expect("Failed to train the model");
+
+        cp.calibrate(&train_inputs.slice(s![n_train.., ..]),
+                     &train_targets.slice(s![n_train..]))
+          .expect("Failed to train the model");
+
+        let pvalues = cp.predict_confidence(&test_inputs.view()).unwrap();
+        println!("Expected p-values: {:?}. P-values: {:?}.", expected_pvalues,
+                 pvalues);
+        assert!(pvalues == expected_pvalues);
+    }
+
+    /// Verify that a weighted ICP with a constant likelihood ratio
+    /// gives the same p-values as the standard ICP.
+    #[test]
+    fn weighted_icp_constant() {
+        let n_labels = 2;
+        let mut cp = CP::new_inductive(KNN::new(2), n_labels, None);
+        cp.set_likelihood_ratio(Box::new(|_| 2.));
+
+        let train_inputs = array![[0., 0.],
+                                  [1., 0.],
+                                  [1., 1.],
+                                  [0., 1.],
+                                  [2., 2.],
+                                  [1., 2.]];
+        let n_train = 3;            // Size of proper training set.
+        let train_targets = array![0, 0, 1, 0, 1, 1];
+        let test_inputs = array![[2., 1.],
+                                 [2., 2.]];
+        let expected_pvalues = array![[0.5, 1.],
+                                      [0.5, 2./3.]];
+
+        cp.train(&train_inputs.slice(s![..n_train, ..]),
+                 &train_targets.slice(s![..n_train]))
+          .expect("Failed to train the model");
+
+        cp.calibrate(&train_inputs.slice(s![n_train.., ..]),
+                     &train_targets.slice(s![n_train..]))
+          .expect("Failed to train the model");
+
+        let pvalues = cp.predict_confidence(&test_inputs.view()).unwrap();
+        assert!(pvalues == expected_pvalues);
+
+        // NaN likelihood ratios are rejected.
+        cp.set_likelihood_ratio(Box::new(|x| if x[0] > 1. { f64::NAN } else { 1. }));
+        match cp.predict_confidence(&test_inputs.view()) {
+            Err(Error::NaN) => {},
+            r => panic!("Unexpected result: {:?}", r),
+        }
+    }
+
+    /// Verify the p-values of a weighted ICP with a likelihood ratio
+    /// depending on the inputs, and that invalid likelihood ratios
+    /// are rejected.
+    #[test]
+    fn weighted_icp() {
+        let n_labels = 2;
+        let mut cp = CP::new_inductive(KNN::new(1), n_labels, None);
+        cp.set_likelihood_ratio(Box::new(|x| x[0]));
+
+        let train_inputs = array![[0.], [10.]];
+        let train_targets = array![0, 1];
+        let calibration_inputs = array![[1.], [2.], [3.], [8.]];
+        let calibration_targets = array![0, 0, 0, 1];
+        let test_inputs = array![[1.5],
+                                 [2.]];
+        // Calibration scores are 1, 2, 3 (label 0) and 2 (label 1),
+        // with weights 1, 2, 3 and 8.
+        let expected_pvalues = array![[(2. + 3. + 1.5) / (1. + 2. + 3. + 1.5),
+                                       1.5 / (8. + 1.5)],
+                                      [(3. + 2. + 2.) / (1. + 2. + 3. + 2.),
+                                       2. / (8. + 2.)]];
+
+        cp.train(&train_inputs.view(), &train_targets.view())
+          .expect("Failed to train the model");
+        cp.calibrate(&calibration_inputs.view(), &calibration_targets.view())
+          .expect("Failed to calibrate the model");
+
+        let pvalues = cp.predict_confidence(&test_inputs.view()).unwrap();
+        assert!(pvalues == expected_pvalues);
+
+        // Negative and infinite likelihood ratios are rejected.
+        cp.set_likelihood_ratio(Box::new(|x| x[0] - 2.));
+        match cp.predict_confidence(&test_inputs.view()) {
+            Err(Error::InvalidWeight(-1.)) => {},
+            r => panic!("Unexpected result: {:?}", r),
+        }
+        cp.set_likelihood_ratio(classifier_likelihood_ratio(
+                                    |x| if x[0] > 1.8 { 1. } else { 0.5 }, 1, 1));
+        match cp.predict_confidence(&test_inputs.view()) {
+            Err(Error::InvalidWeight(w)) if w.is_infinite() => {},
+            r => panic!("Unexpected result: {:?}", r),
+        }
+    }
+
+    /// Verify that calibrate() + update_calibration() on partial
+    /// calibration sets is equivalent to calibrate()-ing on the full one.
+    #[test]
+    fn update_calibration() {
+        let n_labels = 2;
+        let mut cp = CP::new_inductive(KNN::new(2), n_labels, None);
+        let mut cp_alt = CP::new_inductive(KNN::new(2), n_labels, None);
+
+        let train_inputs = array![[0., 0.],
+                                  [1., 0.],
+                                  [1., 1.],
+                                  [0., 1.],
+                                  [2., 2.],
+                                  [1., 2.]];
+        let n_train = 3;            // Size of proper training set.
+        let train_targets = array![0, 0, 1, 0, 1, 1];
+        let test_inputs = array![[2., 1.],
+                                 [2., 2.]];
+
+        cp.train(&train_inputs.slice(s![..n_train, ..]),
+                 &train_targets.slice(s![..n_train]))
+          .expect("Failed to train the model");
+        cp_alt.train(&train_inputs.slice(s![..n_train, ..]),
+                     &train_targets.slice(s![..n_train]))
+              .expect("Failed to train the model");
+
+        // Calibrate on one example, then add the remaining ones.
+        cp.calibrate(&train_inputs.slice(s![n_train..n_train+1, ..]),
+                     &train_targets.slice(s![n_train..n_train+1]))
+          .expect("Failed to calibrate the model");
+        cp.update_calibration(&train_inputs.slice(s![n_train+1.., ..]),
+                              &train_targets.slice(s![n_train+1..]))
+          .expect("Failed to update the calibration set");
+        cp_alt.calibrate(&train_inputs.slice(s![n_train.., ..]),
+                         &train_targets.slice(s![n_train..]))
+              .expect("Failed to calibrate the model");
+
+        let pvalues = cp.predict_confidence(&test_inputs.view()).unwrap();
+        let pvalues_alt = cp_alt.predict_confidence(&test_inputs.view()).unwrap();
+        assert!(pvalues == pvalues_alt);
+    }
+
+    /// Verify that smooth ICP is reproducible when seeded, and that its
+    /// p-values are not larger than those of deterministic ICP.
+    #[test]
+    fn smooth_icp() {
+        let n_labels = 2;
+        let seed = [0, 0];
+        let mut cp = CP::new_smooth_inductive(KNN::new(2), n_labels, None, Some(seed));
+
+        let train_inputs = array![[0., 0.],
+                                  [1., 0.],
+                                  [1., 1.],
+                                  [0., 1.],
+                                  [2., 2.],
+                                  [1., 2.]];
+        let n_train = 3;            // Size of proper training set.
+        let train_targets = array![0, 0, 1, 0, 1, 1];
+        let test_inputs = array![[2., 1.],
+                                 [2., 2.]];
+        let expected_pvalues = array![[0., 0.386642594165895],
+                                      [0.3344399335120407, 0.3404215623417821]];
+
+        cp.train(&train_inputs.slice(s![..n_train, ..]),
+                 &train_targets.slice(s![..n_train]))
+          .expect("Failed to train the model");
+
+        cp.calibrate(&train_inputs.slice(s![n_train.., ..]),
+                     &train_targets.slice(s![n_train..]))
+          .expect("Failed to train the model");
+
+        let pvalues = cp.predict_confidence(&test_inputs.view()).unwrap();
+        println!("Expected p-values: {:?}. P-values: {:?}.", expected_pvalues,
+                 pvalues);
+        assert!(pvalues == expected_pvalues);
+
+        // Deterministic ICP on the same data.
+        let mut cp = CP::new_inductive(KNN::new(2), n_labels, None);
+        cp.train(&train_inputs.slice(s![..n_train, ..]),
+                 &train_targets.slice(s![..n_train]))
+          .expect("Failed to train the model");
+        cp.calibrate(&train_inputs.slice(s![n_train.., ..]),
+                     &train_targets.slice(s![n_train..]))
+          .expect("Failed to train the model");
+        let deterministic_pvalues = cp.predict_confidence(&test_inputs.view()).unwrap();
+        for (p, p_det) in pvalues.iter().zip(deterministic_pvalues.iter()) {
+            assert!(p <= p_det);
+        }
+    }
+
+    /// Verify that p-values computed from precomputed calibration
+    /// scores are identical to those computed by comparing all the
+    /// calibration scores (as a Mondrian ICP with one category does).
+    #[test]
+    fn precomputed_calibration_scores() {
+        let n_labels = 3;
+        let mut cp = CP::new_inductive(KNN::new(3), n_labels, None);
+        let mut cp_alt = CP::new_inductive(KNN::new(3), n_labels, None);
+        cp_alt.set_taxonomy(Box::new(|_, _| 0));
+
+        let n = 60;
+        let inputs = Array::from_shape_fn((n, 2), |(i, j)| ((i * (j + 3)) as f64).sin().round());
+        let targets = Array::from_shape_fn(n, |i| i % n_labels);
+        let test_inputs = Array::from_shape_fn((10, 2), |(i, j)| ((i + j) as f64).cos());
+        let n_train = 30;
+
+        for cp in [&mut cp, &mut cp_alt].iter_mut() {
+            cp.train(&inputs.slice(s![..n_train, ..]), &targets.slice(s![..n_train]))
+              .expect("Failed to train the model");
+            cp.calibrate(&inputs.slice(s![n_train.., ..]), &targets.slice(s![n_train..]))
+              .expect("Failed to calibrate the model");
+        }
+
+        let pvalues = cp.predict_confidence(&test_inputs.view()).unwrap();
+        let pvalues_alt = cp_alt.predict_confidence(&test_inputs.view()).unwrap();
+        assert!(pvalues == pvalues_alt);
+    }
+    /// Verify that misusing an ICP, or feeding it bad input, returns
+    /// an error rather than panicking.
+    #[test]
+    fn errors() {
+        let n_labels = 2;
+        let mut cp = CP::new_inductive(KNN::new(2), n_labels, None);
+
+        let train_inputs = array![[0., 0.],
+                                  [1., 0.],
+                                  [1., 1.]];
+        let train_targets = array![0, 0, 1];
+        let test_inputs = array![[2., 1.]];
+
+        match cp.predict_confidence(&test_inputs.view()) {
+            Err(Error::NotCalibrated) => {},
+            r => panic!("Unexpected result: {:?}", r),
+        }
+        match cp.train(&train_inputs.view(), &array![0, 2, 1].view()) {
+            Err(Error::InvalidLabel { label: 2, n_labels: 2 }) => {},
+            r => panic!("Unexpected result: {:?}", r),
+        }
+        match cp.train(&train_inputs.view(), &array![0, 1].view()) {
+            Err(Error::DimensionMismatch { expected: 3, found: 2 }) => {},
+            r => panic!("Unexpected result: {:?}", r),
+        }
+
+        cp.train(&train_inputs.view(), &train_targets.view())
+          .expect("Failed to train the model");
+        match cp.train(&train_inputs.view(), &train_targets.view()) {
+            Err(Error::AlreadyTrained) => {},
+            r => panic!("Unexpected result: {:?}", r),
+        }
+        match cp.update_calibration(&train_inputs.view(), &train_targets.view()) {
+            Err(Error::NotCalibrated) => {},
+            r => panic!("Unexpected result: {:?}", r),
+        }
+        cp.calibrate(&array![[0., 1.], [2., 2.]].view(), &array![0, 1].view())
+          .expect("Failed to calibrate the model");
+
+        match cp.predict_confidence(&array![[2., 1., 0.]].view()) {
+            Err(Error::DimensionMismatch { expected: 2, found: 3 }) => {},
+            r => panic!("Unexpected result: {:?}", r),
+        }
+        match cp.predict_confidence(&array![[2., ::std::f64::NAN]].view()) {
+            Err(Error::NaN) => {},
+            r => panic!("Unexpected result: {:?}", r),
+        }
+        match cp.predict(&test_inputs.view()) {
+            Err(Error::NoEpsilon) => {},
+            r => panic!("Unexpected result: {:?}", r),
+        }
+        // The ICP is still usable.
+        assert!(cp.predict_confidence(&test_inputs.view()).is_ok());
+
+        // Transductive CP cannot be calibrated.
+        let mut cp = CP::new(KNN::new(2), n_labels, None);
+        match cp.calibrate(&train_inputs.view(), &train_targets.view()) {
+            Err(Error::NotInductive) => {},
+            r => panic!("Unexpected result: {:?}", r),
+        }
+    }
+}
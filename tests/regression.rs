@@ -0,0 +1,57 @@
+extern crate ndarray;
+extern crate rusty_machine;
+
+extern crate random_world;
+
+#[cfg(test)]
+mod tests {
+    use std::f64;
+    use ndarray::*;
+    use rusty_machine::learning::lin_reg::LinRegressor;
+    use random_world::cp::*;
+    use random_world::ncm::*;
+
+    #[test]
+    fn icp_regressor() {
+        let ncm = AbsoluteResidual::new(LinRegressor::default());
+        let mut cp = ICPRegressor::new(ncm, Some(0.5));
+
+        let train_inputs = array![[0.], [1.], [2.], [3.]];
+        let train_targets = array![0., 2., 4., 6.];
+        let calibration_inputs = array![[0.], [1.], [2.], [3.]];
+        let calibration_targets = array![1., 1., 4.5, 6.];
+        let test_inputs = array![[4.], [5.]];
+        let test_targets = array![8., 11.];
+        let expected_pvalues = array![1., 0.6];
+
+        cp.train(&train_inputs.view(), &train_targets.view())
+          .expect("Failed to train the model");
+        cp.calibrate(&calibration_inputs.view(), &calibration_targets.view())
+          .expect("Failed to calibrate the model");
+
+        // Residuals are [1., 1., 0.5, 0.]: with epsilon = 0.5,
+        // the threshold is the 3rd smallest residual.
+        let intervals = cp.predict(&test_inputs.view()).unwrap();
+        let expected_intervals = array![[7., 9.],
+                                        [9., 11.]];
+        for (a, b) in intervals.iter().zip(expected_intervals.iter()) {
+            assert!((a - b).abs() < 1e-9);
+        }
+
+        let pvalues = cp.predict_confidence(&test_inputs.view(),
+                                            &test_targets.view()).unwrap();
+        for (a, b) in pvalues.iter().zip(expected_pvalues.iter()) {
+            assert!((a - b).abs() < 1e-9);
+        }
+
+        // Small epsilon: the interval is the whole real line.
+        cp.set_epsilon(0.1);
+        let intervals = cp.predict(&test_inputs.view()).unwrap();
+        assert!(intervals.row(0) == array![f64::NEG_INFINITY, f64::INFINITY]);
+
+        // Large epsilon: the interval is empty.
+        cp.set_epsilon(1.);
+        let intervals = cp.predict(&test_inputs.view()).unwrap();
+        assert!(intervals.iter().all(|v| v.is_nan()));
+    }
+}